@@ -0,0 +1,185 @@
+// Server-side allow-list of client identities, with a policy per client.
+//
+// Stored as TOML, by default at ~/.config/sst/authorized_clients.toml:
+//
+//   [[client]]
+//   name = "laptop"
+//   identity = "<hex identity key>"
+//   tcp_endpoint = "127.0.0.1:22"   # optional, else the server's endpoint
+//   rate_limit = 1048576            # optional, bytes per second each way
+//   expires = 1767225600            # optional, unix seconds
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use hex::FromHex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("client identity is not authorized.")]
+    Unknown,
+    #[error("authorization of client {0} has expired.")]
+    Expired(String),
+    #[error("client {0} has an invalid identity key.")]
+    InvalidIdentity(String),
+    #[error("cannot read authorized_clients: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("cannot parse authorized_clients: {0}")]
+    Parse(#[from] toml::de::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientPolicy {
+    pub name: String,
+    /// Hex of the identity key.
+    pub identity: String,
+    /// Where this client's tunnel goes, instead of the server's `tcp_endpoint`.
+    pub tcp_endpoint: Option<String>,
+    /// Bytes per second, in each direction.
+    pub rate_limit: Option<u64>,
+    /// Unix seconds after which the client is refused.
+    pub expires: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthorizedClients {
+    #[serde(default, rename = "client")]
+    clients: Vec<ClientPolicy>,
+    #[serde(skip)]
+    keys: Vec<[u8; 32]>,
+}
+
+impl AuthorizedClients {
+    /// ~/.config/sst/authorized_clients.toml, next to the config file.
+    pub fn default_path() -> PathBuf {
+        let home = std::env::var("HOME").unwrap_or("/".to_owned());
+        #[cfg(target_os = "windows")]
+        let dir = Path::new(&home).join("sst/");
+        #[cfg(not(target_os = "windows"))]
+        let dir = Path::new(&home).join(".config/sst/");
+        dir.join("authorized_clients.toml")
+    }
+
+    pub fn new(clients: Vec<ClientPolicy>) -> Result<Self, AuthError> {
+        let keys = clients
+            .iter()
+            .map(|c| {
+                <[u8; 32]>::from_hex(&c.identity)
+                    .map_err(|_| AuthError::InvalidIdentity(c.name.clone()))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { clients, keys })
+    }
+
+    pub fn parse(text: &str) -> Result<Self, AuthError> {
+        let parsed: Self = toml::from_str(text)?;
+        Self::new(parsed.clients)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, AuthError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Policy of `identity`, if listed and not expired at `now`.
+    pub fn authorize(&self, identity: &[u8; 32], now: u64) -> Result<&ClientPolicy, AuthError> {
+        let i = self
+            .keys
+            .iter()
+            .position(|k| k == identity)
+            .ok_or(AuthError::Unknown)?;
+        let policy = &self.clients[i];
+        if matches!(policy.expires, Some(t) if now > t) {
+            return Err(AuthError::Expired(policy.name.clone()));
+        }
+        Ok(policy)
+    }
+}
+
+/// Token bucket holding up to one second of traffic.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    rate: u64,
+    /// Bytes that may pass now; negative when in debt.
+    allowance: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            rate: bytes_per_second,
+            allowance: bytes_per_second as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// Account for `n` bytes, and return how long to wait before sending them.
+    pub fn delay(&mut self, n: usize) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.allowance = (self.allowance + elapsed * self.rate as f64).min(self.rate as f64);
+        self.allowance -= n as f64;
+        if self.allowance >= 0.0 || self.rate == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.allowance / self.rate as f64)
+        }
+    }
+}
+
+#[test]
+fn test_authorized_clients() {
+    let key = [7u8; 32];
+    let text = format!(
+        r#"
+[[client]]
+name = "laptop"
+identity = "{}"
+tcp_endpoint = "127.0.0.1:22"
+rate_limit = 1000
+
+[[client]]
+name = "old"
+identity = "{}"
+expires = 100
+"#,
+        hex::encode(key),
+        hex::encode([8u8; 32])
+    );
+    let clients = AuthorizedClients::parse(&text).unwrap();
+    let policy = clients.authorize(&key, 1000).unwrap();
+    assert_eq!(policy.name, "laptop");
+    assert_eq!(policy.tcp_endpoint.as_deref(), Some("127.0.0.1:22"));
+    assert_eq!(policy.rate_limit, Some(1000));
+    assert!(clients.authorize(&[8u8; 32], 100).is_ok());
+    assert!(matches!(
+        clients.authorize(&[8u8; 32], 101),
+        Err(AuthError::Expired(_))
+    ));
+    assert!(matches!(
+        clients.authorize(&[9u8; 32], 0),
+        Err(AuthError::Unknown)
+    ));
+
+    assert!(AuthorizedClients::parse("")
+        .unwrap()
+        .authorize(&key, 0)
+        .is_err());
+    let bad = "[[client]]\nname = \"x\"\nidentity = \"abcd\"\n";
+    assert!(matches!(
+        AuthorizedClients::parse(bad),
+        Err(AuthError::InvalidIdentity(_))
+    ));
+}
+
+#[test]
+fn test_rate_limiter() {
+    let mut limiter = RateLimiter::new(1000);
+    // One second of burst passes at once, then traffic waits its turn.
+    assert_eq!(limiter.delay(1000), Duration::ZERO);
+    let wait = limiter.delay(500);
+    assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+}
@@ -0,0 +1,178 @@
+// Trust-on-first-use store of peer identities, like ssh's known_hosts.
+//
+// One `endpoint fingerprint` pair per line, `#` starts a comment. The fingerprint is the
+// hex SHA256 of the identity key. An endpoint may have several lines: a server lists
+// every client allowed on its listening endpoint.
+// The first identity seen on an endpoint is recorded; any other is rejected afterwards.
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use hex::{FromHex, ToHex};
+use thiserror::Error;
+
+use crate::sha256::SHA256;
+
+#[derive(Error, Debug)]
+pub enum PeerError {
+    #[error(
+        "IDENTITY OF {endpoint} HAS CHANGED! Got fingerprint {fingerprint}, which is not in {path}. \
+         Someone may be intercepting the connection. If the change is expected, \
+         remove the old line for this endpoint."
+    )]
+    KeyChanged {
+        endpoint: String,
+        fingerprint: String,
+        path: String,
+    },
+    #[error("known_peers line {0} is malformed.")]
+    Malformed(usize),
+    #[error("cannot access known_peers: {0}")]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerStatus {
+    /// The identity was already listed for the endpoint.
+    Known,
+    /// First contact; `check` records the identity, `lookup` does not.
+    New,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct KnownPeers {
+    /// Where new entries are appended; `None` keeps them in memory.
+    path: Option<PathBuf>,
+    entries: Vec<(String, [u8; 32])>,
+}
+
+pub fn fingerprint(identity: &[u8; 32]) -> [u8; 32] {
+    SHA256::do_hash(identity)
+}
+
+impl KnownPeers {
+    /// ~/.config/sst/known_peers, next to the config file.
+    pub fn default_path() -> PathBuf {
+        let home = std::env::var("HOME").unwrap_or("/".to_owned());
+        #[cfg(target_os = "windows")]
+        let dir = Path::new(&home).join("sst/");
+        #[cfg(not(target_os = "windows"))]
+        let dir = Path::new(&home).join(".config/sst/");
+        dir.join("known_peers")
+    }
+
+    /// Read the store at `path`; a missing file is an empty store.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PeerError> {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut entries = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (endpoint, fp) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(endpoint), Some(fp), None) => (endpoint, fp),
+                _ => return Err(PeerError::Malformed(i + 1)),
+            };
+            let fp = <[u8; 32]>::from_hex(fp).map_err(|_| PeerError::Malformed(i + 1))?;
+            entries.push((endpoint.to_owned(), fp));
+        }
+        Ok(Self {
+            path: Some(path.to_owned()),
+            entries,
+        })
+    }
+
+    /// Is `identity` allowed on `endpoint`? Records it if the endpoint is new.
+    pub fn check(&mut self, endpoint: &str, identity: &[u8; 32]) -> Result<PeerStatus, PeerError> {
+        let status = self.lookup(endpoint, identity)?;
+        if status == PeerStatus::New {
+            self.add(endpoint, identity)?;
+        }
+        Ok(status)
+    }
+
+    /// Is `identity` allowed on `endpoint`? Records nothing, so a handshake can refuse a
+    /// changed identity early and record a new one only once the peer proved it holds it.
+    pub fn lookup(&self, endpoint: &str, identity: &[u8; 32]) -> Result<PeerStatus, PeerError> {
+        let fp = fingerprint(identity);
+        let mut listed = self
+            .entries
+            .iter()
+            .filter(|(e, _)| e == endpoint)
+            .peekable();
+        if listed.peek().is_none() {
+            return Ok(PeerStatus::New);
+        }
+        if listed.any(|(_, known)| *known == fp) {
+            Ok(PeerStatus::Known)
+        } else {
+            Err(PeerError::KeyChanged {
+                endpoint: endpoint.to_owned(),
+                fingerprint: fp.encode_hex(),
+                path: self
+                    .path
+                    .as_ref()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or("known_peers".to_owned()),
+            })
+        }
+    }
+
+    /// Allow `identity` on `endpoint`, in addition to any already listed.
+    pub fn add(&mut self, endpoint: &str, identity: &[u8; 32]) -> Result<(), PeerError> {
+        let fp = fingerprint(identity);
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{} {}", endpoint, fp.encode_hex::<String>())?;
+        }
+        self.entries.push((endpoint.to_owned(), fp));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_known_peers() {
+    let path = std::env::temp_dir().join(format!("known-peers-test-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let (alice, bob, carol) = ([1u8; 32], [2u8; 32], [3u8; 32]);
+
+    let mut peers = KnownPeers::load(&path).unwrap();
+    assert_eq!(peers.lookup("relay:443", &alice).unwrap(), PeerStatus::New);
+    assert_eq!(peers.lookup("relay:443", &bob).unwrap(), PeerStatus::New);
+    assert_eq!(peers.check("relay:443", &alice).unwrap(), PeerStatus::New);
+    assert_eq!(peers.check("relay:443", &alice).unwrap(), PeerStatus::Known);
+    let err = peers.check("relay:443", &bob).unwrap_err();
+    assert!(matches!(err, PeerError::KeyChanged { .. }));
+    assert!(err.to_string().contains("HAS CHANGED"));
+    assert_eq!(peers.check("other:443", &bob).unwrap(), PeerStatus::New);
+
+    // Entries persist, and several identities may share one endpoint.
+    let mut peers = KnownPeers::load(&path).unwrap();
+    peers.add("relay:443", &bob).unwrap();
+    let mut peers = KnownPeers::load(&path).unwrap();
+    assert_eq!(peers.check("relay:443", &alice).unwrap(), PeerStatus::Known);
+    assert_eq!(peers.check("relay:443", &bob).unwrap(), PeerStatus::Known);
+    assert!(peers.check("relay:443", &carol).is_err());
+
+    // Comments, and malformed lines.
+    let mut text = fs::read_to_string(&path).unwrap();
+    text.insert_str(0, "# known peers\n\n");
+    fs::write(&path, &text).unwrap();
+    assert_eq!(KnownPeers::load(&path).unwrap().entries.len(), 3);
+    fs::write(&path, text + "relay:443 nothex\n").unwrap();
+    assert!(matches!(
+        KnownPeers::load(&path),
+        Err(PeerError::Malformed(6))
+    ));
+    fs::remove_file(&path).unwrap();
+}
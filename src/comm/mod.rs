@@ -1,4 +1,6 @@
 // Communication module.
 // This requires tokio.
 
+pub mod authorized_clients;
+pub mod known_peers;
 pub mod ws;
@@ -1,6 +1,8 @@
 // Websocket based connection.
 
 use std::fmt::Binary;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
 use futures_util::{SinkExt, StreamExt, TryFutureExt};
@@ -10,6 +12,8 @@ use rand_chacha::ChaCha20Rng;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
+    sync::{mpsc, Notify},
+    time::{sleep, timeout},
 };
 use tokio_tungstenite::{
     accept_async, client_async, connect_async,
@@ -19,14 +23,23 @@ use tokio_tungstenite::{
 
 use crate::{
     aes::Aes,
-    pke::mqv::FHMQV,
-    stream::streamenc::AesCtrHmac,
+    comm::authorized_clients::{AuthorizedClients, ClientPolicy, RateLimiter},
+    comm::known_peers::{fingerprint, KnownPeers, PeerStatus},
+    pke::mqv::{confirmation_mac, CONFIRM_CLIENT, CONFIRM_SERVER, FHMQV},
+    stream::streamenc::{AesCtrHmac, RekeyPolicy, CLIENT_TO_SERVER, SERVER_TO_CLIENT},
+    util::{ct_eq, zero},
     wire::{
         self,
-        message::{Certificate, LinkMsg, Packet, StreamType, WireMessage},
+        cert::{unix_time, TrustStore},
+        hello::NegotiationError,
+        message::{
+            fatal_reason, Certificate, CipherSuite, Hello, LinkMsg, Packet, StreamType,
+            WireMessage, FATAL_HANDSHAKE, FATAL_INTEGRITY, FATAL_NEGOTIATION, FATAL_TCP_RESET,
+            FATAL_TCP_UNREACHABLE,
+        },
     },
 };
-use log::{error, info, log, warn};
+use log::{debug, error, info, log, warn};
 use postcard::{from_bytes, to_allocvec, to_vec};
 
 #[derive(Debug, Clone)]
@@ -42,15 +55,54 @@ enum ConnectionState {
     Aborted,
 }
 
+/// Which end of the connection this is, for the direction of its keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Client,
+    Server,
+}
+
 pub struct WsConnection {
-    enc_state: Option<AesCtrHmac>,
+    /// Keys of what this side sends, and of what it receives.
+    send_state: Option<AesCtrHmac>,
+    recv_state: Option<AesCtrHmac>,
+    /// Bytes per second in each direction, if limited.
+    rate_limit: Option<u64>,
+    /// When each direction moves to its next keys.
+    rekey: RekeyPolicy,
 }
 
 impl WsConnection {
-    pub fn new(aes_key: &[u8; 16], mac_key: &[u8; 16], serial: u64) -> Self {
-        Self {
-            enc_state: Some(AesCtrHmac::new(aes_key, mac_key, serial)),
-        }
+    /// Stream protection with the cipher suite negotiated in the handshake.
+    ///
+    /// Each direction gets its own keys from the handshake `key`, which is zeroed.
+    pub fn new(suite: CipherSuite, key: &mut [u8; 32], side: Side) -> Result<Self> {
+        let (send, recv) = match side {
+            Side::Client => (CLIENT_TO_SERVER, SERVER_TO_CLIENT),
+            Side::Server => (SERVER_TO_CLIENT, CLIENT_TO_SERVER),
+        };
+        let states = match suite {
+            CipherSuite::AesCtrHmac => {
+                Ok((AesCtrHmac::derive(key, send), AesCtrHmac::derive(key, recv)))
+            }
+            other => Err(anyhow!("cipher suite {:?} is not implemented.", other)),
+        };
+        zero(key);
+        let (send_state, recv_state) = states?;
+        Ok(Self {
+            send_state: Some(send_state),
+            recv_state: Some(recv_state),
+            rate_limit: None,
+            rekey: RekeyPolicy::default(),
+        })
+    }
+
+    pub fn set_rekey_policy(&mut self, policy: RekeyPolicy) {
+        self.rekey = policy;
+    }
+
+    pub fn set_rate_limit(&mut self, bytes_per_second: Option<u64>) {
+        self.rate_limit = bytes_per_second;
     }
     pub async fn client_hello(&mut self) -> Result<()> {
         Ok(())
@@ -99,90 +151,215 @@ impl WsConnection {
         Ok(())
     }
 
+    /// Relay between `ws` and `tcp` until both directions are closed.
+    ///
+    /// Local EOF is passed on as an authenticated close notify, and a close notify from
+    /// the peer becomes EOF on `tcp`; the other direction stays open until it closes too.
+    /// Anything else ending the stream, a fatal alert from the peer, a stream cut short or
+    /// too many integrity errors, resets `tcp` instead, so truncation never looks clean.
     pub async fn message_crypt(
         &mut self,
-        mut ws: WebSocketStream<TcpStream>,
+        ws: WebSocketStream<TcpStream>,
         mut tcp: TcpStream,
     ) -> Result<()> {
         let mut buffer: Vec<u8> = vec![];
         buffer.resize(1024 * 1024, 0);
         let (mut tx, mut rx) = ws.split();
-        let (mut tcp_rx, mut tcp_tx) = tcp.into_split();
-        let mut state_recv = self.enc_state.as_ref().unwrap().clone();
-        let mut state_send = self.enc_state.as_ref().unwrap().clone();
-        let a = tokio::spawn(async move {
+        let (mut tcp_rx, mut tcp_tx) = tcp.split();
+        // Taken, so no copy of the keys outlives the connection.
+        let mut state_recv = self.recv_state.take().ok_or(anyhow!("no key."))?;
+        let mut state_send = self.send_state.take().ok_or(anyhow!("no key."))?;
+        let mut limit_recv = self.rate_limit.map(RateLimiter::new);
+        let mut limit_send = limit_recv.clone();
+        let rekey = self.rekey.clone();
+        // Receiving half to sending half: stop, sending a fatal alert with this code if any.
+        let (abort_tx, mut abort_rx) = mpsc::unbounded_channel::<Option<u32>>();
+        // Sending half to receiving half: the local connection was reset.
+        let local_reset = Notify::new();
+
+        let recv = async {
             let mut error_count = 0;
             loop {
-                let msg = rx.next().await;
-                // Send to tcp.
-                if let Some(k) = msg {
-                    match k {
-                        Ok(Message::Binary(v)) => {
-                            // Deencaplusate data.
-                            let msg: Result<wire::message::Message, _> = from_bytes(&v);
-                            if let Ok(wire::message::Message::Data(Packet {
-                                payload: v,
-                                stream: _,
-                                stream_type: _,
-                            })) = msg
-                            {
-                                // Decrypt data.
-                                match state_recv.decrypt_stream(&v) {
-                                    Ok(data) => {
-                                        tcp_tx.write(&data).await.unwrap();
+                let frame = tokio::select! {
+                    frame = rx.next() => frame,
+                    _ = local_reset.notified() => return Err(anyhow!("local connection reset.")),
+                };
+                let v = match frame {
+                    Some(Ok(Message::Binary(v))) => v,
+                    Some(Ok(Message::Close(_))) | None => {
+                        let _ = abort_tx.send(None);
+                        return Err(anyhow!("stream truncated: closed without close notify."));
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        let _ = abort_tx.send(None);
+                        return Err(e.into());
+                    }
+                };
+                let result = match from_bytes(&v) {
+                    Ok(WireMessage::Encrypted { msg }) => match from_bytes(&msg) {
+                        Ok(wire::message::Message::Data(Packet {
+                            payload: v,
+                            stream: _,
+                            stream_type: _,
+                        })) => {
+                            // Decrypt data.
+                            match state_recv.decrypt_stream(&v) {
+                                Ok(data) => {
+                                    if let Some(limit) = limit_recv.as_mut() {
+                                        sleep(limit.delay(data.len())).await;
                                     }
-                                    Err(e) => {
-                                        error_count += 1;
-                                        eprintln!("{}", e);
-                                        if error_count > 5 {
-                                            panic!("Too many integrity errors!")
-                                        }
+                                    if tcp_tx.write_all(&data).await.is_err() {
+                                        let _ = abort_tx.send(Some(FATAL_TCP_RESET));
+                                        return Err(anyhow!("local connection reset."));
                                     }
+                                    Ok(())
                                 }
-                            } else {
-                                eprintln!("format mismatch!");
+                                Err(e) => Err(e.into()),
                             }
                         }
-                        Ok(Message::Close(_)) => {
-                            return;
+                        // Packets before this one were under the old keys, after it under the new.
+                        Ok(wire::message::Message::ReKey { serial, mac }) => {
+                            state_recv.accept_rekey(serial, &mac).map_err(Into::into)
                         }
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!("{}", e);
-                            panic!();
+                        Ok(wire::message::Message::Fatal { code, serial, mac }) => {
+                            match state_recv.open_fatal(code, serial, &mac) {
+                                Ok(()) => {
+                                    let _ = abort_tx.send(None);
+                                    return Err(anyhow!("peer aborted: {}.", fatal_reason(code)));
+                                }
+                                Err(e) => Err(e.into()),
+                            }
                         }
+                        Err(e) => Err(e.into()),
+                    },
+                    Ok(WireMessage::Link {
+                        msg: LinkMsg::Shutdown { serial, mac },
+                    }) => match state_recv.open_shutdown(serial, &mac) {
+                        // Half close: the local side reads EOF, and may go on sending.
+                        Ok(()) => return Ok(tcp_tx.shutdown().await?),
+                        Err(e) => Err(e.into()),
+                    },
+                    _ => Err(anyhow!("format mismatch!")),
+                };
+                if let Err(e) = result {
+                    error_count += 1;
+                    eprintln!("{}", e);
+                    if error_count > 5 {
+                        let _ = abort_tx.send(Some(FATAL_INTEGRITY));
+                        return Err(anyhow!("too many integrity errors."));
                     }
                 }
             }
-        });
-        let b = tokio::spawn(async move {
+        };
+        let send = async {
             loop {
-                let _ = tcp_rx.readable().await.unwrap();
+                // Rekey on schedule even when idle, so old keys do not linger.
+                let read = tokio::select! {
+                    read = timeout(
+                        state_send.rekey_due_in(&rekey),
+                        tcp_rx.read(buffer.as_mut_slice()),
+                    ) => read,
+                    abort = abort_rx.recv() => {
+                        if let Some(Some(code)) = abort {
+                            let (serial, mac) = state_send.seal_fatal(code);
+                            let fatal = wire::message::Message::Fatal { code, serial, mac };
+                            tx.send(encrypted_frame(&fatal)?).await?;
+                        }
+                        return Ok(());
+                    }
+                };
+                if state_send.needs_rekey(&rekey) {
+                    let (serial, mac) = state_send.start_rekey();
+                    let msg = wire::message::Message::ReKey { serial, mac };
+                    tx.send(encrypted_frame(&msg)?).await?;
+                }
+                let msglen = match read {
+                    // Local EOF: close notify, then only receive.
+                    Ok(Ok(0)) => {
+                        let (serial, mac) = state_send.seal_shutdown();
+                        let msg = WireMessage::Link {
+                            msg: LinkMsg::Shutdown { serial, mac },
+                        };
+                        tx.send(Message::Binary(to_allocvec(&msg)?)).await?;
+                        return Ok(());
+                    }
+                    Ok(Ok(msglen)) => msglen,
+                    Ok(Err(_)) => {
+                        let (serial, mac) = state_send.seal_fatal(FATAL_TCP_RESET);
+                        let fatal = wire::message::Message::Fatal {
+                            code: FATAL_TCP_RESET,
+                            serial,
+                            mac,
+                        };
+                        let _ = tx.send(encrypted_frame(&fatal)?).await;
+                        local_reset.notify_one();
+                        return Err(anyhow!("local connection reset."));
+                    }
+                    Err(_) => continue,
+                };
                 // Send to ws.
-                let msglen = tcp_rx.read(buffer.as_mut_slice()).await.unwrap();
+                if let Some(limit) = limit_send.as_mut() {
+                    sleep(limit.delay(msglen)).await;
+                }
                 let enc_data = state_send.encrypt_stream(&buffer[..msglen]);
                 let enc_data = wire::message::Message::Data(Packet {
                     payload: enc_data,
                     stream: 0,
                     stream_type: StreamType::TCP,
                 });
-                let enc_data = to_allocvec(&enc_data).unwrap();
-                tx.send(Message::Binary(enc_data)).await.unwrap();
+                tx.send(encrypted_frame(&enc_data)?).await?;
             }
-        });
-        tokio::join!(a, b).0.unwrap();
+        };
+        let (sent, received): (Result<()>, Result<()>) = tokio::join!(send, recv);
+        if let Ok(mut ws) = rx.reunite(tx) {
+            let _ = ws.close(None).await;
+        }
+        match (sent, received) {
+            (Ok(()), Ok(())) => Ok(()),
+            (sent, received) => {
+                // Reset, so the local side does not take a failure for a clean end.
+                let _ = tcp.set_linger(Some(Duration::ZERO));
+                Err(received.err().or(sent.err()).unwrap())
+            }
+        }
+    }
+
+    /// Refuse the connection after the handshake, with an authenticated alert.
+    pub async fn abort(&mut self, mut ws: WebSocketStream<TcpStream>, code: u32) -> Result<()> {
+        let state = self.send_state.as_mut().ok_or(anyhow!("no key."))?;
+        let (serial, mac) = state.seal_fatal(code);
+        let fatal = wire::message::Message::Fatal { code, serial, mac };
+        ws.send(encrypted_frame(&fatal)?).await?;
+        ws.close(None).await?;
         Ok(())
     }
 }
 
+/// A websocket frame carrying `msg` in `WireMessage::Encrypted`.
+fn encrypted_frame(msg: &wire::message::Message) -> Result<Message> {
+    let msg = to_allocvec(msg)?;
+    Ok(Message::Binary(to_allocvec(&WireMessage::Encrypted {
+        msg,
+    })?))
+}
+
 #[derive(Debug, Clone)]
 pub struct WsServer {
     local_endpoint: String,
     tcp_endpoint: String,
     certificate: Option<Vec<Certificate>>,
     identity_key: [u8; 32],
-    /// Expected identity of remote. If this exists, then will replace the pubkey given by client.
+    /// Expected identity of remote. If this exists, any other client is refused.
     remote_identity_key: Option<[u8; 32]>,
+    /// Trusted roots and revocations. If this exists, the client identity must be certified.
+    trust: Option<TrustStore>,
+    /// Clients seen on `local_endpoint`, shared by all connections.
+    known_peers: Option<Arc<Mutex<KnownPeers>>>,
+    /// If this exists, only listed clients are accepted, each under its policy.
+    authorized: Option<AuthorizedClients>,
+    /// Versions and algorithms this server accepts.
+    hello: Hello,
 }
 
 impl WsServer {
@@ -192,6 +369,7 @@ impl WsServer {
         cert: Option<Vec<Certificate>>,
         id: [u8; 32],
         remote_id: Option<[u8; 32]>,
+        trust: Option<TrustStore>,
     ) -> Self {
         Self {
             local_endpoint: local,
@@ -199,8 +377,23 @@ impl WsServer {
             certificate: cert,
             identity_key: id,
             remote_identity_key: remote_id,
+            trust,
+            known_peers: None,
+            authorized: None,
+            hello: Hello::local(),
         }
     }
+
+    /// Accept only clients listed for `local_endpoint`, recording the first one.
+    /// Ignored when authorized clients are configured.
+    pub fn set_known_peers(&mut self, peers: KnownPeers) {
+        self.known_peers = Some(Arc::new(Mutex::new(peers)));
+    }
+
+    /// Accept only the listed clients, with their endpoint, rate limit and expiry.
+    pub fn set_authorized_clients(&mut self, clients: AuthorizedClients) {
+        self.authorized = Some(clients);
+    }
     pub async fn server_main_loop(&mut self) -> Result<()> {
         // Listen on ws endpoint
         let listener = TcpListener::bind(&self.local_endpoint).await?;
@@ -208,8 +401,6 @@ impl WsServer {
         while let Ok((tcp_stream, _remote_addr)) = listener.accept().await {
             // Accept ws link.
             let ws = accept_async(tcp_stream).await?;
-            // Connect to local endpoint.
-            let local_link = TcpStream::connect(&self.tcp_endpoint).await?;
             // Handover to WsConnection.
             let mut state = self.clone();
             let mut session_key = [0u8; 32];
@@ -217,75 +408,250 @@ impl WsServer {
             tokio::spawn(async move {
                 let session_key = session_key;
                 let mut ws = ws;
-                let mut local_link = local_link;
-                let key = state
-                    .server_hello(session_key, &mut ws, &mut local_link)
-                    .await
-                    .unwrap();
-
-                println!("shared key {}", key.encode_hex::<String>());
-                // Start stream.
-                let mut conn_state = WsConnection::new(
-                    key[0..16].try_into().unwrap(),
-                    key[16..].try_into().unwrap(),
-                    0,
+                let (mut key, suite, policy) = match state.server_hello(session_key, &mut ws).await
+                {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        eprintln!("handshake failed: {}", e);
+                        return;
+                    }
+                };
+                debug!(
+                    "session key fingerprint {}",
+                    fingerprint(&key).encode_hex::<String>()
                 );
-                conn_state.message_crypt(ws, local_link).await.unwrap();
+                let mut conn_state = match WsConnection::new(suite, &mut key, Side::Server) {
+                    Ok(conn_state) => conn_state,
+                    Err(e) => {
+                        eprintln!("handshake failed: {}", e);
+                        return;
+                    }
+                };
+                conn_state.set_rate_limit(policy.as_ref().and_then(|p| p.rate_limit));
+                // Connect to local endpoint, which may depend on the client.
+                let endpoint = policy
+                    .and_then(|p| p.tcp_endpoint)
+                    .unwrap_or(state.tcp_endpoint.clone());
+                let local_link = match TcpStream::connect(&endpoint).await {
+                    Ok(link) => link,
+                    Err(e) => {
+                        eprintln!("cannot connect to {}: {}", endpoint, e);
+                        let _ = conn_state.abort(ws, FATAL_TCP_UNREACHABLE).await;
+                        return;
+                    }
+                };
+                // Start stream.
+                if let Err(e) = conn_state.message_crypt(ws, local_link).await {
+                    eprintln!("connection closed: {}", e);
+                }
             });
         }
 
         Ok(())
     }
+    /// Server side of the handshake.
+    ///
+    /// Client hello with its offer -> server hello with the selection and the server's
+    /// confirmation MAC -> `KeyConfirm` with the client's. Both MACs cover both hellos,
+    /// so a downgraded offer is caught. The key is returned only when the client's MAC checks,
+    /// with the selected cipher suite, and the client's policy if authorized clients are configured.
+    ///
+    /// A client is refused before any key is derived if it is not the pinned identity,
+    /// or not authorized. A refused client is sent a `WireMessage::Fatal`.
     pub async fn server_hello(
         &mut self,
         session_key: [u8; 32],
         ws: &mut WebSocketStream<TcpStream>,
-        tcp: &mut TcpStream,
-    ) -> Result<[u8; 32]> {
+    ) -> Result<([u8; 32], CipherSuite, Option<ClientPolicy>)> {
+        let result = self.server_handshake(session_key, ws).await;
+        if let Err(e) = &result {
+            refuse(ws, e).await;
+        }
+        result
+    }
+
+    async fn server_handshake(
+        &mut self,
+        session_key: [u8; 32],
+        ws: &mut WebSocketStream<TcpStream>,
+    ) -> Result<([u8; 32], CipherSuite, Option<ClientPolicy>)> {
         let mut mqv = FHMQV::new(self.identity_key, session_key);
         // Receive message.
-        let client_hello = ws.next().await.transpose()?.ok_or(anyhow!("No message."))?;
-
-        // Parse message.
-        if let Message::Binary(k) = client_hello {
-            // decode data.
-            let mqv_msg: WireMessage = from_bytes(&k)?;
-            if let WireMessage::Link {
+        let client_hello = receive(ws).await?;
+        let (offer, identity, ephemeral_key, certification) = match &client_hello {
+            WireMessage::Link {
                 msg:
                     LinkMsg::FHMQVHandshake {
+                        hello,
                         identity,
                         ephemeral_key,
                         certification,
-                        mac,
-                    },
-            } = mqv_msg
-            {
-                mqv.set_remote_key(self.remote_identity_key.unwrap_or(identity), ephemeral_key)?;
-
-                // Get key.
-                let key = mqv.key_server()?;
-                let key_send = mqv.send();
-
-                // Generate message.
-                let msg = WireMessage::Link {
-                    msg: LinkMsg::FHMQVHandshake {
-                        identity: key_send.0,
-                        ephemeral_key: key_send.1,
-                        certification: self.certificate.clone(),
-                        mac: [0u8; 32],
+                        ..
                     },
-                };
-                // Send message.
-                let msg_vec = to_allocvec(&msg)?;
-                ws.send(Message::Binary(msg_vec)).await?;
-                Ok(key)
-            } else {
-                return Err(anyhow!("invalid message!"));
+            } => (hello, *identity, *ephemeral_key, certification),
+            _ => return Err(anyhow!("invalid message!")),
+        };
+        let selection = self.hello.select(offer)?;
+        let suite = selection.cipher_suites[0];
+        if matches!(self.remote_identity_key, Some(pinned) if pinned != identity) {
+            return Err(anyhow!("client identity does not match the pinned key."));
+        }
+        let policy = match &self.authorized {
+            Some(clients) => Some(clients.authorize(&identity, unix_time())?.clone()),
+            None => None,
+        };
+        check_certification(&identity, certification, &self.trust)?;
+        // authorized_clients lists every client itself; one TOFU entry per endpoint would
+        // lock out all but the first.
+        let known_peers = self
+            .known_peers
+            .as_ref()
+            .filter(|_| self.authorized.is_none());
+        lookup_known_peer(known_peers, &self.local_endpoint, &identity)?;
+        mqv.set_remote_key(identity, ephemeral_key)?;
+
+        // Get key.
+        let key = mqv.key_server()?;
+        let key_send = mqv.send();
+
+        // Generate message.
+        let mut msg = WireMessage::Link {
+            msg: LinkMsg::FHMQVHandshake {
+                hello: selection,
+                identity: key_send.0,
+                ephemeral_key: key_send.1,
+                certification: self.certificate.clone(),
+                mac: [0u8; 32],
+            },
+        };
+        let client_bytes = handshake_bytes(&client_hello)?;
+        let server_bytes = handshake_bytes(&msg)?;
+        set_handshake_mac(
+            &mut msg,
+            confirmation_mac(&key, CONFIRM_SERVER, &client_bytes, &server_bytes),
+        );
+        // Send message.
+        let msg_vec = to_allocvec(&msg)?;
+        ws.send(Message::Binary(msg_vec)).await?;
+
+        // Check the client derived the same key.
+        match receive(ws).await? {
+            WireMessage::Link {
+                msg: LinkMsg::KeyConfirm { mac },
+            } => {
+                let expected = confirmation_mac(&key, CONFIRM_CLIENT, &client_bytes, &server_bytes);
+                if !ct_eq(&mac, &expected) {
+                    return Err(anyhow!(
+                        "key confirmation failed: client derived another key."
+                    ));
+                }
+                record_known_peer(known_peers, &self.local_endpoint, &identity)?;
+                Ok((key, suite, policy))
             }
-        } else {
-            return Err(anyhow!("invalid message!"));
+            _ => Err(anyhow!("invalid message!")),
+        }
+    }
+}
+
+/// With a trust store configured, the remote identity must come with an unrevoked chain
+/// up to one of its roots.
+fn check_certification(
+    identity: &[u8; 32],
+    certification: &Option<Vec<Certificate>>,
+    trust: &Option<TrustStore>,
+) -> Result<()> {
+    if let Some(trust) = trust {
+        let chain = certification.as_deref().unwrap_or(&[]);
+        trust
+            .verify(identity, chain, unix_time())
+            .map_err(|e| anyhow!("identity is not certified: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Refuse an identity other than the one known for `endpoint`, before any key is derived.
+fn lookup_known_peer(
+    peers: Option<&Arc<Mutex<KnownPeers>>>,
+    endpoint: &str,
+    identity: &[u8; 32],
+) -> Result<()> {
+    if let Some(peers) = peers {
+        let peers = peers
+            .lock()
+            .map_err(|_| anyhow!("known_peers lock poisoned."))?;
+        peers.lookup(endpoint, identity)?;
+    }
+    Ok(())
+}
+
+/// Record an identity seen first, once key confirmation proved the peer holds its key.
+fn record_known_peer(
+    peers: Option<&Arc<Mutex<KnownPeers>>>,
+    endpoint: &str,
+    identity: &[u8; 32],
+) -> Result<()> {
+    if let Some(peers) = peers {
+        let mut peers = peers
+            .lock()
+            .map_err(|_| anyhow!("known_peers lock poisoned."))?;
+        if peers.check(endpoint, identity)? == PeerStatus::New {
+            eprintln!(
+                "new identity for {} recorded, fingerprint {}",
+                endpoint,
+                fingerprint(identity).encode_hex::<String>()
+            );
         }
     }
+    Ok(())
+}
+
+/// Receive and decode the next handshake message.
+async fn receive(ws: &mut WebSocketStream<TcpStream>) -> Result<WireMessage> {
+    let msg = ws.next().await.transpose()?.ok_or(anyhow!("No message."))?;
+    if let Message::Binary(k) = msg {
+        match from_bytes(&k)? {
+            WireMessage::Fatal { code, .. } => Err(anyhow!(
+                "handshake refused by peer: {} (unauthenticated).",
+                fatal_reason(code)
+            )),
+            msg => Ok(msg),
+        }
+    } else {
+        Err(anyhow!("invalid message!"))
+    }
+}
+
+/// Tell the peer why the handshake failed, before hanging up.
+/// No key is confirmed yet, so the alert carries no MAC.
+async fn refuse(ws: &mut WebSocketStream<TcpStream>, e: &anyhow::Error) {
+    let code = if e.is::<NegotiationError>() {
+        FATAL_NEGOTIATION
+    } else {
+        FATAL_HANDSHAKE
+    };
+    let msg = WireMessage::Fatal {
+        code,
+        mac: [0u8; 32],
+    };
+    if let Ok(msg) = to_allocvec(&msg) {
+        let _ = ws.send(Message::Binary(msg)).await;
+    }
+}
+
+fn set_handshake_mac(msg: &mut WireMessage, value: [u8; 32]) {
+    if let WireMessage::Link {
+        msg: LinkMsg::FHMQVHandshake { mac, .. },
+    } = msg
+    {
+        *mac = value;
+    }
+}
+
+/// Postcard bytes of a hello with the mac field zeroed, as covered by key confirmation.
+fn handshake_bytes(msg: &WireMessage) -> Result<Vec<u8>> {
+    let mut msg = msg.clone();
+    set_handshake_mac(&mut msg, [0u8; 32]);
+    Ok(to_allocvec(&msg)?)
 }
 
 #[derive(Debug, Clone)]
@@ -295,7 +661,14 @@ pub struct WsClient {
     certificate: Option<Vec<Certificate>>,
     identity_key: [u8; 32],
     session_key: [u8; 32],
+    /// Expected identity of the server. If this exists, any other server is refused.
     remote_key: Option<[u8; 32]>,
+    /// Trusted roots and revocations. If this exists, the server identity must be certified.
+    trust: Option<TrustStore>,
+    /// Servers seen on `remote_endpoint`, shared by all connections.
+    known_peers: Option<Arc<Mutex<KnownPeers>>>,
+    /// Versions and algorithms offered to the server.
+    hello: Hello,
 }
 
 impl WsClient {
@@ -306,6 +679,7 @@ impl WsClient {
         id: [u8; 32],
         sk: [u8; 32],
         remote_id: Option<[u8; 32]>,
+        trust: Option<TrustStore>,
     ) -> Self {
         Self {
             remote_endpoint: remote,
@@ -314,8 +688,16 @@ impl WsClient {
             identity_key: id,
             remote_key: remote_id,
             session_key: sk,
+            trust,
+            known_peers: None,
+            hello: Hello::local(),
         }
     }
+
+    /// Accept only the server identity listed for `remote_endpoint`, recording it on first contact.
+    pub fn set_known_peers(&mut self, peers: KnownPeers) {
+        self.known_peers = Some(Arc::new(Mutex::new(peers)));
+    }
     pub async fn client_main_loop(&mut self) -> Result<()> {
         let listener = TcpListener::bind(&self.tcp_endpoint).await?;
         while let Ok((tcp_stream, _remote_addr)) = listener.accept().await {
@@ -333,34 +715,58 @@ impl WsClient {
             rng.fill_bytes(&mut sk);
             tokio::spawn(async move {
                 let mut remote_ws = remote_ws;
-                let mut tcp_stream = tcp_stream;
-                let key = state
-                    .client_hello(&mut remote_ws.0, &mut tcp_stream, sk)
-                    .await
-                    .unwrap();
+                let tcp_stream = tcp_stream;
+                let (mut key, suite) = match state.client_hello(&mut remote_ws.0, sk).await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        eprintln!("handshake failed: {}", e);
+                        let _ = tcp_stream.set_linger(Some(Duration::ZERO));
+                        return;
+                    }
+                };
                 // Start stream.
-                println!("shared key {}", key.encode_hex::<String>());
-                let mut conn_state = WsConnection::new(
-                    key[0..16].try_into().unwrap(),
-                    key[16..].try_into().unwrap(),
-                    0,
+                debug!(
+                    "session key fingerprint {}",
+                    fingerprint(&key).encode_hex::<String>()
                 );
-                conn_state
-                    .message_crypt(remote_ws.0, tcp_stream)
-                    .await
-                    .unwrap();
+                let mut conn_state = match WsConnection::new(suite, &mut key, Side::Client) {
+                    Ok(conn_state) => conn_state,
+                    Err(e) => {
+                        eprintln!("handshake failed: {}", e);
+                        let _ = tcp_stream.set_linger(Some(Duration::ZERO));
+                        return;
+                    }
+                };
+                if let Err(e) = conn_state.message_crypt(remote_ws.0, tcp_stream).await {
+                    eprintln!("connection closed: {}", e);
+                }
             });
         }
 
         Ok(())
     }
 
+    /// Client side of the handshake, see `WsServer::server_hello`.
+    ///
+    /// Fails before sending anything further if the server's confirmation MAC does not check,
+    /// but a `WireMessage::Fatal`.
     pub async fn client_hello(
         &mut self,
         ws: &mut WebSocketStream<TcpStream>,
-        tcp: &mut TcpStream,
         sk: [u8; 32],
-    ) -> Result<[u8; 32]> {
+    ) -> Result<([u8; 32], CipherSuite)> {
+        let result = self.client_handshake(ws, sk).await;
+        if let Err(e) = &result {
+            refuse(ws, e).await;
+        }
+        result
+    }
+
+    async fn client_handshake(
+        &mut self,
+        ws: &mut WebSocketStream<TcpStream>,
+        sk: [u8; 32],
+    ) -> Result<([u8; 32], CipherSuite)> {
         let mut mqv = FHMQV::new(self.identity_key, sk);
         // Send client hello.
         let key_send = mqv.send();
@@ -368,6 +774,7 @@ impl WsClient {
         // Generate message.
         let msg = WireMessage::Link {
             msg: LinkMsg::FHMQVHandshake {
+                hello: self.hello.clone(),
                 identity: key_send.0,
                 ephemeral_key: key_send.1,
                 certification: self.certificate.clone(),
@@ -378,34 +785,46 @@ impl WsClient {
         let msg_vec = to_allocvec(&msg)?;
         ws.send(Message::Binary(msg_vec)).await?;
         // Receive message.
-        let client_hello = ws.next().await.transpose()?.ok_or(anyhow!("No message."))?;
-
-        // Parse message.
-        if let Message::Binary(k) = client_hello {
-            // decode data.
-            let mqv_msg: WireMessage = from_bytes(&k)?;
-            if let WireMessage::Link {
+        let server_hello = receive(ws).await?;
+        let (selection, identity, ephemeral_key, certification, mac) = match &server_hello {
+            WireMessage::Link {
                 msg:
                     LinkMsg::FHMQVHandshake {
+                        hello,
                         identity,
                         ephemeral_key,
-                        certification: _,
-                        mac: _,
+                        certification,
+                        mac,
                     },
-            } = mqv_msg
-            {
-                mqv.set_remote_key(self.remote_key.unwrap_or(identity), ephemeral_key)?;
-
-                // Get key.
-                let key = mqv.key_client()?;
-                // Set key.
-                return Ok(key);
-            } else {
-                return Err(anyhow!("invalid message!"));
-            }
-        } else {
-            return Err(anyhow!("invalid message!"));
+            } => (hello, *identity, *ephemeral_key, certification, *mac),
+            _ => return Err(anyhow!("invalid message!")),
+        };
+        let suite = self.hello.accept(selection)?;
+        if matches!(self.remote_key, Some(pinned) if pinned != identity) {
+            return Err(anyhow!("server identity does not match the pinned key."));
         }
+        check_certification(&identity, certification, &self.trust)?;
+        lookup_known_peer(self.known_peers.as_ref(), &self.remote_endpoint, &identity)?;
+        mqv.set_remote_key(identity, ephemeral_key)?;
+
+        // Get key.
+        let key = mqv.key_client()?;
+        let client_bytes = handshake_bytes(&msg)?;
+        let server_bytes = handshake_bytes(&server_hello)?;
+        let expected = confirmation_mac(&key, CONFIRM_SERVER, &client_bytes, &server_bytes);
+        if !ct_eq(&mac, &expected) {
+            return Err(anyhow!(
+                "key confirmation failed: server derived another key."
+            ));
+        }
+        record_known_peer(self.known_peers.as_ref(), &self.remote_endpoint, &identity)?;
+        let confirm = WireMessage::Link {
+            msg: LinkMsg::KeyConfirm {
+                mac: confirmation_mac(&key, CONFIRM_CLIENT, &client_bytes, &server_bytes),
+            },
+        };
+        ws.send(Message::Binary(to_allocvec(&confirm)?)).await?;
+        Ok((key, suite))
     }
 }
 
@@ -439,3 +858,534 @@ async fn test_ws_tcp_bridge() -> Result<()> {
     tokio::join!(server, client);
     Ok(())
 }
+
+/// Two ends of a loopback TCP connection.
+#[cfg(test)]
+async fn tcp_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+    (client.unwrap(), server.unwrap().0)
+}
+
+/// Run both handshakes over a loopback websocket.
+#[cfg(test)]
+async fn loopback_handshake(
+    server: WsServer,
+    client: WsClient,
+) -> (
+    Result<([u8; 32], CipherSuite)>,
+    Result<([u8; 32], CipherSuite)>,
+) {
+    tampered_handshake(server, client, None).await
+}
+
+/// Two ends of a loopback websocket, client side first.
+#[cfg(test)]
+async fn ws_pair() -> (WebSocketStream<TcpStream>, WebSocketStream<TcpStream>) {
+    let (client_tcp, server_tcp) = tcp_pair().await;
+    let (server_ws, client_ws) = tokio::join!(
+        accept_async(server_tcp),
+        client_async("ws://localhost/", client_tcp)
+    );
+    (client_ws.unwrap().0, server_ws.unwrap())
+}
+
+/// Run both handshakes, through a relay rewriting the client's offer with `tamper` if given.
+#[cfg(test)]
+async fn tampered_handshake(
+    mut server: WsServer,
+    mut client: WsClient,
+    tamper: Option<fn(&mut Hello)>,
+) -> (
+    Result<([u8; 32], CipherSuite)>,
+    Result<([u8; 32], CipherSuite)>,
+) {
+    let (mut client_ws, mut server_ws) = ws_pair().await;
+    if let Some(tamper) = tamper {
+        let (relay_ws, relay_client_ws) = (server_ws, client_ws);
+        let (to_server, from_relay) = ws_pair().await;
+        (client_ws, server_ws) = (relay_client_ws, from_relay);
+        tokio::spawn(async move {
+            let (mut from_client, mut to_server) = (relay_ws, to_server);
+            let relay = async {
+                let mut client_hello = receive(&mut from_client).await?;
+                if let WireMessage::Link {
+                    msg: LinkMsg::FHMQVHandshake { hello, .. },
+                } = &mut client_hello
+                {
+                    tamper(hello);
+                }
+                to_server
+                    .send(Message::Binary(to_allocvec(&client_hello)?))
+                    .await?;
+                let server_hello = receive(&mut to_server).await?;
+                from_client
+                    .send(Message::Binary(to_allocvec(&server_hello)?))
+                    .await?;
+                let confirm = receive(&mut from_client).await?;
+                to_server
+                    .send(Message::Binary(to_allocvec(&confirm)?))
+                    .await?;
+                Ok::<(), anyhow::Error>(())
+            };
+            let _ = relay.await;
+            let _ = from_client.close(None).await;
+            let _ = to_server.close(None).await;
+        });
+    }
+    let mut session_key = [0u8; 32];
+    ChaCha20Rng::from_entropy().fill_bytes(&mut session_key);
+    let mut sk = [0u8; 32];
+    ChaCha20Rng::from_entropy().fill_bytes(&mut sk);
+    let (server_key, client_key) = tokio::join!(
+        async {
+            let key = server
+                .server_hello(session_key, &mut server_ws)
+                .await
+                .map(|(key, suite, _)| (key, suite));
+            if key.is_err() {
+                let _ = server_ws.close(None).await;
+            }
+            key
+        },
+        async {
+            let key = client.client_hello(&mut client_ws, sk).await;
+            // Hang up on failure, as the task would, so the server does not wait forever.
+            if key.is_err() {
+                let _ = client_ws.close(None).await;
+            }
+            key
+        }
+    );
+    (server_key, client_key)
+}
+
+#[tokio::test]
+async fn test_handshake_key_confirmation() {
+    let mut rng = ChaCha20Rng::from_entropy();
+    let mut server_id = [0u8; 32];
+    let mut client_id = [0u8; 32];
+    rng.fill_bytes(&mut server_id);
+    rng.fill_bytes(&mut client_id);
+    let server = WsServer::new(String::new(), String::new(), None, server_id, None, None);
+    let client = WsClient::new(
+        String::new(),
+        String::new(),
+        None,
+        client_id,
+        [0u8; 32],
+        None,
+        None,
+    );
+    let (server_key, client_key) = loopback_handshake(server.clone(), client.clone()).await;
+    assert_eq!(server_key.unwrap(), client_key.unwrap());
+
+    // A client pinning another server identity refuses it outright.
+    let mut other = [0u8; 32];
+    rng.fill_bytes(&mut other);
+    let pinned = WsClient::new(
+        String::new(),
+        String::new(),
+        None,
+        client_id,
+        [0u8; 32],
+        Some(crate::pke::ec25519::EdwardsPoint::get_pubkey(other)),
+        None,
+    );
+    let (server_key, client_key) = loopback_handshake(server.clone(), pinned).await;
+    let err = client_key.unwrap_err().to_string();
+    assert!(
+        err.contains("server identity does not match the pinned key"),
+        "{}",
+        err
+    );
+    assert!(server_key.is_err());
+
+    // A server pinning another client refuses it outright.
+    let pinned = WsServer::new(
+        String::new(),
+        String::new(),
+        None,
+        server_id,
+        Some(crate::pke::ec25519::EdwardsPoint::get_pubkey(other)),
+        None,
+    );
+    let (server_key, client_key) = loopback_handshake(pinned, client).await;
+    let err = server_key.unwrap_err().to_string();
+    assert!(err.contains("does not match the pinned key"), "{}", err);
+    let err = client_key.unwrap_err().to_string();
+    assert!(
+        err.contains("refused by peer: handshake refused"),
+        "{}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn test_handshake_certificates() {
+    use crate::pke::ec25519::EdwardsPoint;
+    let mut rng = ChaCha20Rng::from_entropy();
+    let mut key = || {
+        let mut k = [0u8; 32];
+        rng.fill_bytes(&mut k);
+        k
+    };
+    let (root, server_id, client_id, client_sign) = (key(), key(), key(), key());
+    let roots = Some(TrustStore::new(vec![EdwardsPoint::get_pubkey(root)]));
+    // The handshake identity is the MQV public key.
+    let client_identity = FHMQV::new(client_id, [0u8; 32]).send().0;
+    let mut cert = Certificate::new(
+        client_identity,
+        EdwardsPoint::get_pubkey(client_sign),
+        "client",
+        0,
+        u64::MAX,
+    );
+    cert.sign(root, "root");
+    let chain = vec![cert];
+
+    let server = WsServer::new(
+        String::new(),
+        String::new(),
+        None,
+        server_id,
+        None,
+        roots.clone(),
+    );
+    let client = |cert| {
+        WsClient::new(
+            String::new(),
+            String::new(),
+            cert,
+            client_id,
+            [0u8; 32],
+            None,
+            None,
+        )
+    };
+    let (server_key, client_key) =
+        loopback_handshake(server.clone(), client(Some(chain.clone()))).await;
+    assert_eq!(server_key.unwrap(), client_key.unwrap());
+
+    // Uncertified client.
+    let (server_key, client_key) = loopback_handshake(server.clone(), client(None)).await;
+    let err = server_key.unwrap_err().to_string();
+    assert!(err.contains("not certified"), "{}", err);
+    assert!(client_key.is_err());
+
+    // Revoked client certificate.
+    let mut crl = crate::wire::message::RevocationList::new("root", 0, u64::MAX);
+    crl.serials.push(chain[0].serial);
+    crl.sign(root);
+    let mut revoking = server.clone();
+    let mut trust = roots.clone().unwrap();
+    trust.add_revocation_list(crl).unwrap();
+    revoking.trust = Some(trust);
+    let (server_key, client_key) = loopback_handshake(revoking, client(Some(chain.clone()))).await;
+    let err = server_key.unwrap_err().to_string();
+    assert!(err.contains("revoked"), "{}", err);
+    assert!(client_key.is_err());
+
+    // The client requires a certified server, which has none.
+    let mut strict = client(Some(chain));
+    strict.trust = roots;
+    let (server_key, client_key) = loopback_handshake(server, strict).await;
+    assert!(client_key
+        .unwrap_err()
+        .to_string()
+        .contains("not certified"));
+    assert!(server_key.is_err());
+}
+
+#[tokio::test]
+async fn test_handshake_known_peers() {
+    let mut rng = ChaCha20Rng::from_entropy();
+    let mut server_id = [0u8; 32];
+    let mut client_id = [0u8; 32];
+    rng.fill_bytes(&mut server_id);
+    rng.fill_bytes(&mut client_id);
+    let server = WsServer::new(String::new(), String::new(), None, server_id, None, None);
+    let mut client = WsClient::new(
+        String::new(),
+        String::new(),
+        None,
+        client_id,
+        [0u8; 32],
+        None,
+        None,
+    );
+    client.set_known_peers(KnownPeers::default());
+
+    // First contact records the server, and the same server is accepted again.
+    let (server_key, client_key) = loopback_handshake(server.clone(), client.clone()).await;
+    assert_eq!(server_key.unwrap(), client_key.unwrap());
+    let (server_key, client_key) = loopback_handshake(server.clone(), client.clone()).await;
+    assert_eq!(server_key.unwrap(), client_key.unwrap());
+
+    // Another server on the same endpoint is refused.
+    rng.fill_bytes(&mut server_id);
+    let impostor = WsServer::new(String::new(), String::new(), None, server_id, None, None);
+    let (server_key, client_key) = loopback_handshake(impostor.clone(), client.clone()).await;
+    let err = client_key.unwrap_err().to_string();
+    assert!(err.contains("HAS CHANGED"), "{}", err);
+    assert!(server_key.is_err());
+
+    // A server is recorded only once key confirmation succeeds.
+    client.set_known_peers(KnownPeers::default());
+    let extend: fn(&mut Hello) = |hello| {
+        hello.extensions.push(crate::wire::message::Extension {
+            id: "injected".to_owned(),
+            critical: false,
+            value: vec![],
+        })
+    };
+    let (_, client_key) = tampered_handshake(impostor, client.clone(), Some(extend)).await;
+    let err = client_key.unwrap_err().to_string();
+    assert!(err.contains("key confirmation failed"), "{}", err);
+    let (server_key, client_key) = loopback_handshake(server, client).await;
+    assert_eq!(server_key.unwrap(), client_key.unwrap());
+}
+
+#[tokio::test]
+async fn test_handshake_authorized_clients() {
+    use crate::comm::authorized_clients::ClientPolicy;
+    let mut rng = ChaCha20Rng::from_entropy();
+    let mut server_id = [0u8; 32];
+    rng.fill_bytes(&mut server_id);
+    let client = |id| {
+        WsClient::new(
+            String::new(),
+            String::new(),
+            None,
+            id,
+            [0u8; 32],
+            None,
+            None,
+        )
+    };
+    let (mut alice, mut bob, mut carol, mut dave) = ([0u8; 32], [0u8; 32], [0u8; 32], [0u8; 32]);
+    rng.fill_bytes(&mut alice);
+    rng.fill_bytes(&mut bob);
+    rng.fill_bytes(&mut carol);
+    rng.fill_bytes(&mut dave);
+    let policy = |name: &str, id, expires| ClientPolicy {
+        name: name.to_owned(),
+        identity: FHMQV::new(id, [0u8; 32]).send().0.encode_hex(),
+        tcp_endpoint: None,
+        rate_limit: None,
+        expires,
+    };
+    let mut server = WsServer::new(String::new(), String::new(), None, server_id, None, None);
+    server.set_authorized_clients(
+        AuthorizedClients::new(vec![
+            policy("alice", alice, None),
+            policy("bob", bob, Some(1)),
+            policy("dave", dave, None),
+        ])
+        .unwrap(),
+    );
+    // Not keyed per client, so it must not get in the way of the allow-list.
+    server.set_known_peers(KnownPeers::default());
+
+    let (server_key, client_key) = loopback_handshake(server.clone(), client(alice)).await;
+    assert_eq!(server_key.unwrap(), client_key.unwrap());
+    let (server_key, client_key) = loopback_handshake(server.clone(), client(dave)).await;
+    assert_eq!(server_key.unwrap(), client_key.unwrap());
+    let (server_key, client_key) = loopback_handshake(server.clone(), client(bob)).await;
+    let err = server_key.unwrap_err().to_string();
+    assert!(err.contains("expired"), "{}", err);
+    assert!(client_key.is_err());
+    let (server_key, client_key) = loopback_handshake(server, client(carol)).await;
+    let err = server_key.unwrap_err().to_string();
+    assert!(err.contains("not authorized"), "{}", err);
+    assert!(client_key.is_err());
+}
+
+#[tokio::test]
+async fn test_handshake_negotiation() {
+    use crate::wire::message::CipherSuite;
+    let mut rng = ChaCha20Rng::from_entropy();
+    let mut server_id = [0u8; 32];
+    let mut client_id = [0u8; 32];
+    rng.fill_bytes(&mut server_id);
+    rng.fill_bytes(&mut client_id);
+    let mut server = WsServer::new(String::new(), String::new(), None, server_id, None, None);
+    let mut client = WsClient::new(
+        String::new(),
+        String::new(),
+        None,
+        client_id,
+        [0u8; 32],
+        None,
+        None,
+    );
+    // Pretend both sides prefer a stronger suite than the one an attacker wants.
+    server
+        .hello
+        .cipher_suites
+        .push(CipherSuite::ChaCha20Poly1305);
+    client
+        .hello
+        .cipher_suites
+        .insert(0, CipherSuite::ChaCha20Poly1305);
+    let (server_key, client_key) =
+        tampered_handshake(server.clone(), client.clone(), Some(|_| {})).await;
+    let (key, suite) = client_key.unwrap();
+    assert_eq!(server_key.unwrap(), (key, suite));
+    assert_eq!(suite, CipherSuite::ChaCha20Poly1305);
+    // Negotiated, but there is no stream protection for it yet.
+    let err = WsConnection::new(suite, &mut [0u8; 32], Side::Client)
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("not implemented"), "{}", err);
+
+    // Stripping it from the offer is caught by key confirmation.
+    let strip: fn(&mut Hello) = |hello| {
+        hello
+            .cipher_suites
+            .retain(|s| *s == CipherSuite::AesCtrHmac)
+    };
+    let (server_key, client_key) =
+        tampered_handshake(server.clone(), client.clone(), Some(strip)).await;
+    let err = client_key.unwrap_err().to_string();
+    assert!(err.contains("key confirmation failed"), "{}", err);
+    assert!(server_key.is_err());
+
+    // No common suite.
+    client.hello.cipher_suites = vec![CipherSuite::AesGcm];
+    let (server_key, client_key) = loopback_handshake(server, client).await;
+    let err = server_key.unwrap_err().to_string();
+    assert!(err.contains("no common cipher suite"), "{}", err);
+    assert!(client_key.is_err());
+}
+
+#[tokio::test]
+async fn test_message_crypt_rekey() {
+    let mut key = [0u8; 32];
+    ChaCha20Rng::from_entropy().fill_bytes(&mut key);
+    let policy = RekeyPolicy {
+        max_bytes: 1 << 30,
+        max_packets: 2,
+        max_age: Duration::from_millis(50),
+    };
+    let connection = |side| {
+        let mut conn = WsConnection::new(CipherSuite::AesCtrHmac, &mut key.clone(), side).unwrap();
+        conn.set_rekey_policy(policy.clone());
+        conn
+    };
+    let (client_ws, server_ws) = ws_pair().await;
+    let (mut client_app, client_tcp) = tcp_pair().await;
+    let (server_tcp, mut server_app) = tcp_pair().await;
+    let (mut client, mut server) = (connection(Side::Client), connection(Side::Server));
+    tokio::spawn(async move { client.message_crypt(client_ws, client_tcp).await });
+    tokio::spawn(async move { server.message_crypt(server_ws, server_tcp).await });
+
+    // Several packet-count rekeys, and an idle one, in both directions.
+    for i in 0..6u8 {
+        client_app.write_all(&[i; 10]).await.unwrap();
+        let mut buf = [0u8; 10];
+        server_app.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [i; 10]);
+        server_app.write_all(&[!i; 10]).await.unwrap();
+        client_app.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [!i; 10]);
+        if i == 3 {
+            sleep(Duration::from_millis(120)).await;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_message_crypt_teardown() {
+    let mut key = [0u8; 32];
+    ChaCha20Rng::from_entropy().fill_bytes(&mut key);
+    let connection =
+        |side| WsConnection::new(CipherSuite::AesCtrHmac, &mut key.clone(), side).unwrap();
+    let reset = |r: std::io::Result<usize>| matches!(r, Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset);
+
+    // Half close, one direction at a time.
+    let (client_ws, server_ws) = ws_pair().await;
+    let (mut client_app, client_tcp) = tcp_pair().await;
+    let (server_tcp, mut server_app) = tcp_pair().await;
+    let (mut client, mut server) = (connection(Side::Client), connection(Side::Server));
+    let client = tokio::spawn(async move { client.message_crypt(client_ws, client_tcp).await });
+    let server = tokio::spawn(async move { server.message_crypt(server_ws, server_tcp).await });
+    client_app.write_all(b"ping").await.unwrap();
+    client_app.shutdown().await.unwrap();
+    let mut buf = vec![];
+    server_app.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, b"ping");
+    server_app.write_all(b"pong").await.unwrap();
+    server_app.shutdown().await.unwrap();
+    buf.clear();
+    client_app.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, b"pong");
+    client.await.unwrap().unwrap();
+    server.await.unwrap().unwrap();
+
+    // The stream is cut without close notify: the local side is reset, not given EOF.
+    let (client_ws, mut server_ws) = ws_pair().await;
+    let (mut client_app, client_tcp) = tcp_pair().await;
+    let mut client = connection(Side::Client);
+    let client = tokio::spawn(async move { client.message_crypt(client_ws, client_tcp).await });
+    server_ws.close(None).await.unwrap();
+    let err = client.await.unwrap().unwrap_err().to_string();
+    assert!(err.contains("truncated"), "{}", err);
+    assert!(reset(client_app.read(&mut [0u8; 16]).await));
+
+    // Garbage gets an authenticated alert back instead of a panic.
+    let (client_ws, mut server_ws) = ws_pair().await;
+    let (mut client_app, client_tcp) = tcp_pair().await;
+    let mut client = connection(Side::Client);
+    let client = tokio::spawn(async move { client.message_crypt(client_ws, client_tcp).await });
+    let forged = encrypted_frame(&wire::message::Message::Data(Packet {
+        payload: vec![0u8; 64],
+        stream: 0,
+        stream_type: StreamType::TCP,
+    }))
+    .unwrap();
+    for _ in 0..6 {
+        server_ws.send(forged.clone()).await.unwrap();
+    }
+    let alert = receive(&mut server_ws).await.unwrap();
+    // It opens under the server's receive keys, and not reflected back to the client.
+    let mut state = connection(Side::Server).recv_state.unwrap();
+    let mut reflected = connection(Side::Client).recv_state.unwrap();
+    match alert {
+        WireMessage::Encrypted { msg } => match from_bytes(&msg).unwrap() {
+            wire::message::Message::Fatal { code, serial, mac } => {
+                assert_eq!(code, FATAL_INTEGRITY);
+                assert!(reflected.open_fatal(code, serial, &mac).is_err());
+                state.open_fatal(code, serial, &mac).unwrap();
+            }
+            msg => panic!("{:?}", msg),
+        },
+        msg => panic!("{:?}", msg),
+    }
+    let err = client.await.unwrap().unwrap_err().to_string();
+    assert!(err.contains("integrity"), "{}", err);
+    assert!(reset(client_app.read(&mut [0u8; 16]).await));
+
+    // A server which cannot reach its endpoint aborts; a forged abort is ignored.
+    let (client_ws, mut server_ws) = ws_pair().await;
+    let (mut client_app, client_tcp) = tcp_pair().await;
+    let mut client = connection(Side::Client);
+    let client = tokio::spawn(async move { client.message_crypt(client_ws, client_tcp).await });
+    let forged = wire::message::Message::Fatal {
+        code: FATAL_TCP_UNREACHABLE,
+        serial: 1,
+        mac: [0u8; 32],
+    };
+    server_ws
+        .send(encrypted_frame(&forged).unwrap())
+        .await
+        .unwrap();
+    connection(Side::Server)
+        .abort(server_ws, FATAL_TCP_UNREACHABLE)
+        .await
+        .unwrap();
+    let err = client.await.unwrap().unwrap_err().to_string();
+    assert!(err.contains("endpoint unreachable"), "{}", err);
+    assert!(reset(client_app.read(&mut [0u8; 16]).await));
+}
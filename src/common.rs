@@ -20,6 +20,8 @@ pub enum CryptError {
     HMACFailed,
     #[error("replay attack")]
     ReplayAttack,
+    #[error("checksum mismatch")]
+    InvalidChecksum,
 }
 
 pub trait Crypt<T> {
@@ -36,5 +38,7 @@ pub trait StatefulCrypt<T> {
 }
 
 pub trait CryptoHash<const N: usize> {
+    /// Input block size in bytes.
+    const BLOCK_SIZE: usize;
     fn hash(data: &[u8]) -> [u8; N];
 }
@@ -36,10 +36,17 @@ impl Galois {
         ret
     }
 
-    /// Over 0x011b.
+    /// Over 0x011b. a^254 = a^-1, as the multiplicative group has 255 elements.
+    /// inv(0) is 0.
     pub fn inv(a: u8) -> u8 {
-        let im = 0u16;
-        todo!()
+        // 254 = 0b1111_1110
+        let mut ret = 1u8;
+        let mut power = a;
+        for _ in 1..8 {
+            power = Self::mul(power, power);
+            ret = Self::mul(ret, power);
+        }
+        ret
     }
 }
 
@@ -49,3 +56,13 @@ fn test_galois_mul() {
     assert_eq!(Galois::mul(0x24, 0x49), 0xdc);
     assert_eq!(Galois::mul(0x07, 0xd1), 0x1);
 }
+
+#[test]
+fn test_galois_inv() {
+    assert_eq!(Galois::inv(0), 0);
+    assert_eq!(Galois::inv(1), 1);
+    assert_eq!(Galois::inv(0x53), 0xca);
+    for a in 1..=255u8 {
+        assert_eq!(Galois::mul(a, Galois::inv(a)), 1);
+    }
+}
@@ -5,15 +5,18 @@ pub mod galois;
 pub mod mp;
 pub mod pke;
 pub mod sha256;
+pub mod sha512;
+pub mod shamir;
 pub mod stream;
+pub mod transcript;
 pub mod ui;
 pub mod util;
 pub mod wire;
 use clap::Parser;
-use hex::FromHex;
+use hex::{FromHex, ToHex};
 use iced::{Application, Settings};
 use serde::{Deserialize, Serialize};
-use std::{env, fs, path::Path};
+use std::{env, fs, io, path::Path, process::exit};
 use ui::UI;
 
 /// TCP safe tranport wrapper.
@@ -23,6 +26,12 @@ struct Args {
     /// Alternative config file location, default to ~/.config/sst/config.toml
     #[arg(short, long)]
     config: Option<String>,
+    /// Print K-of-N Shamir backup shares of both keys and exit
+    #[arg(long, num_args = 2, value_names = ["K", "N"])]
+    backup: Option<Vec<u8>>,
+    /// Read backup shares of one key from stdin, one per line, print the key and exit
+    #[arg(long)]
+    restore: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +43,30 @@ pub struct Config {
 fn main() {
     // Is config folder exists?
     let args = Args::parse();
+    if args.restore {
+        let mut shares: Vec<shamir::Share> = vec![];
+        for (i, line) in io::stdin().lines().map_while(|l| l.ok()).enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match line.parse() {
+                Ok(share) => shares.push(share),
+                Err(e) => {
+                    eprintln!("line {}: invalid share: {}", i + 1, e);
+                    exit(1);
+                }
+            }
+        }
+        let key = match shamir::combine(&shares) {
+            Ok(key) => key,
+            Err(e) => {
+                eprintln!("cannot combine shares: {}", e);
+                exit(1);
+            }
+        };
+        println!("{}", key.encode_hex::<String>());
+        return;
+    }
     let home_path = env::var("HOME").unwrap_or("/".to_owned());
     let full_path = Path::new(&home_path);
     #[cfg(target_os = "windows")]
@@ -51,6 +84,16 @@ fn main() {
     let privkey = <[u8; 32]>::from_hex(&config.privkey).unwrap();
     let signkey = <[u8; 32]>::from_hex(&config.signkey).unwrap();
 
+    if let Some(backup) = args.backup {
+        for (name, key) in [("privkey", privkey), ("signkey", signkey)] {
+            println!("{}:", name);
+            for share in shamir::split(&key, backup[0], backup[1]).expect("invalid K, N") {
+                println!("{}", share);
+            }
+        }
+        return;
+    }
+
     // start gui.
     UI::run(Settings {
         flags: (privkey, signkey),
@@ -1,6 +1,10 @@
 /// Multi Precision Compute.
 ///
-use std::ops::{Add, BitAnd, BitOr, BitXor, Not, Sub};
+/// Fixed width unsigned integers, little endian bytes.
+/// Everything here runs in time which depends only on N (and on the
+/// modulus length for reductions), never on the values.
+use std::cmp::Ordering;
+use std::ops::{Add, BitAnd, BitOr, BitXor, Mul, Not, Shl, Shr, Sub};
 
 #[derive(Clone, Copy, Debug)]
 pub struct LargeInt<const N: usize> {
@@ -14,23 +18,491 @@ impl<const N: usize> Default for LargeInt<N> {
     }
 }
 
+/// a += b, returns carry. b may be shorter than a.
+fn adc(a: &mut [u8], b: &[u8]) -> u8 {
+    let mut carry = 0u16;
+    for i in 0..a.len() {
+        let bi = if i < b.len() { b[i] } else { 0 };
+        let s = a[i] as u16 + bi as u16 + carry;
+        a[i] = s as u8;
+        carry = s >> 8;
+    }
+    carry as u8
+}
+
+/// a -= b, returns borrow. b may be shorter than a.
+fn sbb(a: &mut [u8], b: &[u8]) -> u8 {
+    let mut borrow = 0i16;
+    for i in 0..a.len() {
+        let bi = if i < b.len() { b[i] } else { 0 };
+        let s = a[i] as i16 - bi as i16 - borrow;
+        a[i] = s as u8;
+        borrow = (s >> 8) & 1;
+    }
+    borrow as u8
+}
+
+/// out = a * b, truncated to out.len().
+fn mul_into(out: &mut [u8], a: &[u8], b: &[u8]) {
+    out.iter_mut().for_each(|x| *x = 0);
+    for i in 0..a.len().min(out.len()) {
+        let mut carry = 0u32;
+        for j in 0..b.len() {
+            if i + j >= out.len() {
+                break;
+            }
+            let s = out[i + j] as u32 + a[i] as u32 * b[j] as u32 + carry;
+            out[i + j] = s as u8;
+            carry = s >> 8;
+        }
+        if i + b.len() < out.len() {
+            out[i + b.len()] = carry as u8;
+        }
+    }
+}
+
+/// dst = src if choice is 1, in constant time.
+fn select_into(dst: &mut [u8], src: &[u8], choice: u8) {
+    let mask = 0u8.wrapping_sub(choice & 1);
+    for i in 0..dst.len() {
+        dst[i] ^= mask & (dst[i] ^ src[i]);
+    }
+}
+
+/// a -= m if a >= m, in constant time. a and m have the same length.
+fn reduce_once(a: &mut [u8], m: &[u8]) {
+    let mut t = a.to_vec();
+    let borrow = sbb(&mut t, m);
+    select_into(a, &t, borrow ^ 1);
+}
+
+/// Binary long division, (num / d, num mod d). d must not be zero.
+fn div_rem_slices(num: &[u8], d: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut q = vec![0u8; num.len()];
+    let mut r = vec![0u8; d.len() + 1];
+    for i in (0..num.len() * 8).rev() {
+        // r = r << 1 | bit
+        let mut carry = (num[i >> 3] >> (i & 7)) & 1;
+        for b in r.iter_mut() {
+            let top = *b >> 7;
+            *b = (*b << 1) | carry;
+            carry = top;
+        }
+        let mut t = r.clone();
+        let borrow = sbb(&mut t, d);
+        select_into(&mut r, &t, borrow ^ 1);
+        q[i >> 3] |= (borrow ^ 1) << (i & 7);
+    }
+    r.truncate(d.len());
+    (q, r)
+}
+
 impl<const N: usize> LargeInt<N> {
-    fn pow(&self, rhs: Self) -> Self {
-        todo!()
+    pub fn zero() -> Self {
+        Self::default()
+    }
+
+    pub fn one() -> Self {
+        let mut ret = Self::default();
+        if N > 0 {
+            ret.data[0] = 1;
+        }
+        ret
+    }
+
+    /// Big endian bytes, left padded or truncated to N.
+    pub fn from_be_slice(bytes: &[u8]) -> Self {
+        let mut ret = Self::default();
+        for (i, b) in bytes.iter().rev().take(N).enumerate() {
+            ret.data[i] = *b;
+        }
+        ret
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; N] {
+        let mut ret = self.data;
+        ret.reverse();
+        ret
+    }
+
+    /// Bit i, i < 8N.
+    pub fn bit(&self, i: usize) -> u8 {
+        (self.data[i >> 3] >> (i & 7)) & 1
+    }
+
+    /// Number of significant bits. Not constant time; use on public values only.
+    pub fn bits(&self) -> usize {
+        for i in (0..N).rev() {
+            if self.data[i] != 0 {
+                return i * 8 + 8 - self.data[i].leading_zeros() as usize;
+            }
+        }
+        0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.ct_eq(&Self::default()) == 1
+    }
+
+    /// 1 if equal, else 0.
+    pub fn ct_eq(&self, rhs: &Self) -> u8 {
+        let mut acc = 0u8;
+        for i in 0..N {
+            acc |= self.data[i] ^ rhs.data[i];
+        }
+        // acc - 1 borrows only if acc is 0.
+        (((acc as u16).wrapping_sub(1) >> 8) & 1) as u8
+    }
+
+    /// 1 if self < rhs, else 0.
+    pub fn ct_lt(&self, rhs: &Self) -> u8 {
+        self.overflowing_sub(rhs).1
+    }
+
+    /// Returns a if choice is 0, b if choice is 1.
+    pub fn select(a: &Self, b: &Self, choice: u8) -> Self {
+        let mut ret = *a;
+        select_into(&mut ret.data, &b.data, choice);
+        ret
+    }
+
+    /// Swap with q if bit is 1, in constant time.
+    pub fn swap(&mut self, q: &mut Self, bit: u8) {
+        let a = *self;
+        select_into(&mut self.data, &q.data, bit);
+        select_into(&mut q.data, &a.data, bit);
+    }
+
+    /// self + rhs, and the carry out.
+    pub fn overflowing_add(&self, rhs: &Self) -> (Self, u8) {
+        let mut ret = *self;
+        let carry = adc(&mut ret.data, &rhs.data);
+        (ret, carry)
+    }
+
+    /// self - rhs, and the borrow out.
+    pub fn overflowing_sub(&self, rhs: &Self) -> (Self, u8) {
+        let mut ret = *self;
+        let borrow = sbb(&mut ret.data, &rhs.data);
+        (ret, borrow)
+    }
+
+    /// Full product, (low N bytes, high N bytes).
+    pub fn mul_wide(&self, rhs: &Self) -> (Self, Self) {
+        let mut out = vec![0u8; 2 * N];
+        mul_into(&mut out, &self.data, &rhs.data);
+        let mut lo = Self::default();
+        let mut hi = Self::default();
+        lo.data.copy_from_slice(&out[..N]);
+        hi.data.copy_from_slice(&out[N..]);
+        (lo, hi)
+    }
+
+    /// (self / rhs, self mod rhs). rhs must not be zero.
+    pub fn div_rem(&self, rhs: &Self) -> (Self, Self) {
+        assert!(!rhs.is_zero(), "division by zero");
+        let (q, r) = div_rem_slices(&self.data, &rhs.data);
+        let mut ret = (Self::default(), Self::default());
+        ret.0.data.copy_from_slice(&q);
+        ret.1.data.copy_from_slice(&r);
+        ret
+    }
+
+    /// self mod rhs. Use `Barrett` or `Montgomery` to reduce many values by the same modulus.
+    pub fn fast_mod(&self, rhs: &LargeInt<N>) -> Self {
+        self.div_rem(rhs).1
+    }
+
+    /// self^rhs mod 2^(8N).
+    pub fn pow(&self, rhs: &Self) -> Self {
+        let mut ret = Self::one();
+        for i in (0..N * 8).rev() {
+            ret = ret * ret;
+            let t = ret * *self;
+            ret = Self::select(&ret, &t, rhs.bit(i));
+        }
+        ret
+    }
+
+    /// self^e mod m. m must not be zero.
+    pub fn pow_mod(&self, e: &Self, m: &Self) -> Self {
+        match Montgomery::new(m) {
+            Some(mont) => mont.pow(self, e),
+            None => Barrett::new(m).pow(self, e),
+        }
+    }
+
+    /// (self + rhs) mod m, for self, rhs < m.
+    pub fn add_mod(&self, rhs: &Self, m: &Self) -> Self {
+        let (sum, carry) = self.overflowing_add(rhs);
+        let (diff, borrow) = sum.overflowing_sub(m);
+        // Take the difference when the sum overflowed or did not borrow.
+        Self::select(&sum, &diff, carry | (borrow ^ 1))
+    }
+
+    /// (self - rhs) mod m, for self, rhs < m.
+    pub fn sub_mod(&self, rhs: &Self, m: &Self) -> Self {
+        let (diff, borrow) = self.overflowing_sub(rhs);
+        let fixed = diff.overflowing_add(m).0;
+        Self::select(&diff, &fixed, borrow)
+    }
+}
+
+/// Barrett reduction (HAC 14.42) with a precomputed mu = floor(256^(2k) / m),
+/// where k is the byte length of m.
+///
+/// Inputs must be below 256^(2k); when the top byte of m is not zero,
+/// that is every double width value.
+#[derive(Clone, Debug)]
+pub struct Barrett<const N: usize> {
+    m: LargeInt<N>,
+    mu: Vec<u8>,
+    k: usize,
+}
+
+impl<const N: usize> Barrett<N> {
+    pub fn new(m: &LargeInt<N>) -> Self {
+        let k = m.bits().div_ceil(8);
+        assert!(k > 0, "zero modulus");
+        let mut num = vec![0u8; 2 * k + 1];
+        num[2 * k] = 1;
+        let (mut mu, _) = div_rem_slices(&num, &m.data[..k]);
+        mu.truncate(k + 1);
+        Self { m: *m, mu, k }
+    }
+
+    pub fn modulus(&self) -> &LargeInt<N> {
+        &self.m
+    }
+
+    /// (hi * 256^N + lo) mod m.
+    pub fn reduce_wide(&self, lo: &LargeInt<N>, hi: &LargeInt<N>) -> LargeInt<N> {
+        let k = self.k;
+        let mut x = vec![0u8; 2 * N];
+        x[..N].copy_from_slice(&lo.data);
+        x[N..].copy_from_slice(&hi.data);
+        debug_assert!(x[2 * k..].iter().all(|b| *b == 0));
+        x.resize(2 * k + 1, 0);
+
+        let q1 = &x[k - 1..2 * k];
+        let mut q2 = vec![0u8; 2 * k + 2];
+        mul_into(&mut q2, q1, &self.mu);
+        let q3 = &q2[k + 1..];
+
+        let m = &self.m.data[..k];
+        let mut r = x[..k + 1].to_vec();
+        let mut r2 = vec![0u8; k + 1];
+        mul_into(&mut r2, q3, m);
+        sbb(&mut r, &r2);
+        let mut m_ext = m.to_vec();
+        m_ext.push(0);
+        reduce_once(&mut r, &m_ext);
+        reduce_once(&mut r, &m_ext);
+
+        let mut ret = LargeInt::default();
+        ret.data[..k].copy_from_slice(&r[..k]);
+        ret
+    }
+
+    /// x mod m, for x < 256^(2k).
+    pub fn reduce(&self, x: &LargeInt<N>) -> LargeInt<N> {
+        self.reduce_wide(x, &LargeInt::default())
+    }
+
+    /// a * b mod m, for a, b < m.
+    pub fn mul(&self, a: &LargeInt<N>, b: &LargeInt<N>) -> LargeInt<N> {
+        let (lo, hi) = a.mul_wide(b);
+        self.reduce_wide(&lo, &hi)
+    }
+
+    /// base^e mod m.
+    pub fn pow(&self, base: &LargeInt<N>, e: &LargeInt<N>) -> LargeInt<N> {
+        let base = base.fast_mod(&self.m);
+        let mut ret = LargeInt::one().fast_mod(&self.m);
+        for i in (0..N * 8).rev() {
+            ret = self.mul(&ret, &ret);
+            let t = self.mul(&ret, &base);
+            ret = LargeInt::select(&ret, &t, e.bit(i));
+        }
+        ret
+    }
+}
+
+/// Montgomery multiplication with R = 256^N, for odd m.
+#[derive(Clone, Debug)]
+pub struct Montgomery<const N: usize> {
+    m: LargeInt<N>,
+    /// -m^-1 mod 256
+    m_inv: u8,
+    /// R^2 mod m
+    r2: LargeInt<N>,
+}
+
+impl<const N: usize> Montgomery<N> {
+    /// `None` if m is even.
+    pub fn new(m: &LargeInt<N>) -> Option<Self> {
+        if N == 0 || m.data[0] & 1 == 0 {
+            return None;
+        }
+        // Newton iteration, each step doubles the correct bits (3 to begin with).
+        let m0 = m.data[0];
+        let mut inv = m0;
+        for _ in 0..3 {
+            inv = inv.wrapping_mul(2u8.wrapping_sub(m0.wrapping_mul(inv)));
+        }
+        // R^2 mod m by doubling 1 for 16N times.
+        let mut r2 = LargeInt::one().fast_mod(m);
+        for _ in 0..N * 16 {
+            r2 = r2.add_mod(&r2, m);
+        }
+        Some(Self {
+            m: *m,
+            m_inv: inv.wrapping_neg(),
+            r2,
+        })
+    }
+
+    pub fn modulus(&self) -> &LargeInt<N> {
+        &self.m
+    }
+
+    /// a * b / R mod m (CIOS), for a, b < m.
+    pub fn mul(&self, a: &LargeInt<N>, b: &LargeInt<N>) -> LargeInt<N> {
+        let m = &self.m.data;
+        let mut t = vec![0u8; N + 2];
+        for i in 0..N {
+            let mut carry = 0u32;
+            for (tj, aj) in t.iter_mut().zip(a.data.iter()) {
+                let s = *tj as u32 + *aj as u32 * b.data[i] as u32 + carry;
+                *tj = s as u8;
+                carry = s >> 8;
+            }
+            let s = t[N] as u32 + carry;
+            t[N] = s as u8;
+            t[N + 1] = (s >> 8) as u8;
+
+            let q = t[0].wrapping_mul(self.m_inv) as u32;
+            let s = t[0] as u32 + m[0] as u32 * q;
+            let mut carry = s >> 8;
+            for j in 1..N {
+                let s = t[j] as u32 + m[j] as u32 * q + carry;
+                t[j - 1] = s as u8;
+                carry = s >> 8;
+            }
+            let s = t[N] as u32 + carry;
+            t[N - 1] = s as u8;
+            t[N] = t[N + 1] + (s >> 8) as u8;
+            t[N + 1] = 0;
+        }
+        // t < 2m here.
+        let mut m_ext = m.to_vec();
+        m_ext.push(0);
+        let mut r = t[..N + 1].to_vec();
+        reduce_once(&mut r, &m_ext);
+        let mut ret = LargeInt::default();
+        ret.data.copy_from_slice(&r[..N]);
+        ret
+    }
+
+    /// a * R mod m.
+    pub fn to_mont(&self, a: &LargeInt<N>) -> LargeInt<N> {
+        self.mul(&a.fast_mod(&self.m), &self.r2)
+    }
+
+    /// a / R mod m.
+    pub fn from_mont(&self, a: &LargeInt<N>) -> LargeInt<N> {
+        self.mul(a, &LargeInt::one())
+    }
+
+    /// base^e mod m.
+    pub fn pow(&self, base: &LargeInt<N>, e: &LargeInt<N>) -> LargeInt<N> {
+        let base = self.to_mont(base);
+        let mut ret = self.to_mont(&LargeInt::one());
+        for i in (0..N * 8).rev() {
+            ret = self.mul(&ret, &ret);
+            let t = self.mul(&ret, &base);
+            ret = LargeInt::select(&ret, &t, e.bit(i));
+        }
+        self.from_mont(&ret)
+    }
+}
+
+impl<const N: usize> From<u64> for LargeInt<N> {
+    fn from(v: u64) -> Self {
+        let mut ret = Self::default();
+        for (i, b) in v.to_le_bytes().iter().take(N).enumerate() {
+            ret.data[i] = *b;
+        }
+        ret
+    }
+}
+
+impl<const N: usize> PartialEq for LargeInt<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other) == 1
+    }
+}
+
+impl<const N: usize> Eq for LargeInt<N> {}
+
+impl<const N: usize> PartialOrd for LargeInt<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<const N: usize> Ord for LargeInt<N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let lt = self.ct_lt(other);
+        let gt = other.ct_lt(self);
+        (gt as i8 - lt as i8).cmp(&0)
     }
 }
 
 impl<const N: usize> Add for LargeInt<N> {
     type Output = LargeInt<N>;
+    /// Wrapping addition.
     fn add(self, rhs: Self) -> Self::Output {
-        todo!()
+        self.overflowing_add(&rhs).0
     }
 }
 
 impl<const N: usize> Add for &LargeInt<N> {
     type Output = LargeInt<N>;
     fn add(self, rhs: Self) -> Self::Output {
-        todo!()
+        *self + *rhs
+    }
+}
+
+impl<const N: usize> Sub for LargeInt<N> {
+    type Output = LargeInt<N>;
+    /// Wrapping subtraction.
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.overflowing_sub(&rhs).0
+    }
+}
+
+impl<const N: usize> Sub for &LargeInt<N> {
+    type Output = LargeInt<N>;
+    fn sub(self, rhs: Self) -> Self::Output {
+        *self - *rhs
+    }
+}
+
+impl<const N: usize> Mul for LargeInt<N> {
+    type Output = LargeInt<N>;
+    /// Wrapping multiplication, the low half of `mul_wide`.
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut ret = LargeInt::default();
+        mul_into(&mut ret.data, &self.data, &rhs.data);
+        ret
+    }
+}
+
+impl<const N: usize> Mul for &LargeInt<N> {
+    type Output = LargeInt<N>;
+    fn mul(self, rhs: Self) -> Self::Output {
+        *self * *rhs
     }
 }
 
@@ -45,8 +517,165 @@ impl<const N: usize> BitXor for &LargeInt<N> {
     }
 }
 
-impl<const N: usize> LargeInt<N> {
-    pub fn fast_mod(&self, rhs: &LargeInt<N>) -> Self {
-        self + rhs
+impl<const N: usize> BitAnd for &LargeInt<N> {
+    type Output = LargeInt<N>;
+    fn bitand(self, rhs: Self) -> Self::Output {
+        let mut ret = LargeInt::default();
+        for i in 0..N {
+            ret.data[i] = self.data[i] & rhs.data[i]
+        }
+        ret
+    }
+}
+
+impl<const N: usize> BitOr for &LargeInt<N> {
+    type Output = LargeInt<N>;
+    fn bitor(self, rhs: Self) -> Self::Output {
+        let mut ret = LargeInt::default();
+        for i in 0..N {
+            ret.data[i] = self.data[i] | rhs.data[i]
+        }
+        ret
+    }
+}
+
+impl<const N: usize> Not for &LargeInt<N> {
+    type Output = LargeInt<N>;
+    fn not(self) -> Self::Output {
+        let mut ret = *self;
+        ret.data.iter_mut().for_each(|b| *b = !*b);
+        ret
+    }
+}
+
+impl<const N: usize> Shl<usize> for &LargeInt<N> {
+    type Output = LargeInt<N>;
+    /// Shift by a public amount.
+    fn shl(self, rhs: usize) -> Self::Output {
+        let mut ret = LargeInt::default();
+        let (bytes, bits) = (rhs / 8, rhs % 8);
+        for i in (bytes..N).rev() {
+            let lo = self.data[i - bytes] as u16;
+            let below = if i > bytes {
+                self.data[i - bytes - 1]
+            } else {
+                0
+            } as u16;
+            ret.data[i] = (((lo << 8 | below) << bits) >> 8) as u8;
+        }
+        ret
+    }
+}
+
+impl<const N: usize> Shr<usize> for &LargeInt<N> {
+    type Output = LargeInt<N>;
+    /// Shift by a public amount.
+    fn shr(self, rhs: usize) -> Self::Output {
+        let mut ret = LargeInt::default();
+        let (bytes, bits) = (rhs / 8, rhs % 8);
+        for i in 0..N.saturating_sub(bytes) {
+            let lo = self.data[i + bytes] as u16;
+            let above = if i + bytes + 1 < N {
+                self.data[i + bytes + 1]
+            } else {
+                0
+            } as u16;
+            ret.data[i] = ((above << 8 | lo) >> bits) as u8;
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+fn to_big<const N: usize>(a: &LargeInt<N>) -> num_bigint::BigUint {
+    num_bigint::BigUint::from_bytes_le(&a.data)
+}
+
+#[cfg(test)]
+fn from_big<const N: usize>(a: &num_bigint::BigUint) -> LargeInt<N> {
+    let mut ret = LargeInt::default();
+    for (i, b) in a.to_bytes_le().iter().take(N).enumerate() {
+        ret.data[i] = *b;
+    }
+    ret
+}
+
+#[test]
+fn test_mp_basic() {
+    use num_bigint::BigUint;
+    use rand::prelude::*;
+    use rand_chacha::ChaCha20Rng;
+
+    let mut rng = ChaCha20Rng::seed_from_u64(31);
+    let modulus = BigUint::from(1u8) << 256;
+    for _ in 0..100 {
+        let mut a = LargeInt::<32>::default();
+        let mut b = LargeInt::<32>::default();
+        rng.fill_bytes(&mut a.data);
+        let len = rng.gen_range(1..32);
+        rng.fill_bytes(&mut b.data[..len]);
+        let (ba, bb) = (to_big(&a), to_big(&b));
+
+        assert_eq!(to_big(&(a + b)), (&ba + &bb) % &modulus);
+        assert_eq!(to_big(&(a - b)), (&ba + &modulus - &bb) % &modulus);
+        let (lo, hi) = a.mul_wide(&b);
+        assert_eq!(to_big(&lo) + (to_big(&hi) << 256), &ba * &bb);
+        let (q, r) = a.div_rem(&b);
+        assert_eq!((to_big(&q), to_big(&r)), (&ba / &bb, &ba % &bb));
+        assert_eq!(a.cmp(&b), ba.cmp(&bb));
+        assert_eq!(to_big(&(&a << 13)), (&ba << 13) % &modulus);
+        assert_eq!(to_big(&(&a >> 77)), &ba >> 77);
+        assert_eq!(to_big(&(&a << 0)), ba);
+    }
+    let a = LargeInt::<32>::from(0xdeadbeefu64);
+    assert_eq!(a, a);
+    assert!(!a.is_zero() && LargeInt::<32>::zero().is_zero());
+    assert_eq!(
+        to_big(&a.pow(&LargeInt::from(3))),
+        BigUint::from(0xdeadbeefu64).pow(3)
+    );
+}
+
+#[test]
+fn test_mp_reduction() {
+    use rand::prelude::*;
+    use rand_chacha::ChaCha20Rng;
+
+    let mut rng = ChaCha20Rng::seed_from_u64(32);
+    // Order of G on ec25519, a random odd modulus and an even one.
+    let l = LargeInt::<32>::from_be_slice(
+        &hex::decode("1000000000000000000000000000000014def9dea2f79cd65812631a5cf5d3ed").unwrap(),
+    );
+    let mut odd = LargeInt::<32>::default();
+    rng.fill_bytes(&mut odd.data);
+    odd.data[0] |= 1;
+    let even = LargeInt::<32>::from(1000000u64);
+    for m in [l, odd, even] {
+        let bm = to_big(&m);
+        let barrett = Barrett::new(&m);
+        for _ in 0..20 {
+            let mut a = LargeInt::<32>::default();
+            let mut e = LargeInt::<32>::default();
+            rng.fill_bytes(&mut a.data);
+            rng.fill_bytes(&mut e.data);
+            let a = a.fast_mod(&m);
+            let ba = to_big(&a);
+            assert_eq!(to_big(&barrett.mul(&a, &a)), &ba * &ba % &bm);
+            assert_eq!(to_big(&a.pow_mod(&e, &m)), ba.modpow(&to_big(&e), &bm));
+            let b = e.fast_mod(&m);
+            assert_eq!(to_big(&a.add_mod(&b, &m)), (&ba + to_big(&b)) % &bm);
+            assert_eq!(to_big(&a.sub_mod(&b, &m)), (&ba + &bm - to_big(&b)) % &bm);
+            if let Some(mont) = Montgomery::new(&m) {
+                let am = mont.to_mont(&a);
+                assert_eq!(mont.from_mont(&mont.mul(&am, &am)), barrett.mul(&a, &a));
+            }
+        }
     }
+    // Wide reduction, as used for 64 byte hashes.
+    let mut lo = LargeInt::<32>::default();
+    let mut hi = LargeInt::<32>::default();
+    rng.fill_bytes(&mut lo.data);
+    rng.fill_bytes(&mut hi.data);
+    let expect = (to_big(&lo) + (to_big(&hi) << 256)) % to_big(&l);
+    assert_eq!(Barrett::new(&l).reduce_wide(&lo, &hi), from_big(&expect));
 }
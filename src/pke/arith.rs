@@ -5,172 +5,245 @@ use rand_chacha::ChaCha20Rng;
 use serde::{Deserialize, Serialize};
 use std::{
     cmp::Ordering,
-    num::Wrapping,
-    ops::{Add, BitAnd, BitOr, BitXor, Mul, Not, Sub},
+    ops::{Add, Mul, Neg, Sub},
 };
 
+const LOW_51_BIT_MASK: u64 = (1u64 << 51) - 1;
+
 pub const ITEM25519: P25519FieldItem = P25519FieldItem([
-    0xffed, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff,
-    0xffff, 0xffff, 0xffff, 0x7fff,
+    0x7ffffffffffed,
+    0x7ffffffffffff,
+    0x7ffffffffffff,
+    0x7ffffffffffff,
+    0x7ffffffffffff,
 ]);
+/// 2p, kept in unreduced limbs.
 pub const ITEM25519_2: P25519FieldItem = P25519FieldItem([
-    0xffda, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff,
-    0xffff, 0xffff, 0xffff, 0xffff,
+    0xfffffffffffda,
+    0xffffffffffffe,
+    0xffffffffffffe,
+    0xffffffffffffe,
+    0xffffffffffffe,
+]);
+/// 16p, added before subtraction so limbs never go negative.
+const ITEM25519_16: P25519FieldItem = P25519FieldItem([
+    0x7ffffffffffed0,
+    0x7ffffffffffff0,
+    0x7ffffffffffff0,
+    0x7ffffffffffff0,
+    0x7ffffffffffff0,
+]);
+pub const ZERO: P25519FieldItem = P25519FieldItem([0; 5]);
+pub const ONE: P25519FieldItem = P25519FieldItem([1, 0, 0, 0, 0]);
+pub const TWO: P25519FieldItem = P25519FieldItem([2, 0, 0, 0, 0]);
+pub const EIGHT: P25519FieldItem = P25519FieldItem([8, 0, 0, 0, 0]);
+/// sqrt(-1) = 2^((p-1)/4).
+pub const SQRT_M1: P25519FieldItem = P25519FieldItem([
+    0x61b274a0ea0b0,
+    0x0d5a5fc8f189d,
+    0x7ef5e9cbd0c60,
+    0x78595a6804c9e,
+    0x2b8324804fc1d,
 ]);
-pub const ZERO: P25519FieldItem = P25519FieldItem([0; 16]);
-pub const ONE: P25519FieldItem = P25519FieldItem([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-pub const TWO: P25519FieldItem = P25519FieldItem([2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-pub const EIGHT: P25519FieldItem =
-    P25519FieldItem([8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
 /// This struct stores a number over Z_p=2^255-19.
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
-pub struct P25519FieldItem(pub [i64; 16]);
+///
+/// The number is kept in 5 limbs of 51 bits (radix 2^51).
+/// Limbs may exceed 51 bits between operations; `carry` brings them back,
+/// and `pack` gives the canonical encoding.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default)]
+pub struct P25519FieldItem(pub [u64; 5]);
 
 impl P25519FieldItem {
+    /// Weak reduction: every limb is put below 2^51 (plus a tiny carry into limb 0).
     pub fn carry(&mut self) {
-        for i in 0..16 {
-            let carry = self.0[i] >> 16;
-            self.0[i] -= carry << 16;
-
-            if i < 15 {
-                self.0[i + 1] += carry;
-            } else {
-                self.0[0] += 38 * carry;
-            }
-        }
+        let l = &mut self.0;
+        let c0 = l[0] >> 51;
+        let c1 = l[1] >> 51;
+        let c2 = l[2] >> 51;
+        let c3 = l[3] >> 51;
+        let c4 = l[4] >> 51;
+        l[0] &= LOW_51_BIT_MASK;
+        l[1] &= LOW_51_BIT_MASK;
+        l[2] &= LOW_51_BIT_MASK;
+        l[3] &= LOW_51_BIT_MASK;
+        l[4] &= LOW_51_BIT_MASK;
+        // 2^255 = 19 mod p.
+        l[0] += c4 * 19;
+        l[1] += c0;
+        l[2] += c1;
+        l[3] += c2;
+        l[4] += c3;
     }
 
     pub fn carry_new(&self) -> Self {
-        let mut ret = self.clone();
+        let mut ret = *self;
         ret.carry();
         ret
     }
 
+    /// Reduce 128-bit limb accumulators produced by `mul` and `square`.
+    #[inline(always)]
+    fn reduce_wide(c: [u128; 5]) -> Self {
+        let mut c = c;
+        let mut out = [0u64; 5];
+        c[1] += c[0] >> 51;
+        out[0] = (c[0] as u64) & LOW_51_BIT_MASK;
+        c[2] += c[1] >> 51;
+        out[1] = (c[1] as u64) & LOW_51_BIT_MASK;
+        c[3] += c[2] >> 51;
+        out[2] = (c[2] as u64) & LOW_51_BIT_MASK;
+        c[4] += c[3] >> 51;
+        out[3] = (c[3] as u64) & LOW_51_BIT_MASK;
+        let carry = (c[4] >> 51) as u64;
+        out[4] = (c[4] as u64) & LOW_51_BIT_MASK;
+        out[0] += carry * 19;
+        out[1] += out[0] >> 51;
+        out[0] &= LOW_51_BIT_MASK;
+        P25519FieldItem(out)
+    }
+
+    /// self^2, cheaper than `self * self`.
+    pub fn square(&self) -> Self {
+        let a = &self.0;
+        let m = |x: u64, y: u64| (x as u128) * (y as u128);
+        let a3_19 = 19 * a[3];
+        let a4_19 = 19 * a[4];
+        let c0 = m(a[0], a[0]) + 2 * (m(a[1], a4_19) + m(a[2], a3_19));
+        let c1 = m(a[3], a3_19) + 2 * (m(a[0], a[1]) + m(a[2], a4_19));
+        let c2 = m(a[1], a[1]) + 2 * (m(a[0], a[2]) + m(a[4], a3_19));
+        let c3 = m(a[4], a4_19) + 2 * (m(a[0], a[3]) + m(a[1], a[2]));
+        let c4 = m(a[2], a[2]) + 2 * (m(a[0], a[4]) + m(a[1], a[3]));
+        Self::reduce_wide([c0, c1, c2, c3, c4])
+    }
+
+    /// self^(2^k), k >= 1.
+    pub fn pow2k(&self, k: u32) -> Self {
+        let mut ret = self.square();
+        for _ in 1..k {
+            ret = ret.square();
+        }
+        ret
+    }
+
+    /// Returns (self^(2^250-1), self^11), shared by `inverse` and `pow22523`.
+    fn pow22501(&self) -> (Self, Self) {
+        let t0 = self.square(); // 2
+        let t1 = t0.pow2k(2); // 8
+        let t2 = *self * t1; // 9
+        let t3 = t0 * t2; // 11
+        let t4 = t3.square(); // 22
+        let t5 = t2 * t4; // 2^5 - 1
+        let t6 = t5.pow2k(5);
+        let t7 = t6 * t5; // 2^10 - 1
+        let t8 = t7.pow2k(10);
+        let t9 = t8 * t7; // 2^20 - 1
+        let t10 = t9.pow2k(20);
+        let t11 = t10 * t9; // 2^40 - 1
+        let t12 = t11.pow2k(10);
+        let t13 = t12 * t7; // 2^50 - 1
+        let t14 = t13.pow2k(50);
+        let t15 = t14 * t13; // 2^100 - 1
+        let t16 = t15.pow2k(100);
+        let t17 = t16 * t15; // 2^200 - 1
+        let t18 = t17.pow2k(50);
+        let t19 = t18 * t13; // 2^250 - 1
+        (t19, t3)
+    }
+
     /// inverse: Get multiplicational reverse.
     /// since p is a prime, there is a^(p-1) = 1
     /// therefore, we have a*a^(p-2) = 1,
     /// so, a^(p-2) is inverse and we can compute it in constant time.
     pub fn inverse(&self) -> Self {
-        let mut c = *self;
+        // p-2 = 2^255 - 21 = (2^250 - 1) * 2^5 + 11
+        let (t19, t3) = self.pow22501();
+        let t20 = t19.pow2k(5);
+        t20 * t3
+    }
 
-        for i in (0..254).rev() {
-            c = c * c;
-            if i != 2 && i != 4 {
-                c = c * *self;
-            }
-        }
-        c
+    /// self^((p-5)/8) = self^(2^252-3).
+    pub fn pow22523(&self) -> Self {
+        let (t19, _) = self.pow22501();
+        let t20 = t19.pow2k(2);
+        *self * t20
     }
 
     pub fn swap(&mut self, q: &mut Self, bit: i64) {
-        let mut t = !(bit - 1);
-        let c = t;
-        for i in 0..16 {
-            t = c & (self.0[i] ^ q.0[i]);
+        let mask = (bit as u64).wrapping_neg();
+        for i in 0..5 {
+            let t = mask & (self.0[i] ^ q.0[i]);
             self.0[i] ^= t;
             q.0[i] ^= t;
         }
     }
 
     pub fn pack(&self) -> [u8; 32] {
-        let mut carry: i64;
-        let mut m = Self([0; 16]);
-        let mut t: P25519FieldItem;
+        let mut l = self.carry_new().0;
+        // Now l < 2^255 + small, find whether l >= p by adding 19 and looking at bit 255.
+        let mut q = (l[0] + 19) >> 51;
+        q = (l[1] + q) >> 51;
+        q = (l[2] + q) >> 51;
+        q = (l[3] + q) >> 51;
+        q = (l[4] + q) >> 51;
+        // l - q*p = l + 19q - q*2^255
+        l[0] += 19 * q;
+        l[1] += l[0] >> 51;
+        l[0] &= LOW_51_BIT_MASK;
+        l[2] += l[1] >> 51;
+        l[1] &= LOW_51_BIT_MASK;
+        l[3] += l[2] >> 51;
+        l[2] &= LOW_51_BIT_MASK;
+        l[4] += l[3] >> 51;
+        l[3] &= LOW_51_BIT_MASK;
+        l[4] &= LOW_51_BIT_MASK;
+
         let mut ret = [0u8; 32];
-        t = *self;
-        t.carry();
-        t.carry();
-        t.carry();
-
-        for _ in 0..2 {
-            m.0[0] = t.0[0] - 0xffed;
-            for i in 1..15 {
-                m.0[i] = t.0[i] - 0xffff - ((m.0[i - 1] >> 16) & 1);
-                m.0[i - 1] &= 0xffff;
+        let mut acc: u128 = 0;
+        let mut acc_bits = 0;
+        let mut pos = 0;
+        for limb in l.iter() {
+            acc |= (*limb as u128) << acc_bits;
+            acc_bits += 51;
+            while acc_bits >= 8 && pos < 32 {
+                ret[pos] = acc as u8;
+                acc >>= 8;
+                acc_bits -= 8;
+                pos += 1;
             }
-
-            m.0[15] = t.0[15] - 0x7fff - ((m.0[14] >> 16) & 1);
-            carry = (m.0[15] >> 16) & 1;
-            m.0[14] &= 0xffff;
-            t.swap(&mut m, 1 - carry);
         }
-        for i in 0..16 {
-            ret[2 * i] = t.0[i] as u8;
-            ret[2 * i + 1] = (t.0[i] >> 8) as u8;
+        if pos < 32 {
+            ret[pos] = acc as u8;
         }
         ret
     }
 
     /// Get sqrt(x) over p=2^255-19
     /// Note: some x may not have a square root.
-    /// To test residue, check a^((p-1)/2)
-    /// For `p mod 8 = 5`, solution is `+- a^((p+3)/8)` .
+    /// For `p mod 8 = 5`, candidate is `r = a^((p+3)/8)`,
+    /// if r^2 = -a instead of a, then r*sqrt(-1) is the root.
     pub fn sqrt(&self) -> Option<(Self, Self)> {
-        // Check for residue.
-        // 0b11111...1110
-        let mut b: P25519FieldItem = *self;
-        let mut ans: P25519FieldItem = ONE;
-        // a^((p-1)/2)
-        for i in 0..254 {
-            if i != 0 && i != 3 {
-                ans = ans * b;
-            }
-            let b2 = b * b;
-            b = b2;
-        }
-        ans.clamp();
-        //println!("residue: {:?}", ans);
-
-        // Compare with 1.
-        if ans != 1.into() {
+        // a^((p+3)/8) = a * a^((p-5)/8)
+        let r = *self * self.pow22523();
+        let r2 = r.square();
+        let is_root = r2 == *self;
+        let is_flipped = r2 == ZERO - *self;
+
+        // Zero is kept as having no root, like the residue test would say.
+        if *self == ZERO {
             return None;
         }
 
-        let mut b: P25519FieldItem = *self;
-        let mut ans: P25519FieldItem = ONE;
-
-        // Get residue.
-        // 0b11111...0110
-        // a^((p+3)/8)
-        for i in 0..252 {
-            if i != 0 {
-                ans = ans * b;
-            }
-            b = b * b;
-        }
-        ans.carry();
-
-        // Check a^((p-1)/4)
-        let mut b: P25519FieldItem = *self;
-        let mut ans2: P25519FieldItem = ONE;
-        for i in 0..253 {
-            if i != 2 {
-                ans2 = ans2 * b;
-            }
-            b = b * b;
-        }
-        ans2.clamp();
-
-        // If is quadratic nonresidue, then multiply 2^((a-1)/4)
-        // Sorry, but this is data dependent.
-        let mut b = TWO;
-        let mut ans3 = ans;
-        for i in 0..253 {
-            if i != 2 {
-                ans3 = ans3 * b;
-            }
-            b = b * b;
-        }
-        ans3.carry();
-
         // Choice answer.
-        if ans2 == ONE {
-            ans = ans;
+        let ans = if is_root {
+            r
+        } else if is_flipped {
+            ZERO - r * SQRT_M1
         } else {
-            ans = ITEM25519 - ans3;
-        }
+            return None;
+        };
 
-        let ans_alt = ITEM25519 - ans;
+        let ans_alt = ZERO - ans;
 
         // Get another residue.
         Some((ans, ans_alt))
@@ -178,17 +251,45 @@ impl P25519FieldItem {
 
     /// Clamp self into range of [0,2^255-19).
     pub fn clamp(&mut self) {
-        let ret1 = *self - ITEM25519;
-        let ret2 = *self - ITEM25519_2;
-        let gt_2 = *self > ITEM25519_2;
-        let gt_1 = *self > ITEM25519;
-        let ret: P25519FieldItem = match (gt_2, gt_1) {
-            (true, _) => ret2,
-            (false, true) => ret1,
-            (false, false) => *self,
-        };
-        let ret = ret.carry_new();
-        *self = ret;
+        *self = self.pack().into();
+    }
+
+    /// A value is "negative" if its canonical encoding is odd.
+    pub fn is_negative(&self) -> bool {
+        self.pack()[0] & 1 == 1
+    }
+
+    /// Negate if bit is 1, in constant time.
+    pub fn conditional_negate(&mut self, bit: i64) {
+        let mut neg = -*self;
+        self.swap(&mut neg, bit);
+    }
+
+    /// The non-negative one of self and -self.
+    pub fn abs(&self) -> Self {
+        let mut ret = *self;
+        ret.conditional_negate(self.is_negative() as i64);
+        ret
+    }
+
+    /// Get sqrt(u/v) without inversion.
+    ///
+    /// Returns (true, sqrt(u/v)) if u/v is square, (false, sqrt(i*u/v)) otherwise,
+    /// the root is always non-negative. For u = 0, (true, 0) is returned.
+    pub fn sqrt_ratio_m1(u: &Self, v: &Self) -> (bool, Self) {
+        let v3 = v.square() * *v;
+        let v7 = v3.square() * *v;
+        let mut r = (*u * v3) * (*u * v7).pow22523();
+        let check = *v * r.square();
+
+        let u_neg = -*u;
+        let correct_sign = check == *u;
+        let flipped_sign = check == u_neg;
+        let flipped_sign_i = check == u_neg * SQRT_M1;
+
+        let mut r_prime = r * SQRT_M1;
+        r.swap(&mut r_prime, (flipped_sign | flipped_sign_i) as i64);
+        (correct_sign | flipped_sign, r.abs())
     }
 }
 
@@ -205,11 +306,17 @@ impl From<P25519FieldItem> for [u8; 32] {
 }
 
 impl From<[u8; 32]> for P25519FieldItem {
+    /// All 256 bits are taken, bit 255 is folded back as 19 (2^255 = 19 mod p).
     fn from(input: [u8; 32]) -> Self {
-        let mut ret = Self([0; 16]);
-        for i in 0..16 {
-            ret.0[i] = input[2 * i] as i64 + ((input[2 * i + 1] as i64) << 8) as i64
-        }
+        let load8 = |b: &[u8]| u64::from_le_bytes(b[..8].try_into().unwrap());
+        let mut ret = Self([
+            load8(&input[0..]) & LOW_51_BIT_MASK,
+            (load8(&input[6..]) >> 3) & LOW_51_BIT_MASK,
+            (load8(&input[12..]) >> 6) & LOW_51_BIT_MASK,
+            (load8(&input[19..]) >> 1) & LOW_51_BIT_MASK,
+            (load8(&input[24..]) >> 12) & LOW_51_BIT_MASK,
+        ]);
+        ret.0[0] += 19 * ((input[31] >> 7) as u64);
         ret
     }
 }
@@ -217,82 +324,83 @@ impl From<[u8; 32]> for P25519FieldItem {
 impl Add for P25519FieldItem {
     type Output = Self;
     fn add(self, rhs: Self) -> Self::Output {
-        let mut ret = [0; 16];
-        for i in 0..16 {
-            ret[i] = self.0[i] + rhs.0[i];
-        }
-        P25519FieldItem(ret)
+        let ret = core::array::from_fn(|i| self.0[i] + rhs.0[i]);
+        P25519FieldItem(ret).carry_new()
     }
 }
 
 impl Sub for P25519FieldItem {
     type Output = Self;
     fn sub(self, rhs: Self) -> Self::Output {
-        let mut ret = [0; 16];
-        for i in 0..16 {
-            ret[i] = self.0[i] - rhs.0[i];
-        }
-        P25519FieldItem(ret)
+        // Inputs may be slightly above 2^51 per limb, 16p keeps everything positive.
+        let lhs = self.carry_new();
+        let rhs = rhs.carry_new();
+        let ret = core::array::from_fn(|i| (lhs.0[i] + ITEM25519_16.0[i]) - rhs.0[i]);
+        P25519FieldItem(ret).carry_new()
     }
 }
 
 impl Mul for P25519FieldItem {
     type Output = Self;
     fn mul(self, rhs: Self) -> Self::Output {
-        let mut product = [0i64; 31];
-        let mut ret = [0i64; 16];
-
-        for i in 0..16 {
-            for j in 0..16 {
-                product[i + j] += self.0[i] * rhs.0[j];
-            }
-        }
-
-        for i in 0..15 {
-            product[i] += 38 * product[i + 16];
-        }
-
-        for i in 0..16 {
-            ret[i] = product[i];
-        }
+        let a = &self.0;
+        let b = &rhs.0;
+        let m = |x: u64, y: u64| (x as u128) * (y as u128);
+        // Limbs above 2^255 wrap around with factor 19.
+        let b1_19 = 19 * b[1];
+        let b2_19 = 19 * b[2];
+        let b3_19 = 19 * b[3];
+        let b4_19 = 19 * b[4];
+
+        let c0 = m(a[0], b[0]) + m(a[4], b1_19) + m(a[3], b2_19) + m(a[2], b3_19) + m(a[1], b4_19);
+        let c1 = m(a[1], b[0]) + m(a[0], b[1]) + m(a[4], b2_19) + m(a[3], b3_19) + m(a[2], b4_19);
+        let c2 = m(a[2], b[0]) + m(a[1], b[1]) + m(a[0], b[2]) + m(a[4], b3_19) + m(a[3], b4_19);
+        let c3 = m(a[3], b[0]) + m(a[2], b[1]) + m(a[1], b[2]) + m(a[0], b[3]) + m(a[4], b4_19);
+        let c4 = m(a[4], b[0]) + m(a[3], b[1]) + m(a[2], b[2]) + m(a[1], b[3]) + m(a[0], b[4]);
+
+        Self::reduce_wide([c0, c1, c2, c3, c4])
+    }
+}
 
-        let mut ret = Self(ret);
-        ret.carry();
-        ret.carry();
-        ret.carry();
-        ret
+impl Neg for P25519FieldItem {
+    type Output = Self;
+    fn neg(self) -> Self::Output {
+        ZERO - self
     }
 }
 
 impl From<i32> for P25519FieldItem {
     fn from(t: i32) -> Self {
-        let mut arr = [0 as i64; 16];
-        arr[0] = t as i64;
-        P25519FieldItem(arr)
+        let mut arr = [0u64; 5];
+        arr[0] = t.unsigned_abs() as u64;
+        if t < 0 {
+            ZERO - P25519FieldItem(arr)
+        } else {
+            P25519FieldItem(arr)
+        }
+    }
+}
+
+impl PartialEq for P25519FieldItem {
+    /// Values are compared, not limbs.
+    fn eq(&self, other: &Self) -> bool {
+        let a = self.pack();
+        let b = other.pack();
+        let mut diff = 0u8;
+        for i in 0..32 {
+            diff |= a[i] ^ b[i];
+        }
+        diff == 0
     }
 }
 
+impl Eq for P25519FieldItem {}
+
 impl PartialOrd for P25519FieldItem {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let mut equal = false;
-        let mut greater = false;
-        let mut smaller = false;
-        if self == other {
-            equal = true;
-        }
-        for i in (0..16).rev() {
-            if (self.0[i] > other.0[i]) & !smaller {
-                greater = true;
-            }
-            if self.0[i] < other.0[i] {
-                smaller = true;
-            }
-        }
-        match (equal, greater, smaller) {
-            (true, _, _) => Some(Ordering::Equal),
-            (false, true, _) => Some(Ordering::Greater),
-            (false, false, _) => Some(Ordering::Less),
-        }
+        let a = self.pack();
+        let b = other.pack();
+        Some(a.iter().rev().cmp(b.iter().rev()))
     }
 }
 
@@ -333,3 +441,35 @@ fn inv25519() {
         assert_eq!(c.pack(), ONE.pack());
     }
 }
+
+#[test]
+fn pack25519() {
+    // p itself and p+1 must come back reduced.
+    let mut p = [0xffu8; 32];
+    p[0] = 0xed;
+    p[31] = 0x7f;
+    assert_eq!(P25519FieldItem::from(p).pack(), [0u8; 32]);
+    p[0] = 0xee;
+    assert_eq!(P25519FieldItem::from(p).pack(), ONE.pack());
+    // Bit 255 is 2^255 = 19.
+    let mut top = [0u8; 32];
+    top[31] = 0x80;
+    assert_eq!(P25519FieldItem::from(top).pack()[0], 19);
+
+    let mut rng = ChaCha20Rng::from_entropy();
+    let mut a = [0u8; 32];
+    for _ in 0..10000 {
+        rng.fill_bytes(&mut a);
+        a[31] &= 0x7f;
+        let b: P25519FieldItem = a.into();
+        if a[31] != 0x7f {
+            assert_eq!(b.pack(), a);
+        }
+        assert_eq!(b.square(), b * b);
+        assert_eq!((ZERO - b) + b, ZERO);
+        assert_eq!(
+            (b - ONE).pack().encode_hex::<String>(),
+            (b + (ZERO - ONE)).pack().encode_hex::<String>()
+        );
+    }
+}
@@ -1,45 +1,112 @@
-use num_bigint::BigUint;
-use std::ops::{Add, Mul};
+use crate::mp::{Barrett, LargeInt};
+use crate::sha512::SHA512;
+use rand::prelude::*;
+use rand_chacha::ChaCha20Rng;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::sync::OnceLock;
 
 // Arithmetric over n (order of G).
-const N: [u32; 8] = [
-    0x5cf5d3ed, 0x5812631a, 0xa2f79cd6, 0x14def9de, 0x00000000, 0x00000000, 0x00000000, 0x10000000,
-];
+// n = 2^252 + 27742317777372353535851937790883648493
+// All values are kept reduced, and every operation runs in constant time.
+pub const N: LargeInt<32> = LargeInt {
+    data: [
+        0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde,
+        0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x10,
+    ],
+};
+/// n - 2, exponent for inversion.
+const N_MINUS_2: LargeInt<32> = LargeInt {
+    data: [
+        0xeb, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde,
+        0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x10,
+    ],
+};
 
-#[derive(Clone)]
-pub struct ModNItem(BigUint);
+fn barrett() -> &'static Barrett<32> {
+    static BARRETT: OnceLock<Barrett<32>> = OnceLock::new();
+    BARRETT.get_or_init(|| Barrett::new(&N))
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ModNItem(LargeInt<32>);
+
+pub const SCALAR_ZERO: ModNItem = ModNItem(LargeInt { data: [0; 32] });
+pub const SCALAR_ONE: ModNItem = ModNItem(LargeInt {
+    data: [
+        1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0,
+    ],
+});
 
 impl ModNItem {
+    /// Any 256-bit number, reduced mod n.
     pub fn from_bytes(a: [u8; 32]) -> Self {
-        Self(BigUint::from_bytes_le(&a[..]))
+        Self(barrett().reduce(&LargeInt { data: a }))
+    }
+
+    /// A 512-bit number (e.g. a SHA-512 output), reduced mod n.
+    /// The result is close to uniform when the input is.
+    pub fn from_bytes_wide(a: &[u8; 64]) -> Self {
+        let mut lo = LargeInt::<32>::default();
+        let mut hi = LargeInt::<32>::default();
+        lo.data.copy_from_slice(&a[..32]);
+        hi.data.copy_from_slice(&a[32..]);
+        Self(barrett().reduce_wide(&lo, &hi))
+    }
+
+    /// Only accept the canonical encoding, i.e. a number below n.
+    pub fn from_canonical_bytes(a: [u8; 32]) -> Option<Self> {
+        if Self::is_canonical(&a) {
+            Some(Self(LargeInt { data: a }))
+        } else {
+            None
+        }
+    }
+
+    pub fn is_canonical(a: &[u8; 32]) -> bool {
+        LargeInt { data: *a }.ct_lt(&N) == 1
     }
 
     pub fn to_bytes(&self) -> [u8; 32] {
-        let mut ret = [0u8; 32];
-        let bytes = self.0.to_bytes_le();
-        for i in 0..(bytes.len()) {
-            ret[i] = bytes[i];
+        self.0.data
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    /// H(parts[0] || parts[1] || ...) with SHA-512, reduced mod n.
+    pub fn from_hash(parts: &[&[u8]]) -> Self {
+        let mut data = vec![];
+        for part in parts {
+            data.extend_from_slice(part);
         }
-        ret
+        Self::from_bytes_wide(&SHA512::do_hash(&data))
     }
-}
 
-impl Add<ModNItem> for ModNItem {
-    type Output = ModNItem;
-    fn add(self, rhs: ModNItem) -> Self::Output {
-        let n = BigUint::from_slice(&N[..]);
-        ModNItem((self.0 + rhs.0) % n)
+    /// Uniformly random scalar.
+    pub fn random() -> Self {
+        let mut bytes = [0u8; 64];
+        ChaCha20Rng::from_entropy().fill_bytes(&mut bytes);
+        Self::from_bytes_wide(&bytes)
+    }
+
+    /// Multiplicative inverse, by n - 2 power. Zero goes to zero.
+    pub fn inverse(&self) -> Self {
+        Self(barrett().pow(&self.0, &N_MINUS_2))
     }
 }
 
-impl Mul<ModNItem> for ModNItem {
-    type Output = ModNItem;
-    fn mul(self, rhs: ModNItem) -> Self::Output {
-        let n = BigUint::from_slice(&N[..]);
-        ModNItem((self.0 * rhs.0) % n)
+impl PartialEq for ModNItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
     }
 }
 
+impl Eq for ModNItem {}
+
 impl From<[u8; 32]> for ModNItem {
     fn from(t: [u8; 32]) -> Self {
         Self::from_bytes(t)
@@ -51,3 +118,70 @@ impl From<ModNItem> for [u8; 32] {
         t.to_bytes()
     }
 }
+
+impl From<u64> for ModNItem {
+    fn from(t: u64) -> Self {
+        Self(LargeInt::from(t))
+    }
+}
+
+impl Add<ModNItem> for ModNItem {
+    type Output = ModNItem;
+    fn add(self, rhs: ModNItem) -> Self::Output {
+        ModNItem(self.0.add_mod(&rhs.0, &N))
+    }
+}
+
+impl Sub<ModNItem> for ModNItem {
+    type Output = ModNItem;
+    fn sub(self, rhs: ModNItem) -> Self::Output {
+        ModNItem(self.0.sub_mod(&rhs.0, &N))
+    }
+}
+
+impl Neg for ModNItem {
+    type Output = ModNItem;
+    fn neg(self) -> Self::Output {
+        SCALAR_ZERO - self
+    }
+}
+
+impl Mul<ModNItem> for ModNItem {
+    type Output = ModNItem;
+    fn mul(self, rhs: ModNItem) -> Self::Output {
+        ModNItem(barrett().mul(&self.0, &rhs.0))
+    }
+}
+
+#[test]
+fn test_mod_n() {
+    use super::ec25519::G;
+    use hex::ToHex;
+
+    let mut rng = ChaCha20Rng::from_entropy();
+    // n itself reduces to zero, and is not canonical.
+    assert!(ModNItem::from_bytes(N.data).is_zero());
+    assert!(ModNItem::from_canonical_bytes(N.data).is_none());
+    assert!(ModNItem::from_canonical_bytes(N_MINUS_2.data).is_some());
+    for _ in 0..10 {
+        let mut a = [0u8; 32];
+        let mut b = [0u8; 64];
+        rng.fill_bytes(&mut a);
+        rng.fill_bytes(&mut b);
+        let a = ModNItem::from_bytes(a);
+        let b = ModNItem::from_bytes_wide(&b);
+        assert_eq!(a * a.inverse(), SCALAR_ONE);
+        assert_eq!(a + (-a), SCALAR_ZERO);
+        assert_eq!(a - b + b, a);
+        // Scalars act on G the same way as before reduction.
+        let left = (a * b + a).to_bytes() * G;
+        let right = (b.to_bytes() * G) * a.to_bytes() + a.to_bytes() * G;
+        assert!(left == right);
+    }
+    // 2^512 - 1 mod n
+    let wide = ModNItem::from_bytes_wide(&[0xff; 64]);
+    assert_eq!(
+        wide.to_bytes().encode_hex::<String>(),
+        "000f9c44e31106a447938568a71b0ed065bef517d273ecce3d9a307c1b419903"
+    );
+}
@@ -0,0 +1,160 @@
+// blind.rs - Clause blind Schnorr signatures.
+// See: Fuchsbauer, Plouviez, Seurin - "Blind Schnorr Signatures and Signed ElGamal
+// Encryption in the Algebraic Group Model", section 5.
+//
+// The server opens two signing sessions, the client blinds both, and the server
+// finishes only one of them, chosen at random. This defeats the ROS attack on
+// plain blind Schnorr, where concurrent sessions let a client forge extra signatures.
+//
+// The result is a Sr25519 signature under the context given to `blind`, checked with
+// `Sr25519::verify_with_context`. The server cannot see, let alone enforce, that context:
+// a blind signing key must never sign anything else.
+// The server sees neither the message nor the final (R, s), so a redeemed token
+// cannot be linked to the session that issued it.
+//
+// Server: `commit`, sends the `Commitment`.
+// Client: `blind`, sends the `BlindedChallenges`.
+// Server: `sign`, sends the `Response`.
+// Client: `unblind`, keeps the signature.
+use super::arith_n::ModNItem;
+use super::ec25519::{ECCError, EdwardsPoint, G};
+use super::eddsa::Sr25519;
+use rand::prelude::*;
+use rand_chacha::ChaCha20Rng;
+
+/// Server nonces of one issuance.
+///
+/// It is consumed by `sign`: answering twice with one state leaks the key.
+pub struct SignerState {
+    r: [ModNItem; 2],
+}
+
+/// (R_0, R_1), sent to the client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Commitment(pub [u8; 32], pub [u8; 32]);
+
+/// (c_0, c_1), sent to the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlindedChallenges(pub [u8; 32], pub [u8; 32]);
+
+/// The session the server finished, and its s_b = r_b + c_b*x.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub b: u8,
+    pub s: [u8; 32],
+}
+
+/// Client secrets of both sessions.
+pub struct BlindingState {
+    pubkey: EdwardsPoint,
+    commitment: [EdwardsPoint; 2],
+    challenges: [ModNItem; 2],
+    alpha: [ModNItem; 2],
+    /// Blinded R' = R + alpha*G + beta*X.
+    r: [[u8; 32]; 2],
+}
+
+pub fn commit() -> (SignerState, Commitment) {
+    let r = [ModNItem::random(), ModNItem::random()];
+    let commitment = Commitment(
+        (r[0].to_bytes() * G).encode_point(),
+        (r[1].to_bytes() * G).encode_point(),
+    );
+    (SignerState { r }, commitment)
+}
+
+/// Blind both sessions for `msg` under `context`: c = H(context, msg, R', X) + beta.
+pub fn blind(
+    pubkey: [u8; 32],
+    commitment: &Commitment,
+    context: &[u8],
+    msg: &[u8],
+) -> Result<(BlindingState, BlindedChallenges), ECCError> {
+    let x = EdwardsPoint::recover_point(pubkey).ok_or(ECCError::InvalidPoint)?;
+    let pubkey = x.encode_point();
+    let mut commitment_points = [x; 2];
+    let mut challenges = [ModNItem::from(0); 2];
+    let mut alpha = [ModNItem::from(0); 2];
+    let mut blinded = [[0u8; 32]; 2];
+    for (i, r) in [commitment.0, commitment.1].iter().enumerate() {
+        let r = EdwardsPoint::recover_point(*r).ok_or(ECCError::InvalidPoint)?;
+        let a = ModNItem::random();
+        let b = ModNItem::random();
+        let r_blind = (r + a.to_bytes() * G + b.to_bytes() * x).encode_point();
+        let c: ModNItem = Sr25519::challenge(Some(context), msg, &r_blind, &pubkey).into();
+        commitment_points[i] = r;
+        challenges[i] = c + b;
+        alpha[i] = a;
+        blinded[i] = r_blind;
+    }
+    let out = BlindedChallenges(challenges[0].to_bytes(), challenges[1].to_bytes());
+    Ok((
+        BlindingState {
+            pubkey: x,
+            commitment: commitment_points,
+            challenges,
+            alpha,
+            r: blinded,
+        },
+        out,
+    ))
+}
+
+/// Finish one of the two sessions, chosen at random.
+pub fn sign(state: SignerState, key: [u8; 32], challenges: &BlindedChallenges) -> Response {
+    let b = ChaCha20Rng::from_entropy().gen_range(0..2u8);
+    let c = ModNItem::from_bytes(if b == 0 { challenges.0 } else { challenges.1 });
+    let x: ModNItem = key.into();
+    let s = state.r[b as usize] + c * x;
+    Response { b, s: s.to_bytes() }
+}
+
+/// Check the response and turn it into a signature (R', s + alpha) on the message.
+pub fn unblind(
+    state: BlindingState,
+    response: &Response,
+) -> Result<([u8; 32], [u8; 32]), ECCError> {
+    if response.b > 1 {
+        return Err(ECCError::InvalidShare);
+    }
+    let b = response.b as usize;
+    let s = ModNItem::from_canonical_bytes(response.s).ok_or(ECCError::InvalidShare)?;
+    let left = s.to_bytes() * G;
+    let right = state.commitment[b] + state.challenges[b].to_bytes() * state.pubkey;
+    if left != right {
+        return Err(ECCError::InvalidShare);
+    }
+    Ok((state.r[b], (s + state.alpha[b]).to_bytes()))
+}
+
+#[test]
+fn test_blind_schnorr() {
+    const CONTEXT: &[u8] = b"tunnel access token";
+    let mut key = [0u8; 32];
+    ChaCha20Rng::from_entropy().fill_bytes(&mut key);
+    let pubkey = EdwardsPoint::get_pubkey(key);
+    let token = "tunnel access token 42".as_bytes();
+
+    for _ in 0..4 {
+        let (signer, commitment) = commit();
+        let (client, challenges) = blind(pubkey, &commitment, CONTEXT, token).unwrap();
+        let response = sign(signer, key, &challenges);
+        let (r, s) = unblind(client, &response).unwrap();
+        assert!(Sr25519::verify_with_context(pubkey, CONTEXT, token, r, s).unwrap());
+        assert!(!Sr25519::verify_with_context(pubkey, CONTEXT, b"another token", r, s).unwrap());
+        assert!(!Sr25519::verify(pubkey, token, r, s).unwrap());
+        // The server never saw the final R or challenge.
+        assert!(r != commitment.0 && r != commitment.1);
+        assert!(s != response.s);
+    }
+
+    // A wrong response is rejected by the client.
+    let (signer, commitment) = commit();
+    let (client, challenges) = blind(pubkey, &commitment, CONTEXT, token).unwrap();
+    let mut response = sign(signer, key, &challenges);
+    response.b ^= 1;
+    assert!(matches!(
+        unblind(client, &response),
+        Err(ECCError::InvalidShare)
+    ));
+}
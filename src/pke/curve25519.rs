@@ -7,7 +7,8 @@ use serde::{Deserialize, Serialize};
 // A = 0x0000000000000000000000000000000000000000000000000000000000076d06
 // contains 8*q elements.
 use crate::mp::LargeInt;
-use crate::pke::arith::P25519FieldItem;
+use crate::pke::arith::{P25519FieldItem, ONE, ZERO};
+use crate::pke::ec25519::{EdwardsPoint, E};
 use std::ops::{Add, Mul};
 
 /// This struct describes point on curve25519.
@@ -25,6 +26,33 @@ impl MontgomeryCurvePoint {
         self.z = 1.into();
     }
 
+    /// Map onto ec25519: y = (u - 1) / (u + 1), x is picked by `sign`,
+    /// the parity of x on edwards25519, as the sign bit of an Ed25519 key does.
+    /// Only u is used, so this works on bare X25519 keys.
+    /// `None` for u = -1, which has no point on the Edwards curve.
+    pub fn to_edwards(&self, sign: u8) -> Option<EdwardsPoint> {
+        if self.z == ZERO {
+            return Some(E);
+        }
+        EdwardsPoint::from_ed25519_y(self.edwards_y()?, sign)
+    }
+
+    /// Same as `to_edwards`, but `sign` is the parity of x on ec25519.
+    pub fn to_edwards_legacy(&self, sign: u8) -> Option<EdwardsPoint> {
+        if self.z == ZERO {
+            return Some(E);
+        }
+        EdwardsPoint::from_y(self.edwards_y()?, sign)
+    }
+
+    fn edwards_y(&self) -> Option<P25519FieldItem> {
+        let u = self.x * self.z.inverse();
+        if u + ONE == ZERO {
+            return None;
+        }
+        Some((u - ONE) * (u + ONE).inverse())
+    }
+
     pub fn scalar_mul(p: [u8; 32], scalar: [u8; 32]) -> [u8; 32] {
         let mut clamped;
         let mut bit = 0i64;
@@ -37,7 +65,7 @@ impl MontgomeryCurvePoint {
         let mut x: P25519FieldItem = p.into();
         clamped = scalar;
         clamped[0] &= 0xf8;
-        clamped[31] = (clamped[32] & 0x7f) | 0x40;
+        clamped[31] = (clamped[31] & 0x7f) | 0x40;
 
         a.0[0] = 1;
         d.0[0] = 1;
@@ -86,3 +114,55 @@ impl Mul<LargeInt<32>> for MontgomeryCurvePoint {
         todo!()
     }
 }
+
+#[test]
+fn test_edwards_map() {
+    use crate::pke::ec25519::G;
+    use hex::{FromHex, ToHex};
+
+    // RFC 7748 base point.
+    let mut b = G.to_montgomery();
+    b.normalize();
+    assert_eq!(b.x, P25519FieldItem::from(9));
+    let v: [u8; 32] =
+        <[u8; 32]>::from_hex("d9d3ce7ea2c5e929b2617c6d7e4d3d924cd148772cdd1ee0b486a0b8a119ae20")
+            .unwrap();
+    // 14781619447589544791020593568409986887264606134616475288964881837755586237401
+    assert_eq!(b.y.pack(), v);
+    assert!(b.to_edwards(0).unwrap() == G);
+    let sign = G.to_affine().0.is_negative() as u8;
+    assert!(b.to_edwards_legacy(sign).unwrap() == G);
+    assert!(b.to_edwards_legacy(sign ^ 1).unwrap() == -G);
+
+    // Ed25519 key of RFC 8032 test 1 and its X25519 key.
+    let pk =
+        <[u8; 32]>::from_hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a")
+            .unwrap();
+    let p = EdwardsPoint::from_ed25519_bytes(pk).unwrap();
+    let mut m = p.to_montgomery();
+    m.normalize();
+    assert_eq!(
+        m.x.pack().encode_hex::<String>(),
+        "d85e07ec22b0ad881537c2f44d662d1a143cf830c57aca4305d85c7a90f6b62e"
+    );
+    assert_eq!(
+        m.y.pack().encode_hex::<String>(),
+        "e58dca2a373d2c9b992977af37dbb3528e9c849634e5b27fe0fe68d4333f3753"
+    );
+    assert_eq!(m.to_edwards(pk[31] >> 7).unwrap().to_ed25519_bytes(), pk);
+
+    // Neutral element, the point of order 2 and u = -1.
+    let e = E.to_montgomery();
+    assert!(e.z == ZERO);
+    assert!(e.to_edwards(0).unwrap() == E);
+    let t = EdwardsPoint::from_affine(ZERO, -ONE);
+    let mt = t.to_montgomery();
+    assert!(mt.x == ZERO && mt.y == ZERO);
+    assert!(mt.to_edwards(0).unwrap() == t);
+    let bad = MontgomeryCurvePoint {
+        x: -ONE,
+        y: ZERO,
+        z: ONE,
+    };
+    assert!(bad.to_edwards(0).is_none());
+}
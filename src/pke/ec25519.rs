@@ -11,15 +11,18 @@ use thiserror::Error;
 // 486664x^2 + y^2 = 1 + 486660x^2*y^2 mod (2^255-19)
 // p = 0x7fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffed
 
-// Though ec25519 and curve25519 can be mapped,
-// this conversion will not be used as one key can be safely used on only one algorithm.
+// ec25519, edwards25519 and curve25519 are birationally equivalent:
+//   edwards25519: x' = x * sqrt(-486664), y' = y
+//   curve25519:   u = (1 + y) / (1 - y), v = u / x
+// Keys should still be used on only one algorithm; the maps are for converting
+// identity keys and for checking points across representations.
 use crate::pke::arith::P25519FieldItem;
+use crate::pke::arith_n::N;
+use crate::pke::curve25519::MontgomeryCurvePoint;
 use core::panic;
-use std::ops::{Add, Mul};
-pub const A: P25519FieldItem =
-    P25519FieldItem([27912, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-pub const D: P25519FieldItem =
-    P25519FieldItem([27908, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+use std::ops::{Add, Mul, Neg, Sub};
+pub const A: P25519FieldItem = P25519FieldItem([486664, 0, 0, 0, 0]);
+pub const D: P25519FieldItem = P25519FieldItem([486660, 0, 0, 0, 0]);
 pub const EIGHT: [u8; 32] = [
     8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
 ];
@@ -27,19 +30,53 @@ pub const EIGHT: [u8; 32] = [
 /// see: https://fastd.readthedocs.io/en/stable/crypto/ec25519.html
 pub const G: EdwardsPoint = EdwardsPoint {
     x: P25519FieldItem([
-        0x6bd4, 0x7ffe, 0xfa39, 0x228c, 0x96e1, 0xeb23, 0xb726, 0x6a8e, 0x7434, 0x668b, 0xa3d6,
-        0xdd26, 0x5e19, 0x219f, 0x4350, 0x547c,
+        0x4fa397ffe6bd4,
+        0x4dd6472dc2451,
+        0x22dd0d1aa3adc,
+        0x70cee9351eb33,
+        0x547c4350219f5,
     ]),
     y: P25519FieldItem([
-        0x6658, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666,
-        0x6666, 0x6666, 0x6666, 0x6666, 0x6666,
+        0x6666666666658,
+        0x4cccccccccccc,
+        0x1999999999999,
+        0x3333333333333,
+        0x6666666666666,
+    ]),
+    z: ONE,
+    t: P25519FieldItem([
+        0x261c799985647,
+        0x3e4505be35041,
+        0x024a70e21c8b0,
+        0x5a3f20f74bc29,
+        0x29fd02a6814c4,
     ]),
 };
 
+/// x on edwards25519 (-x^2 + y^2 = 1 + d'x^2*y^2) is x on ec25519 times sqrt(-486664),
+/// y is the same. The root is the one that maps G onto the edwards25519 base point.
+pub const ED25519_X_SCALE: P25519FieldItem = P25519FieldItem([
+    0x1fb5500ba81e7,
+    0x5d6905cafa672,
+    0x00ec204e978b0,
+    0x4a216c27b91fe,
+    0x70d9120b9f5ff,
+]);
+/// 1/sqrt(-486664), see `ED25519_X_SCALE`.
+pub const ED25519_X_SCALE_INV: P25519FieldItem = P25519FieldItem([
+    0x404afdb4268e9,
+    0x73e487ab42816,
+    0x4145c04a0c4e3,
+    0x5d4fc30c0cdb3,
+    0x244b67206a3e5,
+]);
+
 /// Constant 'zero' E
 pub const E: EdwardsPoint = EdwardsPoint {
-    x: P25519FieldItem([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
-    y: P25519FieldItem([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+    x: ZERO,
+    y: ONE,
+    z: ONE,
+    t: ZERO,
 };
 
 #[derive(Error, Debug)]
@@ -50,22 +87,84 @@ pub enum ECCError {
     NoExchange,
     #[error("attempt to attack - point over small subgroup.")]
     SmallOrderAttack,
+    #[error("signer is not part of the group.")]
+    UnknownSigner,
+    #[error("invalid signature share.")]
+    InvalidShare,
+    #[error("invalid threshold parameters.")]
+    InvalidParameter,
+    #[error("proof does not verify.")]
+    InvalidProof,
 }
 
-/// This struct describes point on curve25519.
+/// This struct describes point on ec25519.
+///
+/// Points are kept in extended coordinates (X:Y:Z:T), where
+/// x = X/Z, y = Y/Z and x*y = T/Z, so no inversion is needed for arithmetic.
+/// Affine coordinates are only recovered by `pack` and `encode_point`.
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct EdwardsPoint {
     pub x: P25519FieldItem,
     pub y: P25519FieldItem,
+    pub z: P25519FieldItem,
+    pub t: P25519FieldItem,
 }
 
 /// We need aP + bQ, only aP does not work.
 /// curve25519 is optimized for variable base and scale, but work terrible for addition.
 impl EdwardsPoint {
+    pub fn from_affine(x: P25519FieldItem, y: P25519FieldItem) -> Self {
+        Self {
+            x,
+            y,
+            z: ONE,
+            t: x * y,
+        }
+    }
+
+    /// Get affine (x, y).
+    pub fn to_affine(&self) -> (P25519FieldItem, P25519FieldItem) {
+        let z_inv = self.z.inverse();
+        (self.x * z_inv, self.y * z_inv)
+    }
+
+    /// Pack affine (x, y).
     pub fn pack(&self) -> ([u8; 32], [u8; 32]) {
-        let ret_x = self.x.pack();
-        let ret_y = self.y.pack();
-        (ret_x, ret_y)
+        let (x, y) = self.to_affine();
+        (x.pack(), y.pack())
+    }
+
+    /// Bring Z to 1, so the point can be packed without inversion.
+    pub fn normalize(&mut self) {
+        let (x, y) = self.to_affine();
+        *self = Self::from_affine(x, y);
+    }
+
+    /// Normalize many points with a single inversion (Montgomery's trick).
+    pub fn batch_normalize(points: &mut [Self]) {
+        if points.is_empty() {
+            return;
+        }
+        // prefix[i] = z_0 * ... * z_i
+        let mut prefix = Vec::with_capacity(points.len());
+        let mut acc = ONE;
+        for p in points.iter() {
+            acc = acc * p.z;
+            prefix.push(acc);
+        }
+        let mut acc_inv = acc.inverse();
+        for i in (0..points.len()).rev() {
+            // acc_inv = (z_0 * ... * z_i)^-1 here.
+            let z_inv = if i > 0 {
+                acc_inv * prefix[i - 1]
+            } else {
+                acc_inv
+            };
+            acc_inv = acc_inv * points[i].z;
+            let x = points[i].x * z_inv;
+            let y = points[i].y * z_inv;
+            points[i] = Self::from_affine(x, y);
+        }
     }
 
     /// computes aP+bQ .
@@ -73,6 +172,41 @@ impl EdwardsPoint {
         a * p + b * q
     }
 
+    /// computes sum(scalars[i] * points[i]) with Pippenger's bucket method.
+    ///
+    /// WARNING: variable time, only for public scalars and points (e.g. verification).
+    pub fn vartime_multiscalar_mul(scalars: &[[u8; 32]], points: &[Self]) -> Self {
+        assert_eq!(scalars.len(), points.len());
+        // Window width, about ln(n) bits.
+        let c = match points.len() {
+            0..=7 => 3,
+            8..=31 => 4,
+            n => ((n as f64).ln() as usize + 1).min(16),
+        };
+        let mut ret = E;
+        for w in (0..256usize.div_ceil(c)).rev() {
+            for _ in 0..c {
+                ret = ret.double();
+            }
+            let mut buckets = vec![E; (1 << c) - 1];
+            for (k, p) in scalars.iter().zip(points.iter()) {
+                let digit = window_digit(k, w * c, c);
+                if digit > 0 {
+                    buckets[digit - 1] = buckets[digit - 1] + *p;
+                }
+            }
+            // sum(d * buckets[d - 1]) by running sums.
+            let mut running = E;
+            let mut sum = E;
+            for b in buckets.iter().rev() {
+                running = running + *b;
+                sum = sum + running;
+            }
+            ret = ret + sum;
+        }
+        ret
+    }
+
     pub fn recover_point(point: [u8; 32]) -> Option<Self> {
         // y^2 = sqrt((1-ax^2)/(1-dx^2))
         // which y depends on sign bit.
@@ -87,21 +221,20 @@ impl EdwardsPoint {
         let c = a * (b.inverse());
         let y = c.sqrt();
         match (y, sign) {
-            (Some(k), true) => Some(Self { x: x, y: k.1 }),
-            (Some(k), false) => Some(Self { x: x, y: k.0 }),
+            (Some(k), true) => Some(Self::from_affine(x, k.1)),
+            (Some(k), false) => Some(Self::from_affine(x, k.0)),
             (None, _) => None,
         }
     }
 
     pub fn encode_point(&self) -> [u8; 32] {
-        let mut ret: [u8; 32] = self.x.into();
-        let mut x = self.x;
+        let (x, y) = self.to_affine();
+        let mut ret: [u8; 32] = x.into();
         ret[31] &= 0x7f;
         let a = A * x * x - ONE;
         let b = D * x * x - ONE;
         let c = a * (b.inverse());
         let y_sqrt = c.sqrt();
-        let y = self.y;
         if let Some((y1, y2)) = y_sqrt {
             if y.pack() == y1.pack() {
                 ret[31] &= 0x7f;
@@ -123,17 +256,137 @@ impl EdwardsPoint {
         ret
     }
 
+    /// Point from y and the parity of x, `None` if there is no such point.
+    pub fn from_y(y: P25519FieldItem, sign: u8) -> Option<Self> {
+        // x^2 = (1 - y^2) / (A - D*y^2), A/D is not a square so this never divides by 0.
+        let yy = y.square();
+        let xx = (ONE - yy) * (A - D * yy).inverse();
+        if xx == ZERO {
+            return match sign & 1 {
+                0 => Some(Self::from_affine(ZERO, y)),
+                _ => None,
+            };
+        }
+        let (x, _) = xx.sqrt()?;
+        let mut x = x.abs();
+        x.conditional_negate((sign & 1) as i64);
+        Some(Self::from_affine(x, y))
+    }
+
+    /// Same as `from_y`, but `sign` is the parity of x on edwards25519.
+    pub fn from_ed25519_y(y: P25519FieldItem, sign: u8) -> Option<Self> {
+        let p = Self::from_y(y, 0)?;
+        let x_std = p.x * ED25519_X_SCALE;
+        if x_std == ZERO && sign & 1 == 1 {
+            return None;
+        }
+        let flip = x_std.is_negative() as u8 ^ (sign & 1);
+        let mut x = p.x;
+        x.conditional_negate(flip as i64);
+        Some(Self::from_affine(x, y))
+    }
+
+    /// Standard Ed25519 encoding (RFC 8032): y, with the parity of x on edwards25519 in bit 255.
+    pub fn to_ed25519_bytes(&self) -> [u8; 32] {
+        let (x, y) = self.to_affine();
+        let mut ret = y.pack();
+        ret[31] |= ((x * ED25519_X_SCALE).is_negative() as u8) << 7;
+        ret
+    }
+
+    /// Decode the standard Ed25519 encoding, rejecting y >= p.
+    pub fn from_ed25519_bytes(bytes: [u8; 32]) -> Option<Self> {
+        let sign = bytes[31] >> 7;
+        let mut y_bytes = bytes;
+        y_bytes[31] &= 0x7f;
+        let y: P25519FieldItem = y_bytes.into();
+        if y.pack() != y_bytes {
+            return None;
+        }
+        Self::from_ed25519_y(y, sign)
+    }
+
+    /// Map onto curve25519: u = (1 + y) / (1 - y), v = u / x.
+    /// x on edwards25519 is x * sqrt(-486664), so this is also the map of RFC 7748, section 4.1,
+    /// and G goes to the X25519 base point u = 9.
+    /// The neutral element goes to infinity (Z = 0) and (0, -1) to (0, 0).
+    pub fn to_montgomery(&self) -> MontgomeryCurvePoint {
+        if self.x == ZERO {
+            let at_infinity = self.y == self.z;
+            return MontgomeryCurvePoint {
+                x: ZERO,
+                y: if at_infinity { ONE } else { ZERO },
+                z: if at_infinity { ZERO } else { ONE },
+            };
+        }
+        // u = (Z + Y)X / ((Z - Y)X), v = (Z + Y)Z / ((Z - Y)X)
+        let zy = self.z + self.y;
+        MontgomeryCurvePoint {
+            x: zy * self.x,
+            y: zy * self.z,
+            z: (self.z - self.y) * self.x,
+        }
+    }
+
     /// Check if the point is in subgroup of size 8.
     pub fn is_cofactor(&self) -> bool {
-        (*self * EIGHT).pack() == self.pack()
+        self.double().double().double().is_identity()
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == E
+    }
+
+    /// Check if the point is in the subgroup of prime order n, i.e. nP = E.
+    pub fn is_torsion_free(&self) -> bool {
+        (*self * N.data).is_identity()
     }
 
     pub fn get_pubkey(privkey: [u8; 32]) -> [u8; 32] {
         let point = privkey * G;
         point.encode_point()
     }
+
+    /// Doubling based on dbl-2008-hwcd.
+    pub fn double(&self) -> Self {
+        let a = self.x * self.x;
+        let b = self.y * self.y;
+        let c = TWO * (self.z * self.z);
+        let d = A * a;
+        let t0 = self.x + self.y;
+        let e = t0 * t0 - a - b;
+        let g = d + b;
+        let f = g - c;
+        let h = d - b;
+
+        Self {
+            x: e * f,
+            y: g * h,
+            t: e * h,
+            z: f * g,
+        }
+    }
+
+    /// Swap with q if bit is 1, in constant time.
+    pub fn swap(&mut self, q: &mut Self, bit: i64) {
+        self.x.swap(&mut q.x, bit);
+        self.y.swap(&mut q.y, bit);
+        self.z.swap(&mut q.z, bit);
+        self.t.swap(&mut q.t, bit);
+    }
 }
 
+impl PartialEq for EdwardsPoint {
+    /// Compare in projective form: X1*Z2 == X2*Z1 and Y1*Z2 == Y2*Z1.
+    fn eq(&self, other: &Self) -> bool {
+        let x_eq = (self.x * other.z).pack() == (other.x * self.z).pack();
+        let y_eq = (self.y * other.z).pack() == (other.y * self.z).pack();
+        x_eq & y_eq
+    }
+}
+
+impl Eq for EdwardsPoint {}
+
 impl TryFrom<[u8; 32]> for EdwardsPoint {
     type Error = ECCError;
     fn try_from(point: [u8; 32]) -> Result<EdwardsPoint, ECCError> {
@@ -147,158 +400,81 @@ impl From<EdwardsPoint> for [u8; 32] {
     }
 }
 
-// This is very slow.
 impl Add<EdwardsPoint> for EdwardsPoint {
     type Output = EdwardsPoint;
+    /// Addition based on add-2008-hwcd.
+    /// a is a square and d is not, so this rule is complete:
+    /// it also works for doubling and for the neutral element.
     fn add(self, rhs: EdwardsPoint) -> Self::Output {
-        // On twisted edwards curve, the addition rule is complete.
-        // That is, addition and doubling is the same rule.
-        let one: P25519FieldItem = 1.into();
-        let x = self.x * rhs.y + self.y * rhs.x;
-        let y = self.y * rhs.y - A * self.x * rhs.x;
-        let x_rev: P25519FieldItem = one + D * self.x * self.y * rhs.x * rhs.y;
-        let y_rev: P25519FieldItem = one - D * self.x * self.y * rhs.x * rhs.y;
+        let a = self.x * rhs.x;
+        let b = self.y * rhs.y;
+        let c = self.t * D * rhs.t;
+        let d = self.z * rhs.z;
+        let e = (self.x + self.y) * (rhs.x + rhs.y) - a - b;
+        let f = d - c;
+        let g = d + c;
+        let h = b - A * a;
 
         EdwardsPoint {
-            x: x * (x_rev.inverse()),
-            y: y * (y_rev.inverse()),
+            x: e * f,
+            y: g * h,
+            t: e * h,
+            z: f * g,
         }
     }
 }
 
-/// WARNING: This does not handle cofactor!
-impl Mul<[u8; 32]> for EdwardsPoint {
+impl Neg for EdwardsPoint {
     type Output = EdwardsPoint;
-    fn mul(self, rhs: [u8; 32]) -> Self::Output {
-        let mut clamped = rhs;
-        //clamped[0] &= 0xf8;
-        //clamped[31] = (clamped[31] & 0x7f) | 0x40;
-        let mut ret: EdwardsPointCompute = E.into();
-        let mut ret2: EdwardsPointCompute = E.into();
-        let mut b: EdwardsPointCompute = self.into();
-        let e: EdwardsPointCompute = E.into();
-        for i in 0..256 {
-            ret2 = ret + b;
-            if (rhs[i >> 3] & (1 << (i & 0x7))) != 0 {
-                ret = ret2;
-            } else {
-                ret = ret;
-            }
-            b = b.double();
+    fn neg(self) -> Self::Output {
+        EdwardsPoint {
+            x: -self.x,
+            y: self.y,
+            z: self.z,
+            t: -self.t,
         }
-
-        ret.into()
     }
 }
 
-impl Mul<EdwardsPoint> for [u8; 32] {
+impl Sub<EdwardsPoint> for EdwardsPoint {
     type Output = EdwardsPoint;
-    fn mul(self, rhs: EdwardsPoint) -> Self::Output {
-        rhs * self
-    }
-}
-
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
-pub struct EdwardsPointCompute {
-    pub x: P25519FieldItem,
-    pub y: P25519FieldItem,
-    pub z: P25519FieldItem,
-    pub t: P25519FieldItem,
-}
-
-impl EdwardsPointCompute {
-    /// Doubling based on dbl-2008-hwcd.
-    pub fn double(&self) -> Self {
-        let mut a: P25519FieldItem = 0.into();
-        let mut b: P25519FieldItem = 0.into();
-        let mut c: P25519FieldItem = 0.into();
-        let mut d: P25519FieldItem = 0.into();
-        let mut e: P25519FieldItem = 0.into();
-        let mut f: P25519FieldItem = 0.into();
-        let mut g: P25519FieldItem = 0.into();
-        let mut h: P25519FieldItem = 0.into();
-        let mut t0: P25519FieldItem = 0.into();
-        let mut t1: P25519FieldItem = 0.into();
-        let two: P25519FieldItem = 2.into();
-
-        a = self.x * self.x;
-        b = self.y * self.y;
-        t0 = self.z * self.z;
-        c = TWO * t0;
-        d = A * a;
-        t0 = self.x + self.y;
-        t1 = t0 * t0;
-        t0 = t1 - a;
-        e = t0 - b;
-        g = d + b;
-        f = g - c;
-        h = d - b;
-
-        Self {
-            x: e * f,
-            y: g * h,
-            t: e * h,
-            z: f * g,
-        }
+    fn sub(self, rhs: EdwardsPoint) -> Self::Output {
+        self + (-rhs)
     }
 }
 
-impl From<EdwardsPoint> for EdwardsPointCompute {
-    fn from(p: EdwardsPoint) -> Self {
-        Self {
-            x: p.x,
-            y: p.y,
-            z: 1.into(),
-            t: p.x * p.y,
+/// WARNING: This does not handle cofactor!
+impl Mul<[u8; 32]> for EdwardsPoint {
+    type Output = EdwardsPoint;
+    fn mul(self, rhs: [u8; 32]) -> Self::Output {
+        let mut ret = E;
+        for i in (0..256).rev() {
+            ret = ret.double();
+            let mut sum = ret + self;
+            let bit = (rhs[i >> 3] >> (i & 0x7)) & 1;
+            ret.swap(&mut sum, bit as i64);
         }
+        ret
     }
 }
 
-impl From<EdwardsPointCompute> for EdwardsPoint {
-    fn from(p: EdwardsPointCompute) -> Self {
-        Self {
-            x: p.x * (p.z.inverse()),
-            y: p.y * (p.z.inverse()),
-        }
+impl Mul<EdwardsPoint> for [u8; 32] {
+    type Output = EdwardsPoint;
+    fn mul(self, rhs: EdwardsPoint) -> Self::Output {
+        rhs * self
     }
 }
 
-impl Add<EdwardsPointCompute> for EdwardsPointCompute {
-    type Output = EdwardsPointCompute;
-    /// Addition based on add-2008-hwcd-2.
-    /// This will not work for double!
-    fn add(self, rhs: EdwardsPointCompute) -> Self::Output {
-        let mut a: P25519FieldItem = 0.into();
-        let mut b: P25519FieldItem = 0.into();
-        let mut c: P25519FieldItem = 0.into();
-        let mut d: P25519FieldItem = 0.into();
-        let mut e: P25519FieldItem = 0.into();
-        let mut f: P25519FieldItem = 0.into();
-        let mut g: P25519FieldItem = 0.into();
-        let mut h: P25519FieldItem = 0.into();
-        let mut t0: P25519FieldItem = 0.into();
-        let mut t1: P25519FieldItem = 0.into();
-
-        a = self.x * rhs.x;
-        b = self.y * rhs.y;
-        c = self.z * rhs.t;
-        d = self.t * rhs.z;
-        e = d + c;
-        t0 = self.x - self.y;
-        t1 = rhs.x + rhs.y;
-        t0 = t0 * t1;
-        t0 = t0 + b;
-        f = t0 - a;
-        g = b + A * a;
-        h = d - c;
-
-        Self {
-            x: e * f,
-            y: g * h,
-            t: e * h,
-            z: f * g,
+/// Bits [start, start + width) of a little endian scalar.
+fn window_digit(k: &[u8; 32], start: usize, width: usize) -> usize {
+    let mut digit = 0;
+    for i in 0..width {
+        let bit = start + i;
+        if bit < 256 {
+            digit |= (((k[bit >> 3] >> (bit & 7)) & 1) as usize) << i;
         }
     }
+    digit
 }
 
 #[cfg(test)]
@@ -306,10 +482,11 @@ impl Add<EdwardsPointCompute> for EdwardsPointCompute {
 fn test_edwards_add() {
     let (x, y) = (G + G + G).pack();
     println!("{} {}", x.encode_hex::<String>(), y.encode_hex::<String>());
-    let g: EdwardsPointCompute = G.into();
-    let g_2: EdwardsPoint = g.double().into();
-    let g_2_2 = g + g.double();
-    let g_2_2: EdwardsPoint = g_2_2.into();
+    let g_2 = G.double();
+    let g_2_2 = G + G.double();
+    assert_eq!(g_2, G + G);
+    assert_eq!(g_2_2, G + G + G);
+    assert_eq!(G + E, G);
     let (x, y) = g_2.pack();
     println!("{} {}", x.encode_hex::<String>(), y.encode_hex::<String>());
     let (x, y) = g_2_2.pack();
@@ -322,6 +499,20 @@ fn test_edwards_add() {
         y.encode_hex::<String>(),
         g_100.encode_point().encode_hex::<String>()
     );
+    let mut three = [0u8; 32];
+    three[0] = 3;
+    assert_eq!(G * three, G + G + G);
+}
+
+#[test]
+fn test_edwards_batch_normalize() {
+    let mut points = vec![G, G.double(), G + G.double(), E, G * [7u8; 32]];
+    let expected: Vec<_> = points.iter().map(|p| p.pack()).collect();
+    EdwardsPoint::batch_normalize(&mut points);
+    for (p, e) in points.iter().zip(expected.iter()) {
+        assert_eq!(p.z, ONE);
+        assert_eq!((p.x.pack(), p.y.pack()), *e);
+    }
 }
 
 #[test]
@@ -331,7 +522,8 @@ fn test_edward_key_compress() {
     let p = G + G;
     let p_compressed = p.encode_point();
     let p_decompressed = EdwardsPoint::recover_point(p_compressed).unwrap();
-    println!("origin {:?} {:?}", p.x.pack(), p.y.pack());
+    let (x, y) = p.pack();
+    println!("origin {:?} {:?}", x, y);
     println!("compressed {:?}", p_compressed);
     println!(
         "restored {:?} {:?}",
@@ -348,8 +540,8 @@ fn test_edward_key_compress() {
         let point2 = point.encode_point();
         //println!("point {} {}",point.x.pack().encode_hex::<String>(),point.y.pack().encode_hex::<String>());
         assert_eq!(
-            point.y.pack(),
-            EdwardsPoint::recover_point(point2).unwrap().y.pack()
+            point.pack().1,
+            EdwardsPoint::recover_point(point2).unwrap().pack().1
         );
     }
 
@@ -359,11 +551,62 @@ fn test_edward_key_compress() {
     let point = key * G;
     let point2 = point.encode_point();
     assert_eq!(
-        point.y.pack(),
-        EdwardsPoint::recover_point(point2).unwrap().y.pack()
+        point.pack().1,
+        EdwardsPoint::recover_point(point2).unwrap().pack().1
+    );
+    assert_eq!(
+        point.pack().0,
+        EdwardsPoint::recover_point(point2).unwrap().pack().0
+    );
+}
+
+#[test]
+fn test_pubkey_stable() {
+    // Public keys must not change with the point representation.
+    let key =
+        <[u8; 32]>::from_hex("a6e3f62ee9e153ce2f5c6689789358cc9ece27c18f41ff1063edb6687a7a352e")
+            .unwrap();
+    assert_eq!(
+        EdwardsPoint::get_pubkey(key).encode_hex::<String>(),
+        "293e94a46be0585bf832fa3f779fb74615542341f42765f946188a5ccef970d3"
     );
     assert_eq!(
-        point.x.pack(),
-        EdwardsPoint::recover_point(point2).unwrap().x.pack()
+        EdwardsPoint::get_pubkey([0xff; 32]).encode_hex::<String>(),
+        "2ddaa1f5e91cdf7a128f31cf1aa60f7a74cbc3245773be8d53b0cad422c412f0"
     );
 }
+
+#[test]
+fn test_ed25519_encoding() {
+    // Standard encoding of the base point.
+    let b = G.to_ed25519_bytes();
+    assert_eq!(b.encode_hex::<String>(), "58".to_owned() + &"66".repeat(31));
+    assert!(EdwardsPoint::from_ed25519_bytes(b).unwrap() == G);
+    assert!(EdwardsPoint::from_ed25519_bytes((-G).to_ed25519_bytes()).unwrap() == -G);
+    // y = p is not canonical.
+    let mut p = [0xffu8; 32];
+    p[0] = 0xed;
+    p[31] = 0x7f;
+    assert!(EdwardsPoint::from_ed25519_bytes(p).is_none());
+}
+
+#[test]
+fn test_multiscalar_mul() {
+    let mut rng = ChaCha20Rng::from_entropy();
+    for n in [0, 1, 5, 40] {
+        let mut scalars = vec![];
+        let mut points = vec![];
+        let mut expect = E;
+        for _ in 0..n {
+            let mut k = [0u8; 32];
+            let mut q = [0u8; 32];
+            rng.fill_bytes(&mut k);
+            rng.fill_bytes(&mut q);
+            let p = q * G;
+            expect = expect + k * p;
+            scalars.push(k);
+            points.push(p);
+        }
+        assert!(EdwardsPoint::vartime_multiscalar_mul(&scalars, &points) == expect);
+    }
+}
@@ -1,52 +1,211 @@
-use crate::sha256::SHA256;
+use crate::transcript::Transcript;
 
 // EdDSA - Signature on Twisted Edward curve.
 // This implentation uses legacy parameters, and is not compatible with modern parameters based impls.
+use super::arith_n::SCALAR_ZERO;
 use super::{
     arith_n::ModNItem,
     ec25519::{ECCError, EdwardsPoint, G},
 };
 use hex::FromHex;
+use rand::prelude::*;
+use rand_chacha::ChaCha20Rng;
 /// This is not an ed25519, but ElGamal over edward25519.
 pub struct Sr25519;
 
+/// (pubkey, data, r, s) of one signature in a batch.
+pub type BatchItem<'a> = ([u8; 32], &'a [u8], [u8; 32], [u8; 32]);
+
+/// Context for signing certificates.
+pub const CONTEXT_CERTIFICATE: &[u8] = b"certificate";
+/// Context for signing certificate revocation lists.
+pub const CONTEXT_REVOCATION: &[u8] = b"revocation list";
+/// Context for signing handshake transcripts.
+pub const CONTEXT_HANDSHAKE: &[u8] = b"handshake";
+/// Context for messages signed by users.
+pub const CONTEXT_MESSAGE: &[u8] = b"user message";
+
+const NONCE_TAG: &[u8] = b"Sr25519-nonce";
+const CHALLENGE_TAG: &[u8] = b"Sr25519-challenge";
+
 impl Sr25519 {
+    /// h = H(context, data, R, P) from a transcript, reduced mod n.
+    ///
+    /// The context is a field of its own, and `None` is marked apart from any context,
+    /// so no message signed without a context (or under another) passes for one with it.
+    pub fn challenge(
+        context: Option<&[u8]>,
+        data: &[u8],
+        r: &[u8; 32],
+        pubkey: &[u8; 32],
+    ) -> [u8; 32] {
+        let mut t = Transcript::new(CHALLENGE_TAG);
+        append_context(&mut t, context);
+        t.append_message(b"data", data);
+        t.append_message(b"R", r);
+        t.append_message(b"P", pubkey);
+        t.challenge_scalar(b"h").to_bytes()
+    }
+
+    /// Sign with a hedged nonce, without a context.
     pub fn sign(key: [u8; 32], data: &[u8]) -> ([u8; 32], [u8; 32]) {
+        let mut aux = [0u8; 32];
+        ChaCha20Rng::from_entropy().fill_bytes(&mut aux);
+        Self::sign_inner(key, None, data, Some(&aux))
+    }
+
+    /// Sign data bound to `context` (e.g. `CONTEXT_CERTIFICATE`), so a signature made
+    /// for one purpose does not verify for another. Nonces are hedged.
+    pub fn sign_with_context(key: [u8; 32], context: &[u8], data: &[u8]) -> ([u8; 32], [u8; 32]) {
+        let mut aux = [0u8; 32];
+        ChaCha20Rng::from_entropy().fill_bytes(&mut aux);
+        Self::sign_inner(key, Some(context), data, Some(&aux))
+    }
+
+    pub fn verify_with_context(
+        pubkey: [u8; 32],
+        context: &[u8],
+        data: &[u8],
+        r: [u8; 32],
+        s: [u8; 32],
+    ) -> Result<bool, ECCError> {
+        Self::verify_inner(pubkey, Some(context), data, r, s)
+    }
+
+    /// k = H(prefix, aux, data) mod n, where prefix = H(key) never leaves this function.
+    /// Without aux, k is deterministic; with it, a broken RNG still gives a secure nonce,
+    /// and a fault on one signature does not repeat on the next.
+    fn nonce(
+        key: &[u8; 32],
+        context: Option<&[u8]>,
+        data: &[u8],
+        aux: Option<&[u8; 32]>,
+    ) -> ModNItem {
+        let mut t = Transcript::new(NONCE_TAG);
+        t.append_message(b"key", key);
+        let mut prefix = [0u8; 32];
+        t.challenge_bytes(b"prefix", &mut prefix);
+
+        let mut t = Transcript::new(NONCE_TAG);
+        t.append_message(b"prefix", &prefix);
+        t.append_message(b"aux", aux.unwrap_or(&[0u8; 32]));
+        append_context(&mut t, context);
+        t.append_message(b"data", data);
+        t.challenge_scalar(b"nonce")
+    }
+
+    fn sign_inner(
+        key: [u8; 32],
+        context: Option<&[u8]>,
+        data: &[u8],
+        aux: Option<&[u8; 32]>,
+    ) -> ([u8; 32], [u8; 32]) {
         let p = key * G;
-        let mut secret_source = vec![];
-        secret_source.extend_from_slice(&data);
-        secret_source.extend_from_slice(&key[..]);
-        let k = SHA256::do_hash(&secret_source);
-        let r = k * G;
+        let k = Self::nonce(&key, context, data, aux);
+        let r = k.to_bytes() * G;
         let key: ModNItem = key.into();
-        let mut h = vec![];
-        h.extend_from_slice(&data[..]);
-        h.extend_from_slice(&r.encode_point()[..]);
-        h.extend_from_slice(&p.encode_point()[..]);
-        let k: ModNItem = k.into();
-        let h = SHA256::do_hash(&h);
-        let h: ModNItem = h.into();
+        let h: ModNItem =
+            Self::challenge(context, data, &r.encode_point(), &p.encode_point()).into();
         let s = k + h * key;
 
         (r.into(), s.to_bytes())
     }
 
+    /// Verify a signature made without a context.
     pub fn verify(
         pubkey: [u8; 32],
         data: &[u8],
         r: [u8; 32],
         s: [u8; 32],
     ) -> Result<bool, ECCError> {
+        Self::verify_inner(pubkey, None, data, r, s)
+    }
+
+    fn verify_inner(
+        pubkey: [u8; 32],
+        context: Option<&[u8]>,
+        data: &[u8],
+        r: [u8; 32],
+        s: [u8; 32],
+    ) -> Result<bool, ECCError> {
+        // s >= n would make the signature malleable.
+        if !ModNItem::is_canonical(&s) {
+            return Ok(false);
+        }
         let pubkey = EdwardsPoint::recover_point(pubkey).ok_or(ECCError::InvalidPoint)?;
         let r = EdwardsPoint::recover_point(r).ok_or(ECCError::InvalidPoint)?;
         let left = s * G;
-        let mut h = vec![];
-        h.extend_from_slice(&data[..]);
-        h.extend_from_slice(&r.encode_point()[..]);
-        h.extend_from_slice(&pubkey.encode_point()[..]);
-        let h = SHA256::do_hash(&h);
+        let h = Self::challenge(context, data, &r.encode_point(), &pubkey.encode_point());
         let right = r + h * pubkey;
-        Ok(left.pack() == right.pack())
+        // Cofactored, as in `verify_combined`, so a small order component in R or P
+        // is ignored by both alike.
+        Ok((left - right).is_cofactor())
+    }
+
+    /// Verify many (pubkey, data, r, s) made without a context at once.
+    ///
+    /// With random 128-bit z_i, checks
+    /// 8(sum(z_i*s_i)G - sum(z_i*R_i) - sum(z_i*h_i*P_i)) = 0 in one multi-scalar multiplication.
+    /// If that fails, every signature is verified alone, and the indices of bad ones are returned.
+    pub fn verify_batch(items: &[BatchItem]) -> Result<(), Vec<usize>> {
+        if Self::verify_combined(items) {
+            return Ok(());
+        }
+        let bad: Vec<usize> = items
+            .iter()
+            .enumerate()
+            .filter(|(_, (pubkey, data, r, s))| {
+                !matches!(Self::verify(*pubkey, data, *r, *s), Ok(true))
+            })
+            .map(|(i, _)| i)
+            .collect();
+        if bad.is_empty() {
+            // Cannot happen: 8 kills every term of a signature which verifies alone.
+            Ok(())
+        } else {
+            Err(bad)
+        }
+    }
+
+    fn verify_combined(items: &[BatchItem]) -> bool {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut scalars = Vec::with_capacity(2 * items.len() + 1);
+        let mut points = Vec::with_capacity(2 * items.len() + 1);
+        let mut s_sum = SCALAR_ZERO;
+        for (pubkey, data, r, s) in items {
+            let s = match ModNItem::from_canonical_bytes(*s) {
+                Some(s) => s,
+                None => return false,
+            };
+            let (p, r_point) = match (
+                EdwardsPoint::recover_point(*pubkey),
+                EdwardsPoint::recover_point(*r),
+            ) {
+                (Some(p), Some(r_point)) => (p, r_point),
+                _ => return false,
+            };
+            // Hash the points as `verify` does, so both agree on non-canonical encodings.
+            let h: ModNItem =
+                Self::challenge(None, data, &r_point.encode_point(), &p.encode_point()).into();
+            let mut z = [0u8; 32];
+            rng.fill_bytes(&mut z[..16]);
+            let z: ModNItem = z.into();
+            s_sum = s_sum + z * s;
+            scalars.push(z.to_bytes());
+            points.push(-r_point);
+            scalars.push((z * h).to_bytes());
+            points.push(-p);
+        }
+        scalars.push(s_sum.to_bytes());
+        points.push(G);
+        EdwardsPoint::vartime_multiscalar_mul(&scalars, &points).is_cofactor()
+    }
+}
+
+fn append_context(t: &mut Transcript, context: Option<&[u8]>) {
+    match context {
+        Some(context) => t.append_message(b"context", context),
+        None => t.append_message(b"no context", b""),
     }
 }
 
@@ -61,4 +220,102 @@ fn test_schnorr_sig() {
     assert!(Sr25519::verify(pubkey, data, sig.0, sig.1).unwrap());
     let data = "whatsoever_again".as_bytes();
     assert!(!Sr25519::verify(pubkey, data, sig.0, sig.1).unwrap());
+    // s + n is the same scalar, but must be rejected.
+    let data = "whatsoever".as_bytes();
+    let (s_n, _) = crate::mp::LargeInt { data: sig.1 }.overflowing_add(&super::arith_n::N);
+    assert!(!Sr25519::verify(pubkey, data, sig.0, s_n.data).unwrap());
+}
+
+#[test]
+fn test_schnorr_batch() {
+    let mut rng = ChaCha20Rng::from_entropy();
+    let mut keys = vec![];
+    let mut msgs = vec![];
+    for i in 0..16u8 {
+        let mut key = [0u8; 32];
+        rng.fill_bytes(&mut key);
+        keys.push(key);
+        msgs.push(vec![i; i as usize + 1]);
+    }
+    let mut items: Vec<_> = keys
+        .iter()
+        .zip(msgs.iter())
+        .map(|(key, msg)| {
+            let (r, s) = Sr25519::sign(*key, msg);
+            (EdwardsPoint::get_pubkey(*key), &msg[..], r, s)
+        })
+        .collect();
+    assert!(Sr25519::verify_batch(&items).is_ok());
+    assert!(Sr25519::verify_batch(&[]).is_ok());
+
+    // Wrong message and wrong s.
+    items[3].1 = &msgs[4][..];
+    items[9].3[0] ^= 1;
+    assert_eq!(Sr25519::verify_batch(&items), Err(vec![3, 9]));
+}
+
+#[test]
+fn test_schnorr_context() {
+    let mut key = [0u8; 32];
+    ChaCha20Rng::from_entropy().fill_bytes(&mut key);
+    let pubkey = EdwardsPoint::get_pubkey(key);
+    let data = "whatsoever".as_bytes();
+
+    let (r, s) = Sr25519::sign_with_context(key, CONTEXT_CERTIFICATE, data);
+    assert!(Sr25519::verify_with_context(pubkey, CONTEXT_CERTIFICATE, data, r, s).unwrap());
+    assert!(!Sr25519::verify_with_context(pubkey, CONTEXT_HANDSHAKE, data, r, s).unwrap());
+    assert!(!Sr25519::verify(pubkey, data, r, s).unwrap());
+    let (r, s) = Sr25519::sign(key, data);
+    assert!(!Sr25519::verify_with_context(pubkey, b"", data, r, s).unwrap());
+
+    // A plain signature over anything shaped like a context and message does not
+    // pass for a signature under that context.
+    let mut forged = b"certificate".to_vec();
+    forged.extend_from_slice(data);
+    let (r, s) = Sr25519::sign(key, &forged);
+    assert!(!Sr25519::verify_with_context(pubkey, CONTEXT_CERTIFICATE, data, r, s).unwrap());
+    assert!(!Sr25519::verify_with_context(pubkey, b"", &forged, r, s).unwrap());
+
+    // Nonces are deterministic without aux, and hedged with it.
+    let aux = [7u8; 32];
+    assert_eq!(
+        Sr25519::nonce(&key, None, data, None),
+        Sr25519::nonce(&key, None, data, None)
+    );
+    assert_ne!(
+        Sr25519::nonce(&key, None, data, None),
+        Sr25519::nonce(&key, None, data, Some(&aux))
+    );
+    assert_ne!(
+        Sr25519::nonce(&key, None, data, None),
+        Sr25519::nonce(&key, Some(b""), data, None)
+    );
+    assert_ne!(Sr25519::sign(key, data).0, Sr25519::sign(key, data).0);
+    let (r, s) = Sr25519::sign_inner(key, None, data, None);
+    assert!(Sr25519::verify(pubkey, data, r, s).unwrap());
+}
+
+#[test]
+fn test_schnorr_small_order() {
+    use super::arith::{ONE, ZERO};
+    let mut key = [0u8; 32];
+    ChaCha20Rng::from_entropy().fill_bytes(&mut key);
+    let pubkey = EdwardsPoint::get_pubkey(key);
+    let data = "whatsoever".as_bytes();
+    let t2 = EdwardsPoint::from_affine(ZERO, -ONE);
+
+    // s = k + h*x for R' = kG + T, so sG = R' + hP - T.
+    let k = ModNItem::random();
+    let r = (k.to_bytes() * G + t2).encode_point();
+    let h: ModNItem = Sr25519::challenge(None, data, &r, &pubkey).into();
+    let s = (k + h * ModNItem::from(key)).to_bytes();
+    let single = Sr25519::verify(pubkey, data, r, s).unwrap();
+    assert!(single);
+    // Batches draw fresh z_i, which were even half the time.
+    for _ in 0..16 {
+        assert_eq!(
+            Sr25519::verify_batch(&[(pubkey, data, r, s)]).is_ok(),
+            single
+        );
+    }
 }
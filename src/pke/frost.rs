@@ -0,0 +1,572 @@
+// frost.rs - t-of-n threshold Schnorr signatures (FROST).
+// See: Komlo, Goldberg - "FROST: Flexible Round-Optimized Schnorr Threshold Signatures".
+//
+// Keys are Shamir shares of the group secret, made by a trusted dealer (`trusted_dealer_keygen`)
+// or by all participants together (`dkg_part1`..`dkg_part3`, Pedersen DKG with proofs of knowledge).
+// Signing takes two rounds:
+//   1. every signer runs `commit` and sends its `SigningCommitments` to the aggregator,
+//   2. the aggregator sends a `SigningPackage` back, every signer answers with `sign`,
+// then `aggregate` checks the shares and outputs an `Sr25519` signature under the group key,
+// bound to the context of the `SigningPackage`.
+use super::arith_n::{ModNItem, SCALAR_ZERO};
+use super::ec25519::{ECCError, EdwardsPoint, E, G};
+use super::eddsa::Sr25519;
+#[cfg(test)]
+use super::eddsa::CONTEXT_CERTIFICATE;
+
+const DKG_POK_TAG: &[u8] = b"FROST-dkg-pok";
+const NONCE_TAG: &[u8] = b"FROST-nonce";
+const BINDING_TAG: &[u8] = b"FROST-binding";
+
+/// Participant identifiers are 1..=n; 0 would reveal the secret.
+pub type Identifier = u16;
+
+fn scalar_of(id: Identifier) -> ModNItem {
+    ModNItem::from(id as u64)
+}
+
+fn decode(point: [u8; 32]) -> Result<EdwardsPoint, ECCError> {
+    let p = EdwardsPoint::recover_point(point).ok_or(ECCError::InvalidPoint)?;
+    if !p.is_torsion_free() {
+        return Err(ECCError::SmallOrderAttack);
+    }
+    Ok(p)
+}
+
+/// f(x) = coefficients[0] + coefficients[1]*x + ...
+fn evaluate(coefficients: &[ModNItem], x: ModNItem) -> ModNItem {
+    coefficients
+        .iter()
+        .rev()
+        .fold(SCALAR_ZERO, |acc, a| acc * x + *a)
+}
+
+/// sum(commitments[k] * x^k), the public image of `evaluate`.
+fn evaluate_commitments(commitments: &[EdwardsPoint], x: ModNItem) -> EdwardsPoint {
+    commitments
+        .iter()
+        .rev()
+        .fold(E, |acc, c| x.to_bytes() * acc + *c)
+}
+
+/// Lagrange coefficient of `id` at 0 over the set `ids`.
+fn lagrange(id: Identifier, ids: &[Identifier]) -> ModNItem {
+    let x = scalar_of(id);
+    let mut num = ModNItem::from(1);
+    let mut den = ModNItem::from(1);
+    for j in ids.iter().filter(|j| **j != id) {
+        let xj = scalar_of(*j);
+        num = num * xj;
+        den = den * (xj - x);
+    }
+    num * den.inverse()
+}
+
+fn random_polynomial(secret: ModNItem, threshold: u16) -> Vec<ModNItem> {
+    let mut coefficients = vec![secret];
+    coefficients.extend((1..threshold).map(|_| ModNItem::random()));
+    coefficients
+}
+
+fn check_parameters(threshold: u16, n: u16) -> Result<(), ECCError> {
+    if threshold == 0 || threshold > n {
+        return Err(ECCError::InvalidParameter);
+    }
+    Ok(())
+}
+
+/// What one participant needs to sign.
+#[derive(Clone, Debug)]
+pub struct KeyPackage {
+    pub identifier: Identifier,
+    secret_share: ModNItem,
+    pub public: PublicKeyPackage,
+}
+
+/// What everybody (including the aggregator) knows.
+#[derive(Clone, Debug)]
+pub struct PublicKeyPackage {
+    pub threshold: u16,
+    /// Y_i = s_i * G of every participant.
+    pub verifying_shares: Vec<(Identifier, EdwardsPoint)>,
+    pub group_key: EdwardsPoint,
+}
+
+impl PublicKeyPackage {
+    /// The key which verifies group signatures.
+    pub fn group_key(&self) -> [u8; 32] {
+        self.group_key.encode_point()
+    }
+
+    fn verifying_share(&self, id: Identifier) -> Result<EdwardsPoint, ECCError> {
+        self.verifying_shares
+            .iter()
+            .find(|(i, _)| *i == id)
+            .map(|(_, y)| *y)
+            .ok_or(ECCError::UnknownSigner)
+    }
+}
+
+/// Split `secret` (a new random one if `None`) into `n` shares, any `threshold` of which can sign.
+///
+/// The dealer sees the whole secret; use the DKG if nobody should.
+pub fn trusted_dealer_keygen(
+    secret: Option<[u8; 32]>,
+    threshold: u16,
+    n: u16,
+) -> Result<Vec<KeyPackage>, ECCError> {
+    check_parameters(threshold, n)?;
+    let secret = secret.map(ModNItem::from).unwrap_or_else(ModNItem::random);
+    let coefficients = random_polynomial(secret, threshold);
+    let shares: Vec<_> = (1..=n)
+        .map(|id| (id, evaluate(&coefficients, scalar_of(id))))
+        .collect();
+    let public = PublicKeyPackage {
+        threshold,
+        verifying_shares: shares
+            .iter()
+            .map(|(id, s)| (*id, s.to_bytes() * G))
+            .collect(),
+        group_key: secret.to_bytes() * G,
+    };
+    Ok(shares
+        .into_iter()
+        .map(|(identifier, secret_share)| KeyPackage {
+            identifier,
+            secret_share,
+            public: public.clone(),
+        })
+        .collect())
+}
+
+/// Kept by a participant between DKG rounds.
+pub struct DkgSecret {
+    identifier: Identifier,
+    threshold: u16,
+    n: u16,
+    coefficients: Vec<ModNItem>,
+}
+
+/// Broadcast in DKG round 1: commitments to the polynomial and a proof of knowledge of its secret.
+#[derive(Clone, Debug)]
+pub struct DkgRound1Package {
+    pub identifier: Identifier,
+    pub commitments: Vec<[u8; 32]>,
+    pub proof: ([u8; 32], [u8; 32]),
+}
+
+fn dkg_challenge(id: Identifier, c0: &[u8; 32], r: &[u8; 32]) -> ModNItem {
+    ModNItem::from_hash(&[DKG_POK_TAG, &id.to_be_bytes(), c0, r])
+}
+
+/// DKG round 1.
+pub fn dkg_part1(
+    identifier: Identifier,
+    threshold: u16,
+    n: u16,
+) -> Result<(DkgSecret, DkgRound1Package), ECCError> {
+    check_parameters(threshold, n)?;
+    if identifier == 0 || identifier > n {
+        return Err(ECCError::UnknownSigner);
+    }
+    let coefficients = random_polynomial(ModNItem::random(), threshold);
+    let commitments: Vec<[u8; 32]> = coefficients
+        .iter()
+        .map(|a| (a.to_bytes() * G).encode_point())
+        .collect();
+    // Schnorr proof of a_0, so nobody can pick its commitment after seeing the others.
+    let k = ModNItem::random();
+    let r = (k.to_bytes() * G).encode_point();
+    let c = dkg_challenge(identifier, &commitments[0], &r);
+    let z = k + coefficients[0] * c;
+    Ok((
+        DkgSecret {
+            identifier,
+            threshold,
+            n,
+            coefficients,
+        },
+        DkgRound1Package {
+            identifier,
+            commitments,
+            proof: (r, z.to_bytes()),
+        },
+    ))
+}
+
+/// Round 1 packages of the other participants: exactly one from every identifier in 1..=n
+/// but our own.
+fn others<'a>(
+    secret: &DkgSecret,
+    packages: &'a [DkgRound1Package],
+) -> Result<Vec<&'a DkgRound1Package>, ECCError> {
+    let mut others: Vec<_> = packages
+        .iter()
+        .filter(|p| p.identifier != secret.identifier)
+        .collect();
+    others.sort_by_key(|p| p.identifier);
+    let ids: Vec<_> = others.iter().map(|p| p.identifier).collect();
+    let expected: Vec<_> = (1..=secret.n).filter(|j| *j != secret.identifier).collect();
+    if ids != expected {
+        return Err(ECCError::InvalidParameter);
+    }
+    Ok(others)
+}
+
+/// DKG round 2: check everybody's round 1 package, and compute the share f_i(j) for every j,
+/// to be sent to participant j over a private channel.
+///
+/// `packages` holds the round 1 packages of all participants (own one may be included).
+pub fn dkg_part2(
+    secret: &DkgSecret,
+    packages: &[DkgRound1Package],
+) -> Result<Vec<(Identifier, [u8; 32])>, ECCError> {
+    for p in others(secret, packages)? {
+        if p.commitments.len() != secret.threshold as usize {
+            return Err(ECCError::InvalidParameter);
+        }
+        let c0 = decode(p.commitments[0])?;
+        let r = decode(p.proof.0)?;
+        let z = ModNItem::from_canonical_bytes(p.proof.1).ok_or(ECCError::InvalidShare)?;
+        let c = dkg_challenge(p.identifier, &p.commitments[0], &p.proof.0);
+        if z.to_bytes() * G != r + c.to_bytes() * c0 {
+            return Err(ECCError::InvalidShare);
+        }
+    }
+    Ok((1..=secret.n)
+        .filter(|j| *j != secret.identifier)
+        .map(|j| (j, evaluate(&secret.coefficients, scalar_of(j)).to_bytes()))
+        .collect())
+}
+
+/// DKG round 3: check the received shares, and build the key package.
+///
+/// `shares` holds (sender, f_sender(own identifier)) from every other participant.
+pub fn dkg_part3(
+    secret: &DkgSecret,
+    packages: &[DkgRound1Package],
+    shares: &[(Identifier, [u8; 32])],
+) -> Result<KeyPackage, ECCError> {
+    let mut commitments: Vec<(Identifier, Vec<EdwardsPoint>)> = vec![];
+    for p in others(secret, packages)? {
+        let points = p
+            .commitments
+            .iter()
+            .map(|c| decode(*c))
+            .collect::<Result<Vec<_>, _>>()?;
+        commitments.push((p.identifier, points));
+    }
+    let own: Vec<_> = secret
+        .coefficients
+        .iter()
+        .map(|a| a.to_bytes() * G)
+        .collect();
+    commitments.push((secret.identifier, own));
+    // One share from every other participant, none twice, else one would be summed twice.
+    let mut senders: Vec<_> = shares.iter().map(|(id, _)| *id).collect();
+    senders.sort();
+    let expected: Vec<_> = (1..=secret.n).filter(|j| *j != secret.identifier).collect();
+    if senders != expected {
+        return Err(ECCError::InvalidParameter);
+    }
+
+    let me = scalar_of(secret.identifier);
+    let mut secret_share = evaluate(&secret.coefficients, me);
+    for (sender, share) in shares {
+        let points = &commitments
+            .iter()
+            .find(|(id, _)| id == sender)
+            .ok_or(ECCError::UnknownSigner)?
+            .1;
+        let share = ModNItem::from_canonical_bytes(*share).ok_or(ECCError::InvalidShare)?;
+        if share.to_bytes() * G != evaluate_commitments(points, me) {
+            return Err(ECCError::InvalidShare);
+        }
+        secret_share = secret_share + share;
+    }
+
+    let group_key = commitments.iter().fold(E, |acc, (_, c)| acc + c[0]);
+    let verifying_shares = (1..=secret.n)
+        .map(|j| {
+            let y = commitments
+                .iter()
+                .fold(E, |acc, (_, c)| acc + evaluate_commitments(c, scalar_of(j)));
+            (j, y)
+        })
+        .collect();
+    Ok(KeyPackage {
+        identifier: secret.identifier,
+        secret_share,
+        public: PublicKeyPackage {
+            threshold: secret.threshold,
+            verifying_shares,
+            group_key,
+        },
+    })
+}
+
+/// Secret nonces (d, e) of one signing session. Consumed by `sign`.
+pub struct SigningNonces {
+    hiding: ModNItem,
+    binding: ModNItem,
+}
+
+/// (D, E) = (d*G, e*G), sent to the aggregator in round 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SigningCommitments {
+    pub identifier: Identifier,
+    pub hiding: [u8; 32],
+    pub binding: [u8; 32],
+}
+
+/// Signing round 1.
+pub fn commit(key: &KeyPackage) -> (SigningNonces, SigningCommitments) {
+    // Hedged with the share, in case the RNG is weak.
+    let share = key.secret_share.to_bytes();
+    let rand = ModNItem::random().to_bytes();
+    let hiding = ModNItem::from_hash(&[NONCE_TAG, &rand, &share, &[1]]);
+    let binding = ModNItem::from_hash(&[NONCE_TAG, &rand, &share, &[2]]);
+    (
+        SigningNonces { hiding, binding },
+        SigningCommitments {
+            identifier: key.identifier,
+            hiding: (hiding.to_bytes() * G).encode_point(),
+            binding: (binding.to_bytes() * G).encode_point(),
+        },
+    )
+}
+
+/// Chosen signers, their commitments and the message, sent to the signers in round 2.
+#[derive(Clone, Debug)]
+pub struct SigningPackage {
+    pub commitments: Vec<SigningCommitments>,
+    /// Signing context, as in `Sr25519::sign_with_context`.
+    pub context: Vec<u8>,
+    pub message: Vec<u8>,
+}
+
+/// Values every party derives from a `SigningPackage`.
+struct SigningState {
+    ids: Vec<Identifier>,
+    /// (D_i + rho_i*E_i, rho_i) per signer.
+    nonces: Vec<(EdwardsPoint, ModNItem)>,
+    r: [u8; 32],
+    c: ModNItem,
+}
+
+impl SigningPackage {
+    pub fn new(commitments: Vec<SigningCommitments>, context: &[u8], message: &[u8]) -> Self {
+        let mut commitments = commitments;
+        commitments.sort_by_key(|c| c.identifier);
+        Self {
+            commitments,
+            context: context.to_vec(),
+            message: message.to_vec(),
+        }
+    }
+
+    fn state(&self, public: &PublicKeyPackage) -> Result<SigningState, ECCError> {
+        let ids: Vec<_> = self.commitments.iter().map(|c| c.identifier).collect();
+        if ids.len() < public.threshold as usize || ids.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(ECCError::InvalidParameter);
+        }
+        let group_key = public.group_key();
+        let mut encoded = vec![];
+        for c in &self.commitments {
+            encoded.extend_from_slice(&c.identifier.to_be_bytes());
+            encoded.extend_from_slice(&c.hiding);
+            encoded.extend_from_slice(&c.binding);
+        }
+        let mut nonces = vec![];
+        let mut r = E;
+        for c in &self.commitments {
+            public.verifying_share(c.identifier)?;
+            let rho = ModNItem::from_hash(&[
+                BINDING_TAG,
+                &group_key,
+                &c.identifier.to_be_bytes(),
+                &(self.context.len() as u64).to_be_bytes(),
+                &self.context,
+                &self.message,
+                &encoded,
+            ]);
+            let point = decode(c.hiding)? + rho.to_bytes() * decode(c.binding)?;
+            r = r + point;
+            nonces.push((point, rho));
+        }
+        let r = r.encode_point();
+        let c = Sr25519::challenge(Some(&self.context), &self.message, &r, &group_key).into();
+        Ok(SigningState { ids, nonces, r, c })
+    }
+}
+
+/// Signing round 2: z_i = d_i + e_i*rho_i + lambda_i*s_i*c.
+pub fn sign(
+    package: &SigningPackage,
+    nonces: SigningNonces,
+    key: &KeyPackage,
+) -> Result<[u8; 32], ECCError> {
+    let state = package.state(&key.public)?;
+    let i = state
+        .ids
+        .iter()
+        .position(|id| *id == key.identifier)
+        .ok_or(ECCError::UnknownSigner)?;
+    let own = package.commitments[i];
+    if own.hiding != (nonces.hiding.to_bytes() * G).encode_point()
+        || own.binding != (nonces.binding.to_bytes() * G).encode_point()
+    {
+        return Err(ECCError::InvalidShare);
+    }
+    let rho = state.nonces[i].1;
+    let lambda = lagrange(key.identifier, &state.ids);
+    let z = nonces.hiding + nonces.binding * rho + lambda * key.secret_share * state.c;
+    Ok(z.to_bytes())
+}
+
+/// Check every share, and combine them into (R, s), verifiable with `Sr25519::verify_with_context`
+/// against the group key. Shares must be in the order of `package.commitments`.
+pub fn aggregate(
+    package: &SigningPackage,
+    shares: &[[u8; 32]],
+    public: &PublicKeyPackage,
+) -> Result<([u8; 32], [u8; 32]), ECCError> {
+    let state = package.state(public)?;
+    if shares.len() != state.ids.len() {
+        return Err(ECCError::InvalidShare);
+    }
+    let mut s = SCALAR_ZERO;
+    for (i, share) in shares.iter().enumerate() {
+        let id = state.ids[i];
+        let z = ModNItem::from_canonical_bytes(*share).ok_or(ECCError::InvalidShare)?;
+        let lambda = lagrange(id, &state.ids);
+        let y = public.verifying_share(id)?;
+        let right = state.nonces[i].0 + (state.c * lambda).to_bytes() * y;
+        if z.to_bytes() * G != right {
+            return Err(ECCError::InvalidShare);
+        }
+        s = s + z;
+    }
+    Ok((state.r, s.to_bytes()))
+}
+
+#[cfg(test)]
+fn sign_with(keys: &[&KeyPackage], msg: &[u8]) -> Result<([u8; 32], [u8; 32]), ECCError> {
+    let (nonces, commitments): (Vec<_>, Vec<_>) = keys.iter().map(|k| commit(k)).unzip();
+    let package = SigningPackage::new(commitments, CONTEXT_CERTIFICATE, msg);
+    // Signers answer in identifier order, as the package is sorted.
+    let mut signers: Vec<_> = keys.iter().zip(nonces).collect();
+    signers.sort_by_key(|(k, _)| k.identifier);
+    let shares = signers
+        .into_iter()
+        .map(|(k, n)| sign(&package, n, k))
+        .collect::<Result<Vec<_>, _>>()?;
+    aggregate(&package, &shares, &keys[0].public)
+}
+
+#[test]
+fn test_frost_dealer() {
+    let mut secret = [0u8; 32];
+    secret[0] = 42;
+    let keys = trusted_dealer_keygen(Some(secret), 2, 3).unwrap();
+    let group_key = keys[0].public.group_key();
+    assert_eq!(group_key, EdwardsPoint::get_pubkey(secret));
+
+    let msg = "certificate".as_bytes();
+    for pair in [[0, 1], [1, 2], [2, 0]] {
+        let (r, s) = sign_with(&[&keys[pair[0]], &keys[pair[1]]], msg).unwrap();
+        assert!(Sr25519::verify_with_context(group_key, CONTEXT_CERTIFICATE, msg, r, s).unwrap());
+    }
+    let (r, s) = sign_with(&[&keys[0], &keys[1], &keys[2]], msg).unwrap();
+    assert!(Sr25519::verify_with_context(group_key, CONTEXT_CERTIFICATE, msg, r, s).unwrap());
+    // Not enough signers.
+    assert!(sign_with(&[&keys[0]], msg).is_err());
+    // A signer refuses a package with its binding commitment swapped.
+    let (nonces, mut commitments): (Vec<_>, Vec<_>) =
+        [&keys[0], &keys[1]].iter().map(|k| commit(k)).unzip();
+    commitments[0].binding = commitments[1].binding;
+    let package = SigningPackage::new(commitments, CONTEXT_CERTIFICATE, msg);
+    let own = nonces.into_iter().next().unwrap();
+    assert!(matches!(
+        sign(&package, own, &keys[0]),
+        Err(ECCError::InvalidShare)
+    ));
+    assert!(trusted_dealer_keygen(None, 4, 3).is_err());
+}
+
+#[test]
+fn test_frost_dkg() {
+    let n = 3;
+    let (secrets, packages): (Vec<_>, Vec<_>) =
+        (1..=n).map(|id| dkg_part1(id, 2, n).unwrap()).unzip();
+    let outgoing: Vec<_> = secrets
+        .iter()
+        .map(|s| dkg_part2(s, &packages).unwrap())
+        .collect();
+    let keys: Vec<_> = secrets
+        .iter()
+        .map(|s| {
+            let incoming: Vec<_> = outgoing
+                .iter()
+                .zip(secrets.iter())
+                .filter(|(_, from)| from.identifier != s.identifier)
+                .map(|(shares, from)| {
+                    let share = shares.iter().find(|(to, _)| *to == s.identifier).unwrap();
+                    (from.identifier, share.1)
+                })
+                .collect();
+            dkg_part3(s, &packages, &incoming).unwrap()
+        })
+        .collect();
+    let group_key = keys[0].public.group_key();
+    assert!(keys.iter().all(|k| k.public.group_key() == group_key));
+
+    let msg = "certificate".as_bytes();
+    let (r, s) = sign_with(&[&keys[2], &keys[0]], msg).unwrap();
+    assert!(Sr25519::verify_with_context(group_key, CONTEXT_CERTIFICATE, msg, r, s).unwrap());
+
+    // A forged proof of knowledge is refused.
+    let mut forged = packages.clone();
+    forged[1].proof.1[0] ^= 1;
+    assert!(dkg_part2(&secrets[0], &forged).is_err());
+    // And a participant sending twice in place of another.
+    let mut duplicate = packages.clone();
+    duplicate[2] = duplicate[1].clone();
+    assert!(dkg_part2(&secrets[0], &duplicate).is_err());
+    assert!(dkg_part3(&secrets[0], &duplicate, &[]).is_err());
+    assert!(dkg_part3(
+        &secrets[0],
+        &packages,
+        &[(2, outgoing[1][0].1), (2, outgoing[1][0].1)]
+    )
+    .is_err());
+    assert!(dkg_part3(
+        &secrets[0],
+        &packages,
+        &[
+            (2, outgoing[1][0].1),
+            (2, outgoing[1][0].1),
+            (3, outgoing[2][0].1)
+        ]
+    )
+    .is_err());
+    assert!(dkg_part3(
+        &secrets[0],
+        &packages,
+        &[
+            (1, outgoing[0][0].1),
+            (2, outgoing[1][0].1),
+            (3, outgoing[2][0].1)
+        ]
+    )
+    .is_err());
+    // So is a wrong share.
+    let mut bad = outgoing[1].clone();
+    bad[0].1[0] ^= 1;
+    assert!(dkg_part3(
+        &secrets[0],
+        &packages,
+        &[(2, bad[0].1), (3, outgoing[2][0].1)]
+    )
+    .is_err());
+}
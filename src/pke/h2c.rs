@@ -0,0 +1,377 @@
+// h2c - Hashing to curve25519 and ec25519.
+// See RFC 9380. Suites:
+//   edwards25519_XMD:SHA-512_ELL2_RO_ / _NU_
+//   curve25519_XMD:SHA-512_ELL2_RO_ / _NU_
+//
+// Points produced here are in the prime order subgroup,
+// and nobody knows their discrete log to G.
+use super::arith::{P25519FieldItem, ONE, TWO, ZERO};
+use super::curve25519::MontgomeryCurvePoint;
+use super::ec25519::{EdwardsPoint, E};
+use crate::common::{CryptError, CryptoHash};
+use crate::sha512::SHA512;
+
+/// A of curve25519, J in RFC 9380.
+const J: P25519FieldItem = P25519FieldItem([486662, 0, 0, 0, 0]);
+/// Field elements are taken from L = ceil((ceil(log2(p)) + k) / 8) = 48 bytes.
+const L: usize = 48;
+
+/// expand_message_xmd, section 5.3.1.
+/// Output is `len` bytes, at most 255 hash blocks.
+pub fn expand_message_xmd<H: CryptoHash<B>, const B: usize>(
+    msg: &[u8],
+    dst: &[u8],
+    len: usize,
+) -> Result<Vec<u8>, CryptError> {
+    let ell = len.div_ceil(B);
+    if ell > 255 || len > 65535 {
+        return Err(CryptError::InvalidParameter);
+    }
+    // Long DST is hashed first, section 5.3.3.
+    let dst = if dst.len() > 255 {
+        let mut data = b"H2C-OVERSIZE-DST-".to_vec();
+        data.extend_from_slice(dst);
+        H::hash(&data).to_vec()
+    } else {
+        dst.to_vec()
+    };
+    let mut dst_prime = dst;
+    dst_prime.push(dst_prime.len() as u8);
+
+    let mut msg_prime = vec![0u8; H::BLOCK_SIZE];
+    msg_prime.extend_from_slice(msg);
+    msg_prime.extend_from_slice(&(len as u16).to_be_bytes());
+    msg_prime.push(0);
+    msg_prime.extend_from_slice(&dst_prime);
+    let b_0 = H::hash(&msg_prime);
+
+    let mut ret = Vec::with_capacity(ell * B);
+    let mut b_i = [0u8; B];
+    for i in 1..=ell {
+        let mut data = Vec::with_capacity(B + 1 + dst_prime.len());
+        for j in 0..B {
+            data.push(b_0[j] ^ b_i[j]);
+        }
+        data.push(i as u8);
+        data.extend_from_slice(&dst_prime);
+        b_i = H::hash(&data);
+        ret.extend_from_slice(&b_i);
+    }
+    ret.truncate(len);
+    Ok(ret)
+}
+
+/// hash_to_field for p = 2^255-19, with expand_message_xmd over SHA-512.
+pub fn hash_to_field(msg: &[u8], dst: &[u8], count: usize) -> Vec<P25519FieldItem> {
+    let uniform = expand_message_xmd::<SHA512, 64>(msg, dst, count * L).unwrap();
+    uniform.chunks(L).map(from_be_wide).collect()
+}
+
+/// Reduce a 48 byte big endian number mod p.
+fn from_be_wide(bytes: &[u8]) -> P25519FieldItem {
+    let mut le = bytes.to_vec();
+    le.reverse();
+    let lo: [u8; 32] = le[0..32].try_into().unwrap();
+    let mut hi = [0u8; 32];
+    hi[..16].copy_from_slice(&le[32..48]);
+    // 2^256 = 38 mod p.
+    let lo: P25519FieldItem = lo.into();
+    let hi: P25519FieldItem = hi.into();
+    lo + hi * P25519FieldItem::from(38)
+}
+
+/// Elligator 2 onto curve25519 (J = 486662, K = 1, Z = 2), section 6.7.1.
+/// Returns affine (s, t).
+pub fn map_to_curve_elligator2(u: P25519FieldItem) -> (P25519FieldItem, P25519FieldItem) {
+    // x1 = -J / (1 + Z*u^2), inv0(0) = 0.
+    let mut x1 = -J * (ONE + TWO * u.square()).inverse();
+    if x1 == ZERO {
+        x1 = -J;
+    }
+    let gx1 = x1 * (x1.square() + J * x1 + ONE);
+    let x2 = -x1 - J;
+    let gx2 = x2 * (x2.square() + J * x2 + ONE);
+    match gx1.sqrt() {
+        Some((y, _)) => {
+            // sgn0(y) == 1
+            let y = if y.is_negative() { y } else { -y };
+            (x1, y)
+        }
+        None => {
+            let (y, _) = gx2.sqrt().unwrap();
+            // sgn0(y) == 0
+            (x2, y.abs())
+        }
+    }
+}
+
+/// Inverse of `map_to_curve_elligator2`.
+///
+/// Gives u < 2^254 such that the map sends u to (s, t),
+/// or `None` if the point is not in the image (about half of the points are).
+pub fn elligator2_inverse(s: P25519FieldItem, t: P25519FieldItem) -> Option<P25519FieldItem> {
+    if s == ZERO || s == -J {
+        return None;
+    }
+    // Point from x1: u^2 = -(s + J) / (2s), from x2: u^2 = -s / (2(s + J)).
+    let r2 = if t.is_negative() {
+        -(s + J) * (TWO * s).inverse()
+    } else {
+        // The map only takes x2 when g(x1) is not a square.
+        let x1 = -s - J;
+        if (x1 * (x1.square() + J * x1 + ONE)).sqrt().is_some() {
+            return None;
+        }
+        -s * (TWO * (s + J)).inverse()
+    };
+    let (mut u, _) = r2.sqrt()?;
+    // One of u, -u is below (p - 1) / 2.
+    let high = (u.pack()[31] >> 6) as i64 & 1;
+    u.conditional_negate(high);
+    Some(u)
+}
+
+/// Map Montgomery (s, t) onto ec25519, in the way of RFC 9380 appendix D.1.
+/// RFC 9380 scales x with the even root of -486664, which is -ED25519_X_SCALE,
+/// so on ec25519 x = -s/t. Exceptional points go to the neutral element.
+fn montgomery_to_edwards(s: P25519FieldItem, t: P25519FieldItem) -> EdwardsPoint {
+    if t * (s + ONE) == ZERO {
+        return E;
+    }
+    let x = -(s * t.inverse());
+    let y = (s - ONE) * (s + ONE).inverse();
+    EdwardsPoint::from_affine(x, y)
+}
+
+/// Reverse of `montgomery_to_edwards`.
+fn edwards_to_montgomery(p: EdwardsPoint) -> MontgomeryCurvePoint {
+    let (x, y) = p.to_affine();
+    if x == ZERO {
+        // Neutral element goes to infinity, (0, -1) to (0, 0).
+        return if y == ONE {
+            MontgomeryCurvePoint {
+                x: ZERO,
+                y: ONE,
+                z: ZERO,
+            }
+        } else {
+            MontgomeryCurvePoint {
+                x: ZERO,
+                y: ZERO,
+                z: ONE,
+            }
+        };
+    }
+    let u = (ONE + y) * (ONE - y).inverse();
+    let v = -(u * x.inverse());
+    MontgomeryCurvePoint { x: u, y: v, z: ONE }
+}
+
+fn clear_cofactor(p: EdwardsPoint) -> EdwardsPoint {
+    p.double().double().double()
+}
+
+fn hash_to_edwards(msg: &[u8], dst: &[u8]) -> EdwardsPoint {
+    let u = hash_to_field(msg, dst, 2);
+    let (s0, t0) = map_to_curve_elligator2(u[0]);
+    let (s1, t1) = map_to_curve_elligator2(u[1]);
+    let q0 = montgomery_to_edwards(s0, t0);
+    let q1 = montgomery_to_edwards(s1, t1);
+    clear_cofactor(q0 + q1)
+}
+
+fn encode_to_edwards(msg: &[u8], dst: &[u8]) -> EdwardsPoint {
+    let u = hash_to_field(msg, dst, 1);
+    let (s, t) = map_to_curve_elligator2(u[0]);
+    clear_cofactor(montgomery_to_edwards(s, t))
+}
+
+impl EdwardsPoint {
+    /// edwards25519_XMD:SHA-512_ELL2_RO_
+    pub fn hash_to_curve(msg: &[u8], dst: &[u8]) -> Self {
+        hash_to_edwards(msg, dst)
+    }
+
+    /// edwards25519_XMD:SHA-512_ELL2_NU_
+    pub fn encode_to_curve(msg: &[u8], dst: &[u8]) -> Self {
+        encode_to_edwards(msg, dst)
+    }
+}
+
+impl MontgomeryCurvePoint {
+    /// curve25519_XMD:SHA-512_ELL2_RO_
+    pub fn hash_to_curve(msg: &[u8], dst: &[u8]) -> Self {
+        edwards_to_montgomery(hash_to_edwards(msg, dst))
+    }
+
+    /// curve25519_XMD:SHA-512_ELL2_NU_
+    pub fn encode_to_curve(msg: &[u8], dst: &[u8]) -> Self {
+        edwards_to_montgomery(encode_to_edwards(msg, dst))
+    }
+
+    /// Elligator 2 representative of this point: 32 bytes which look uniformly random.
+    ///
+    /// Bit 254 and 255 are always zero in the representative, so they are filled from `tweak`.
+    /// `None` if the point has no representative; pick another ephemeral key then.
+    pub fn to_representative(&self, tweak: u8) -> Option<[u8; 32]> {
+        let z_inv = self.z.inverse();
+        let u = elligator2_inverse(self.x * z_inv, self.y * z_inv)?;
+        let mut ret = u.pack();
+        ret[31] |= tweak & 0xc0;
+        Some(ret)
+    }
+
+    /// Point from an Elligator 2 representative.
+    pub fn from_representative(repr: [u8; 32]) -> Self {
+        let mut repr = repr;
+        repr[31] &= 0x3f;
+        let (s, t) = map_to_curve_elligator2(repr.into());
+        MontgomeryCurvePoint { x: s, y: t, z: ONE }
+    }
+}
+
+/// Field element as the big endian hex used by the RFC.
+#[cfg(test)]
+fn be_hex(x: P25519FieldItem) -> String {
+    use hex::ToHex;
+    let mut b = x.pack();
+    b.reverse();
+    b.encode_hex::<String>()
+}
+
+#[test]
+fn test_expand_message_xmd() {
+    use crate::sha256::SHA256;
+    use hex::FromHex;
+    // RFC 9380, K.1
+    let dst = "QUUX-V01-CS02-with-expander-SHA256-128".as_bytes();
+    let cases = [
+        (
+            "",
+            "68a985b87eb6b46952128911f2a4412bbc302a9d759667f87f7a21d803f07235",
+        ),
+        (
+            "abc",
+            "d8ccab23b5985ccea865c6c97b6e5b8350e794e603b4b97902f53a8a0d605615",
+        ),
+        (
+            "abcdef0123456789",
+            "eff31487c770a893cfb36f912fbfcbff40d5661771ca4b2cb4eafe524333f5c1",
+        ),
+    ];
+    for (msg, expect) in cases {
+        let out = expand_message_xmd::<SHA256, 32>(msg.as_bytes(), dst, 0x20).unwrap();
+        assert_eq!(out, Vec::from_hex(expect).unwrap());
+    }
+    // RFC 9380, K.3
+    let dst = "QUUX-V01-CS02-with-expander-SHA512-256".as_bytes();
+    let cases = [
+        (
+            "",
+            "6b9a7312411d92f921c6f68ca0b6380730a1a4d982c507211a90964c394179ba",
+        ),
+        (
+            "abc",
+            "0da749f12fbe5483eb066a5f595055679b976e93abe9be6f0f6318bce7aca8dc",
+        ),
+    ];
+    for (msg, expect) in cases {
+        let out = expand_message_xmd::<SHA512, 64>(msg.as_bytes(), dst, 0x20).unwrap();
+        assert_eq!(out, Vec::from_hex(expect).unwrap());
+    }
+    assert!(expand_message_xmd::<SHA256, 32>(b"", dst, 256 * 32).is_err());
+}
+
+#[test]
+fn test_hash_to_edwards25519() {
+    use super::ec25519::ED25519_X_SCALE;
+    // RFC 9380, J.5.1 and J.5.2, coordinates on edwards25519.
+    let dst = "QUUX-V01-CS02-with-edwards25519_XMD:SHA-512_ELL2_RO_".as_bytes();
+    let cases = [
+        (
+            "",
+            "3c3da6925a3c3c268448dcabb47ccde5439559d9599646a8260e47b1e4822fc6",
+            "09a6c8561a0b22bef63124c588ce4c62ea83a3c899763af26d795302e115dc21",
+        ),
+        (
+            "abc",
+            "608040b42285cc0d72cbb3985c6b04c935370c7361f4b7fbdb1ae7f8c1a8ecad",
+            "1a8395b88338f22e435bbd301183e7f20a5f9de643f11882fb237f88268a5531",
+        ),
+    ];
+    for (msg, x, y) in cases {
+        let p = EdwardsPoint::hash_to_curve(msg.as_bytes(), dst);
+        let (px, py) = p.to_affine();
+        assert_eq!(be_hex(px * ED25519_X_SCALE), x);
+        assert_eq!(be_hex(py), y);
+    }
+
+    let dst = "QUUX-V01-CS02-with-edwards25519_XMD:SHA-512_ELL2_NU_".as_bytes();
+    let p = EdwardsPoint::encode_to_curve(b"", dst);
+    let (px, py) = p.to_affine();
+    assert_eq!(
+        be_hex(px * ED25519_X_SCALE),
+        "1ff2b70ecf862799e11b7ae744e3489aa058ce805dd323a936375a84695e76da"
+    );
+    assert_eq!(
+        be_hex(py),
+        "222e314d04a4d5725e9f2aff9fb2a6b69ef375a1214eb19021ceab2d687f0f9b"
+    );
+}
+
+#[test]
+fn test_hash_to_curve25519() {
+    // RFC 9380, J.4.1 and J.4.2
+    let dst = "QUUX-V01-CS02-with-curve25519_XMD:SHA-512_ELL2_RO_".as_bytes();
+    let p = MontgomeryCurvePoint::hash_to_curve(b"", dst);
+    assert_eq!(
+        be_hex(p.x),
+        "2de3780abb67e861289f5749d16d3e217ffa722192d16bbd9d1bfb9d112b98c0"
+    );
+    assert_eq!(
+        be_hex(p.y),
+        "3b5dc2a498941a1033d176567d457845637554a2fe7a3507d21abd1c1bd6e878"
+    );
+
+    let dst = "QUUX-V01-CS02-with-curve25519_XMD:SHA-512_ELL2_NU_".as_bytes();
+    let p = MontgomeryCurvePoint::encode_to_curve(b"", dst);
+    assert_eq!(
+        be_hex(p.x),
+        "1bb913f0c9daefa0b3375378ffa534bda5526c97391952a7789eb976edfe4d08"
+    );
+    assert_eq!(
+        be_hex(p.y),
+        "4548368f4f983243e747b62a600840ae7c1dab5c723991f85d3a9768479f3ec4"
+    );
+}
+
+#[test]
+fn test_elligator2_inverse() {
+    use rand::prelude::*;
+    use rand_chacha::ChaCha20Rng;
+    let mut rng = ChaCha20Rng::from_entropy();
+    let mut found = 0;
+    for _ in 0..64 {
+        let mut r = [0u8; 32];
+        rng.fill_bytes(&mut r);
+        r[31] &= 0x3f;
+        let p = MontgomeryCurvePoint::from_representative(r);
+        let repr = p.to_representative(rng.next_u32() as u8).unwrap();
+        // Same point, whatever the tweak bits are.
+        let q = MontgomeryCurvePoint::from_representative(repr);
+        assert_eq!(p.x, q.x);
+        assert_eq!(p.y, q.y);
+
+        // Random points have a representative about half of the time.
+        let mut key = [0u8; 32];
+        rng.fill_bytes(&mut key);
+        let e = edwards_to_montgomery(key * super::ec25519::G);
+        if let Some(repr) = e.to_representative(0) {
+            let q = MontgomeryCurvePoint::from_representative(repr);
+            assert_eq!(e.x, q.x);
+            assert_eq!(e.y, q.y);
+            found += 1;
+        }
+    }
+    assert!(found > 0);
+}
@@ -1,8 +1,15 @@
 pub mod arith;
 pub mod arith_n;
+pub mod blind;
 pub mod crypt;
 pub mod curve25519;
 pub mod ec25519;
 pub mod eddsa;
+pub mod frost;
+pub mod h2c;
 pub mod key;
 pub mod mqv;
+pub mod musig;
+pub mod ristretto;
+pub mod vrf;
+pub mod zkp;
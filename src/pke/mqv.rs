@@ -1,8 +1,8 @@
-use crate::common::CryptoHash;
 use crate::pke::arith_n::ModNItem;
+use crate::transcript::Transcript;
 // mqv.rs - FHMQV authenticated key exchange.
+use crate::aes;
 use crate::wire;
-use crate::{aes, sha256::SHA256};
 
 use super::arith::P25519FieldItem;
 use super::ec25519::{ECCError, EdwardsPoint, G};
@@ -86,44 +86,14 @@ impl FHMQV {
             let x_pk = remote_sk.encode_point();
             let y_pk = self.session_pubkey.encode_point();
 
-            let mut data_d: Vec<u8> = vec![];
-            data_d.extend_from_slice(&x_pk[..]);
-            data_d.extend_from_slice(&y_pk[..]);
-            data_d.extend_from_slice(&a_pk[..]);
-            data_d.extend_from_slice(&b_pk[..]);
-            let d = SHA256::hash(&data_d);
-
-            let mut data_e: Vec<u8> = vec![];
-            data_e.extend_from_slice(&y_pk[..]);
-            data_e.extend_from_slice(&x_pk[..]);
-            data_e.extend_from_slice(&a_pk[..]);
-            data_e.extend_from_slice(&b_pk[..]);
-            let e = SHA256::hash(&data_e);
-
-            /*
-            let s = y + e * b;
-            let s2 = s.clone();
-            let a_coeff = s * d;
-            let point = EdwardsPoint::mul_add(s2.into(), remote_sk, a_coeff.into(), remote_pk);
-            */
-            let s1 = remote_sk * b * e;
-            let s2 = remote_sk * y;
-            let s2 = s1 + s2;
-
-            let s3 = remote_pk * b * e;
-            let s4 = remote_pk * y;
-            let s4 = s3 + s4;
-            let s4 = s4 * d;
-            let point = s2 + s4;
-            //let point = s.pack()*(remote_sk + d.pack()*remote_pk);
-            let point_bin = point.encode_point();
-            let mut data_key: Vec<u8> = vec![];
-            data_key.extend_from_slice(&point_bin[..]);
-            data_key.extend_from_slice(&a_pk[..]);
-            data_key.extend_from_slice(&b_pk[..]);
-            data_key.extend_from_slice(&x_pk[..]);
-            data_key.extend_from_slice(&y_pk[..]);
-            let shared_key = SHA256::hash(&data_key);
+            let mut t = transcript(&a_pk, &b_pk, &x_pk, &y_pk);
+            let d = t.challenge_scalar(b"d");
+            let e = t.challenge_scalar(b"e");
+
+            // sigma = (y + e*b)(X + d*A)
+            let s = ModNItem::from(y) + e * ModNItem::from(b);
+            let point = s.to_bytes() * (remote_sk + d.to_bytes() * remote_pk);
+            let shared_key = session_key(t, &point);
 
             Ok(shared_key)
         } else {
@@ -143,42 +113,14 @@ impl FHMQV {
             let y_pk = remote_sk.encode_point();
             let x_pk = self.session_pubkey.encode_point();
 
-            let mut data_d: Vec<u8> = vec![];
-            data_d.extend_from_slice(&x_pk[..]);
-            data_d.extend_from_slice(&y_pk[..]);
-            data_d.extend_from_slice(&a_pk[..]);
-            data_d.extend_from_slice(&b_pk[..]);
-            let d = SHA256::hash(&data_d);
-
-            let mut data_e: Vec<u8> = vec![];
-            data_e.extend_from_slice(&y_pk[..]);
-            data_e.extend_from_slice(&x_pk[..]);
-            data_e.extend_from_slice(&a_pk[..]);
-            data_e.extend_from_slice(&b_pk[..]);
-            let e = SHA256::hash(&data_e);
-
-            //let s = x + d * a;
-            //let s2 = s.clone();
-            //let a_coeff = s * e;
-            //let point = EdwardsPoint::mul_add(s2.into(), remote_sk, a_coeff.into(), remote_pk);
-            let s1 = remote_sk * a * d;
-            let s2 = remote_sk * x;
-            let s2 = s1 + s2;
-
-            let s3 = remote_pk * a * d;
-            let s4 = remote_pk * x;
-            let s4 = s3 + s4;
-            let s4 = s4 * e;
-            let point = s2 + s4;
-            //let point = s.pack()*(remote_sk + e.pack()*remote_pk);
-            let point_bin = point.encode_point();
-            let mut data_key: Vec<u8> = vec![];
-            data_key.extend_from_slice(&point_bin[..]);
-            data_key.extend_from_slice(&a_pk[..]);
-            data_key.extend_from_slice(&b_pk[..]);
-            data_key.extend_from_slice(&x_pk[..]);
-            data_key.extend_from_slice(&y_pk[..]);
-            let shared_key = SHA256::hash(&data_key);
+            let mut t = transcript(&a_pk, &b_pk, &x_pk, &y_pk);
+            let d = t.challenge_scalar(b"d");
+            let e = t.challenge_scalar(b"e");
+
+            // sigma = (x + d*a)(Y + e*B)
+            let s = ModNItem::from(x) + d * ModNItem::from(a);
+            let point = s.to_bytes() * (remote_sk + e.to_bytes() * remote_pk);
+            let shared_key = session_key(t, &point);
 
             Ok(shared_key)
         } else {
@@ -187,6 +129,48 @@ impl FHMQV {
     }
 }
 
+/// Transcript of (A, B, X, Y): A, X from the client (initiator), B, Y from the server.
+fn transcript(a_pk: &[u8; 32], b_pk: &[u8; 32], x_pk: &[u8; 32], y_pk: &[u8; 32]) -> Transcript {
+    let mut t = Transcript::new(b"FHMQV");
+    t.append_message(b"A", a_pk);
+    t.append_message(b"B", b_pk);
+    t.append_message(b"X", x_pk);
+    t.append_message(b"Y", y_pk);
+    t
+}
+
+/// K = H(transcript || sigma), after d and e were drawn from the transcript.
+fn session_key(mut t: Transcript, sigma: &EdwardsPoint) -> [u8; 32] {
+    t.append_message(b"sigma", &sigma.encode_point());
+    let mut key = [0u8; 32];
+    t.challenge_bytes(b"session key", &mut key);
+    key
+}
+
+/// Label of the server's key-confirmation MAC.
+pub const CONFIRM_SERVER: &[u8] = b"server confirm";
+/// Label of the client's key-confirmation MAC.
+pub const CONFIRM_CLIENT: &[u8] = b"client confirm";
+
+/// MAC over both handshake messages under the shared key, one per direction.
+///
+/// Hello messages are their postcard bytes with the mac field set to zero.
+/// A peer which derived another key, or saw other messages, gets another MAC.
+pub fn confirmation_mac(
+    key: &[u8; 32],
+    label: &[u8],
+    client_hello: &[u8],
+    server_hello: &[u8],
+) -> [u8; 32] {
+    let mut t = Transcript::new(b"FHMQV-key-confirmation");
+    t.append_message(b"key", key);
+    t.append_message(b"client hello", client_hello);
+    t.append_message(b"server hello", server_hello);
+    let mut mac = [0u8; 32];
+    t.challenge_bytes(label, &mut mac);
+    mac
+}
+
 impl AuthenticatedKEX<32> for FHMQV {}
 
 #[test]
@@ -211,20 +195,20 @@ fn test_fhmqv() {
         server.set_remote_key(send_a, send_x).unwrap();
         client.set_remote_key(send_b, send_y).unwrap();
         assert_eq!(
-            server.pubkey.x.pack(),
-            client.remote_cred.unwrap().0.x.pack()
+            server.pubkey.pack().0,
+            client.remote_cred.unwrap().0.pack().0
         );
         assert_eq!(
-            server.pubkey.y.pack(),
-            client.remote_cred.unwrap().0.y.pack()
+            server.pubkey.pack().1,
+            client.remote_cred.unwrap().0.pack().1
         );
         assert_eq!(
-            client.pubkey.x.pack(),
-            server.remote_cred.unwrap().0.x.pack()
+            client.pubkey.pack().0,
+            server.remote_cred.unwrap().0.pack().0
         );
         assert_eq!(
-            client.pubkey.y.pack(),
-            server.remote_cred.unwrap().0.y.pack()
+            client.pubkey.pack().1,
+            server.remote_cred.unwrap().0.pack().1
         );
 
         assert_eq!(
@@ -0,0 +1,265 @@
+// musig.rs - n-of-n Schnorr multi-signatures (MuSig2).
+// See: Nick, Ruffing, Seurin - "MuSig2: Simple Two-Round Schnorr Multi-Signatures".
+//
+// The result is a plain Sr25519 signature under a context: it verifies with
+// `Sr25519::verify_with_context` against the aggregate key, and nobody can tell
+// it was made by a group.
+//
+// Round 1: every signer runs `nonce_gen` and sends its `PublicNonce`.
+// Round 2: every signer builds the same `Session` and sends `partial_sign`.
+// Anyone can then check the shares with `partial_verify` and `aggregate` them.
+use super::arith_n::{ModNItem, SCALAR_ZERO};
+use super::ec25519::{ECCError, EdwardsPoint, E, G};
+use super::eddsa::Sr25519;
+use crate::sha256::SHA256;
+use rand::prelude::*;
+use rand_chacha::ChaCha20Rng;
+
+const KEYAGG_LIST_TAG: &[u8] = b"MuSig2-keyagg-list";
+const KEYAGG_COEF_TAG: &[u8] = b"MuSig2-keyagg-coef";
+const NONCE_TAG: &[u8] = b"MuSig2-nonce";
+const NONCE_COEF_TAG: &[u8] = b"MuSig2-nonce-coef";
+
+fn decode(point: [u8; 32]) -> Result<EdwardsPoint, ECCError> {
+    let p = EdwardsPoint::recover_point(point).ok_or(ECCError::InvalidPoint)?;
+    if !p.is_torsion_free() {
+        return Err(ECCError::SmallOrderAttack);
+    }
+    Ok(p)
+}
+
+/// Public keys of the group and their aggregate.
+///
+/// Every signer must use the keys in the same order.
+pub struct KeyAggContext {
+    pubkeys: Vec<[u8; 32]>,
+    points: Vec<EdwardsPoint>,
+    /// a_i = H(L || X_i), L = H(X_1 || ... || X_n)
+    coefficients: Vec<ModNItem>,
+    aggregate: EdwardsPoint,
+}
+
+impl KeyAggContext {
+    pub fn new(pubkeys: &[[u8; 32]]) -> Result<Self, ECCError> {
+        if pubkeys.is_empty() {
+            return Err(ECCError::InvalidPoint);
+        }
+        let mut list = KEYAGG_LIST_TAG.to_vec();
+        for pk in pubkeys {
+            list.extend_from_slice(&pk[..]);
+        }
+        let list = SHA256::do_hash(&list);
+
+        let mut points = Vec::with_capacity(pubkeys.len());
+        let mut coefficients = Vec::with_capacity(pubkeys.len());
+        let mut aggregate = E;
+        for pk in pubkeys {
+            let p = decode(*pk)?;
+            let a = ModNItem::from_hash(&[KEYAGG_COEF_TAG, &list, &pk[..]]);
+            aggregate = aggregate + a.to_bytes() * p;
+            points.push(p);
+            coefficients.push(a);
+        }
+        Ok(Self {
+            pubkeys: pubkeys.to_vec(),
+            points,
+            coefficients,
+            aggregate,
+        })
+    }
+
+    /// The key which verifies the final signature.
+    pub fn aggregate_key(&self) -> [u8; 32] {
+        self.aggregate.encode_point()
+    }
+
+    fn index_of(&self, pubkey: &[u8; 32]) -> Result<usize, ECCError> {
+        self.pubkeys
+            .iter()
+            .position(|pk| pk == pubkey)
+            .ok_or(ECCError::UnknownSigner)
+    }
+}
+
+/// Secret half of a signer's nonce pair.
+///
+/// It is consumed by `Session::partial_sign`: signing twice with one nonce leaks the key.
+pub struct SecretNonce {
+    k1: ModNItem,
+    k2: ModNItem,
+}
+
+/// (R_1, R_2), sent to the other signers in round 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublicNonce(pub [u8; 32], pub [u8; 32]);
+
+/// Generate a fresh nonce pair for signing `msg` with `key`.
+///
+/// The nonces are random; key and message are only mixed in as a hedge against a weak RNG.
+pub fn nonce_gen(key: [u8; 32], msg: &[u8]) -> (SecretNonce, PublicNonce) {
+    let mut rand = [0u8; 32];
+    ChaCha20Rng::from_entropy().fill_bytes(&mut rand);
+    let k1 = ModNItem::from_hash(&[NONCE_TAG, &rand, &key, &[1], msg]);
+    let k2 = ModNItem::from_hash(&[NONCE_TAG, &rand, &key, &[2], msg]);
+    let r1 = (k1.to_bytes() * G).encode_point();
+    let r2 = (k2.to_bytes() * G).encode_point();
+    (SecretNonce { k1, k2 }, PublicNonce(r1, r2))
+}
+
+/// State of round 2, the same for every signer.
+pub struct Session {
+    nonces: Vec<(EdwardsPoint, EdwardsPoint)>,
+    /// Nonce coefficient b.
+    b: ModNItem,
+    /// R = R_1 + b*R_2
+    r: [u8; 32],
+    /// c = H(context, msg, R, P)
+    c: ModNItem,
+}
+
+impl Session {
+    /// Nonces must be in the same order as the keys of `ctx`.
+    /// `context` is the signing context, as in `Sr25519::sign_with_context`.
+    pub fn new(
+        ctx: &KeyAggContext,
+        nonces: &[PublicNonce],
+        context: &[u8],
+        msg: &[u8],
+    ) -> Result<Self, ECCError> {
+        if nonces.len() != ctx.points.len() {
+            return Err(ECCError::UnknownSigner);
+        }
+        let mut decoded = Vec::with_capacity(nonces.len());
+        let (mut r1, mut r2) = (E, E);
+        for n in nonces {
+            let pair = (decode(n.0)?, decode(n.1)?);
+            r1 = r1 + pair.0;
+            r2 = r2 + pair.1;
+            decoded.push(pair);
+        }
+        let p = ctx.aggregate_key();
+        let b = ModNItem::from_hash(&[
+            NONCE_COEF_TAG,
+            &r1.encode_point(),
+            &r2.encode_point(),
+            &p,
+            &(context.len() as u64).to_be_bytes(),
+            context,
+            msg,
+        ]);
+        let r = (r1 + b.to_bytes() * r2).encode_point();
+        let c = Sr25519::challenge(Some(context), msg, &r, &p).into();
+        Ok(Self {
+            nonces: decoded,
+            b,
+            r,
+            c,
+        })
+    }
+
+    /// s_i = k_1 + b*k_2 + c*a_i*x_i
+    pub fn partial_sign(
+        &self,
+        ctx: &KeyAggContext,
+        nonce: SecretNonce,
+        key: [u8; 32],
+    ) -> Result<[u8; 32], ECCError> {
+        let i = ctx.index_of(&EdwardsPoint::get_pubkey(key))?;
+        let x: ModNItem = key.into();
+        let s = nonce.k1 + self.b * nonce.k2 + self.c * ctx.coefficients[i] * x;
+        Ok(s.to_bytes())
+    }
+
+    /// Check s_i*G = R_1,i + b*R_2,i + c*a_i*X_i for the share of signer i.
+    pub fn partial_verify(&self, ctx: &KeyAggContext, i: usize, share: [u8; 32]) -> bool {
+        if i >= self.nonces.len() {
+            return false;
+        }
+        let s = match ModNItem::from_canonical_bytes(share) {
+            Some(s) => s,
+            None => return false,
+        };
+        let (r1, r2) = self.nonces[i];
+        let left = s.to_bytes() * G;
+        let right =
+            r1 + self.b.to_bytes() * r2 + (self.c * ctx.coefficients[i]).to_bytes() * ctx.points[i];
+        left == right
+    }
+
+    /// Sum the shares into (R, s), a signature under the aggregate key.
+    ///
+    /// Shares are verified first; the index of a bad one is not reported here,
+    /// use `partial_verify` to find it.
+    pub fn aggregate(
+        &self,
+        ctx: &KeyAggContext,
+        shares: &[[u8; 32]],
+    ) -> Result<([u8; 32], [u8; 32]), ECCError> {
+        if shares.len() != self.nonces.len() {
+            return Err(ECCError::InvalidShare);
+        }
+        let mut s = SCALAR_ZERO;
+        for (i, share) in shares.iter().enumerate() {
+            if !self.partial_verify(ctx, i, *share) {
+                return Err(ECCError::InvalidShare);
+            }
+            s = s + ModNItem::from_bytes(*share);
+        }
+        Ok((self.r, s.to_bytes()))
+    }
+}
+
+#[test]
+fn test_musig2() {
+    use super::eddsa::CONTEXT_MESSAGE;
+    let mut rng = ChaCha20Rng::from_entropy();
+    let msg = "release v1.0".as_bytes();
+    for n in [1, 2, 3] {
+        let keys: Vec<[u8; 32]> = (0..n)
+            .map(|_| {
+                let mut k = [0u8; 32];
+                rng.fill_bytes(&mut k);
+                k
+            })
+            .collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| EdwardsPoint::get_pubkey(*k)).collect();
+        let ctx = KeyAggContext::new(&pubkeys).unwrap();
+
+        // Round 1
+        let (secrets, publics): (Vec<_>, Vec<_>) = keys.iter().map(|k| nonce_gen(*k, msg)).unzip();
+        // Round 2
+        let session = Session::new(&ctx, &publics, CONTEXT_MESSAGE, msg).unwrap();
+        let shares: Vec<_> = secrets
+            .into_iter()
+            .zip(keys.iter())
+            .map(|(nonce, key)| session.partial_sign(&ctx, nonce, *key).unwrap())
+            .collect();
+        for (i, share) in shares.iter().enumerate() {
+            assert!(session.partial_verify(&ctx, i, *share));
+        }
+        let (r, s) = session.aggregate(&ctx, &shares).unwrap();
+        let p = ctx.aggregate_key();
+        assert!(Sr25519::verify_with_context(p, CONTEXT_MESSAGE, msg, r, s).unwrap());
+        assert!(!Sr25519::verify_with_context(p, CONTEXT_MESSAGE, b"release v1.1", r, s).unwrap());
+        assert!(!Sr25519::verify(p, msg, r, s).unwrap());
+
+        // A bad share is caught.
+        let mut bad = shares.clone();
+        bad[n - 1][0] ^= 1;
+        assert!(!session.partial_verify(&ctx, n - 1, bad[n - 1]));
+        assert!(session.aggregate(&ctx, &bad).is_err());
+    }
+
+    // Outsiders cannot sign.
+    let mut k = [0u8; 32];
+    rng.fill_bytes(&mut k);
+    let ctx = KeyAggContext::new(&[EdwardsPoint::get_pubkey(k)]).unwrap();
+    let (_, public) = nonce_gen(k, msg);
+    let session = Session::new(&ctx, &[public], CONTEXT_MESSAGE, msg).unwrap();
+    let (nonce, _) = nonce_gen(k, msg);
+    rng.fill_bytes(&mut k);
+    assert!(matches!(
+        session.partial_sign(&ctx, nonce, k),
+        Err(ECCError::UnknownSigner)
+    ));
+}
@@ -0,0 +1,318 @@
+// Ristretto255 - prime order group built on top of ec25519.
+// See RFC 9496.
+//
+// ec25519 has cofactor 8, so a point may carry a small order component.
+// Ristretto identifies the points which differ by such components,
+// and gives a canonical encoding for each class. Every valid encoding
+// stands for exactly one element of a group of prime order l.
+//
+// The formulas are defined on edwards25519 (a = -1), which is isomorphic to
+// ec25519 by scaling x with sqrt(-486664), see `ED25519_X_SCALE`.
+use super::arith::{P25519FieldItem, ONE, SQRT_M1, TWO, ZERO};
+use super::ec25519::{ECCError, EdwardsPoint, A, E, ED25519_X_SCALE, ED25519_X_SCALE_INV, G};
+use crate::sha512::SHA512;
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// d of edwards25519, -121665/121666.
+pub const EDWARDS_D: P25519FieldItem = P25519FieldItem([
+    0x34dca135978a3,
+    0x1a8283b156ebd,
+    0x5e7a26001c029,
+    0x739c663a03cbb,
+    0x52036cee2b6ff,
+]);
+/// sqrt(a*d - 1)
+const SQRT_AD_MINUS_ONE: P25519FieldItem = P25519FieldItem([
+    0x7f6a0497b2e1b,
+    0x1836f0a97afd2,
+    0x7d747f6be7638,
+    0x456079e7e6498,
+    0x376931bf2b834,
+]);
+/// 1/sqrt(a - d)
+const INVSQRT_A_MINUS_D: P25519FieldItem = P25519FieldItem([
+    0x0fdaa805d40ea,
+    0x2eb482e57d339,
+    0x007610274bc58,
+    0x6510b613dc8ff,
+    0x786c8905cfaff,
+]);
+/// 1 - d^2
+const ONE_MINUS_D_SQ: P25519FieldItem = P25519FieldItem([
+    0x409c1945fc176,
+    0x719abc6a1fc4f,
+    0x1c37f90b20684,
+    0x06bccca55eedf,
+    0x029072a8b2b3e,
+]);
+/// (d - 1)^2
+const D_MINUS_ONE_SQ: P25519FieldItem = P25519FieldItem([
+    0x55aaa44ed4d20,
+    0x59603c3332635,
+    0x26d3baf4a7928,
+    0x120a66e6997a9,
+    0x5968b37af66c2,
+]);
+
+/// Generator of the Ristretto group, same as G.
+pub const RISTRETTO_G: RistrettoPoint = RistrettoPoint(G);
+/// Neutral element.
+pub const RISTRETTO_E: RistrettoPoint = RistrettoPoint(E);
+
+/// An element of the Ristretto255 group.
+///
+/// The inner point is any representative of the class,
+/// so compare with `==` and never with the inner coordinates.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct RistrettoPoint(pub EdwardsPoint);
+
+impl RistrettoPoint {
+    /// Canonical 32 byte encoding.
+    pub fn encode(&self) -> [u8; 32] {
+        let p = &self.0;
+        let mut x = p.x * ED25519_X_SCALE;
+        let mut y = p.y;
+        let z = p.z;
+        let t = p.t * ED25519_X_SCALE;
+
+        let u1 = (z + y) * (z - y);
+        let u2 = x * y;
+        let (_, invsqrt) = P25519FieldItem::sqrt_ratio_m1(&ONE, &(u1 * u2.square()));
+        let den1 = invsqrt * u1;
+        let den2 = invsqrt * u2;
+        let z_inv = den1 * den2 * t;
+        let mut ix = x * SQRT_M1;
+        let mut iy = y * SQRT_M1;
+        let mut enchanted_denominator = den1 * INVSQRT_A_MINUS_D;
+        let mut den_inv = den2;
+
+        let rotate = (t * z_inv).is_negative() as i64;
+        x.swap(&mut iy, rotate);
+        y.swap(&mut ix, rotate);
+        den_inv.swap(&mut enchanted_denominator, rotate);
+
+        y.conditional_negate((x * z_inv).is_negative() as i64);
+        let s = (den_inv * (z - y)).abs();
+        s.pack()
+    }
+
+    /// Decode a canonical encoding.
+    /// Non-canonical or negative field elements and points off the curve are rejected.
+    pub fn decode(bytes: [u8; 32]) -> Option<Self> {
+        let s: P25519FieldItem = bytes.into();
+        if s.pack() != bytes || s.is_negative() {
+            return None;
+        }
+        let ss = s.square();
+        let u1 = ONE - ss;
+        let u2 = ONE + ss;
+        let u2_sqr = u2.square();
+        let v = -(EDWARDS_D * u1.square()) - u2_sqr;
+        let (was_square, invsqrt) = P25519FieldItem::sqrt_ratio_m1(&ONE, &(v * u2_sqr));
+        let den_x = invsqrt * u2;
+        let den_y = invsqrt * den_x * v;
+        let x = (TWO * s * den_x).abs();
+        let y = u1 * den_y;
+        let t = x * y;
+        if !was_square || t.is_negative() || y == ZERO {
+            return None;
+        }
+        Some(Self(EdwardsPoint::from_affine(x * ED25519_X_SCALE_INV, y)))
+    }
+
+    /// Map 64 uniformly random bytes to a group element.
+    pub fn from_uniform_bytes(bytes: &[u8; 64]) -> Self {
+        let mut r0: [u8; 32] = bytes[0..32].try_into().unwrap();
+        let mut r1: [u8; 32] = bytes[32..64].try_into().unwrap();
+        r0[31] &= 0x7f;
+        r1[31] &= 0x7f;
+        let p0 = Self::elligator_map(r0.into());
+        let p1 = Self::elligator_map(r1.into());
+        p0 + p1
+    }
+
+    /// Hash arbitary data to a group element, nobody knows its discrete log.
+    pub fn hash_from_bytes(data: &[u8]) -> Self {
+        Self::from_uniform_bytes(&SHA512::do_hash(data))
+    }
+
+    /// Elligator map onto the Jacobi quartic, then to the curve.
+    fn elligator_map(t: P25519FieldItem) -> Self {
+        let r = SQRT_M1 * t.square();
+        let u = (r + ONE) * ONE_MINUS_D_SQ;
+        let v = (-ONE - r * EDWARDS_D) * (r + EDWARDS_D);
+        let (was_square, mut s) = P25519FieldItem::sqrt_ratio_m1(&u, &v);
+        let mut s_prime = -(s * t).abs();
+        s.swap(&mut s_prime, !was_square as i64);
+        let mut c = -ONE;
+        let mut r_copy = r;
+        c.swap(&mut r_copy, !was_square as i64);
+
+        let n = c * (r - ONE) * D_MINUS_ONE_SQ - v;
+        let w0 = TWO * s * v;
+        let w1 = n * SQRT_AD_MINUS_ONE;
+        let w2 = ONE - s.square();
+        let w3 = ONE + s.square();
+
+        Self(EdwardsPoint {
+            x: w0 * w3 * ED25519_X_SCALE_INV,
+            y: w2 * w1,
+            z: w1 * w3,
+            t: w0 * w2 * ED25519_X_SCALE_INV,
+        })
+    }
+}
+
+impl PartialEq for RistrettoPoint {
+    /// On edwards25519: X1*Y2 == Y1*X2 or Y1*Y2 == X1*X2.
+    /// Scaled back to ec25519 the second one is Y1*Y2 == -a*X1*X2.
+    fn eq(&self, other: &Self) -> bool {
+        let p = &self.0;
+        let q = &other.0;
+        let eq1 = p.x * q.y == p.y * q.x;
+        let eq2 = p.y * q.y == -(A * p.x * q.x);
+        eq1 | eq2
+    }
+}
+
+impl Eq for RistrettoPoint {}
+
+impl TryFrom<[u8; 32]> for RistrettoPoint {
+    type Error = ECCError;
+    fn try_from(bytes: [u8; 32]) -> Result<RistrettoPoint, ECCError> {
+        RistrettoPoint::decode(bytes).ok_or(ECCError::InvalidPoint)
+    }
+}
+
+impl From<RistrettoPoint> for [u8; 32] {
+    fn from(point: RistrettoPoint) -> Self {
+        point.encode()
+    }
+}
+
+impl Add<RistrettoPoint> for RistrettoPoint {
+    type Output = RistrettoPoint;
+    fn add(self, rhs: RistrettoPoint) -> Self::Output {
+        RistrettoPoint(self.0 + rhs.0)
+    }
+}
+
+impl Sub<RistrettoPoint> for RistrettoPoint {
+    type Output = RistrettoPoint;
+    fn sub(self, rhs: RistrettoPoint) -> Self::Output {
+        RistrettoPoint(self.0 - rhs.0)
+    }
+}
+
+impl Neg for RistrettoPoint {
+    type Output = RistrettoPoint;
+    fn neg(self) -> Self::Output {
+        RistrettoPoint(-self.0)
+    }
+}
+
+impl Mul<[u8; 32]> for RistrettoPoint {
+    type Output = RistrettoPoint;
+    fn mul(self, rhs: [u8; 32]) -> Self::Output {
+        RistrettoPoint(self.0 * rhs)
+    }
+}
+
+impl Mul<RistrettoPoint> for [u8; 32] {
+    type Output = RistrettoPoint;
+    fn mul(self, rhs: RistrettoPoint) -> Self::Output {
+        rhs * self
+    }
+}
+
+#[test]
+fn test_ristretto_multiples() {
+    use hex::ToHex;
+    // RFC 9496, A.1: encodings of 0*B .. 15*B.
+    const EXPECT: &[&str] = &[
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "e2f2ae0a6abc4e71a884a961c500515f58e30b6aa582dd8db6a65945e08d2d76",
+        "6a493210f7499cd17fecb510ae0cea23a110e8d5b901f8acadd3095c73a3b919",
+        "94741f5d5d52755ece4f23f044ee27d5d1ea1e2bd196b462166b16152a9d0259",
+        "da80862773358b466ffadfe0b3293ab3d9fd53c5ea6c955358f568322daf6a57",
+        "e882b131016b52c1d3337080187cf768423efccbb517bb495ab812c4160ff44e",
+        "f64746d3c92b13050ed8d80236a7f0007c3b3f962f5ba793d19a601ebb1df403",
+        "44f53520926ec81fbd5a387845beb7df85a96a24ece18738bdcfa6a7822a176d",
+        "903293d8f2287ebe10e2374dc1a53e0bc887e592699f02d077d5263cdd55601c",
+        "02622ace8f7303a31cafc63f8fc48fdc16e1c8c8d234b2f0d6685282a9076031",
+        "20706fd788b2720a1ed2a5dad4952b01f413bcf0e7564de8cdc816689e2db95f",
+        "bce83f8ba5dd2fa572864c24ba1810f9522bc6004afe95877ac73241cafdab42",
+        "e4549ee16b9aa03099ca208c67adafcafa4c3f3e4e5303de6026e3ca8ff84460",
+        "aa52e000df2e16f55fb1032fc33bc42742dad6bd5a8fc0be0167436c5948501f",
+        "46376b80f409b29dc2b5f6f0c52591990896e5716f41477cd30085ab7f10301e",
+        "e0c418f7c8d9c4cdd7395b93ea124f3ad99021bb681dfc3302a9d99a2e53e64e",
+    ];
+    let mut p = RISTRETTO_E;
+    for expect in EXPECT {
+        let encoded = p.encode();
+        assert_eq!(encoded.encode_hex::<String>(), *expect);
+        let decoded = RistrettoPoint::decode(encoded).unwrap();
+        assert_eq!(decoded, p);
+        assert_eq!(decoded.encode(), encoded);
+        p = p + RISTRETTO_G;
+    }
+}
+
+#[test]
+fn test_ristretto_torsion_free() {
+    use rand::prelude::*;
+    use rand_chacha::ChaCha20Rng;
+    // Adding the point of order 2 must not change the group element.
+    let t2 = EdwardsPoint::from_affine(ZERO, -ONE);
+    assert!(t2.is_cofactor());
+    let mut rng = ChaCha20Rng::from_entropy();
+    for _ in 0..10 {
+        let mut key = [0u8; 32];
+        rng.fill_bytes(&mut key[..]);
+        let p = key * RISTRETTO_G;
+        let q = RistrettoPoint(p.0 + t2);
+        assert_eq!(p, q);
+        assert_eq!(p.encode(), q.encode());
+        assert_ne!(p, p + RISTRETTO_G);
+    }
+}
+
+#[test]
+fn test_ristretto_invalid() {
+    use hex::FromHex;
+    // Negative field element.
+    let bad =
+        <[u8; 32]>::from_hex("0100000000000000000000000000000000000000000000000000000000000000")
+            .unwrap();
+    assert!(RistrettoPoint::decode(bad).is_none());
+    // Non-canonical field element (p).
+    let bad =
+        <[u8; 32]>::from_hex("edffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff7f")
+            .unwrap();
+    assert!(RistrettoPoint::decode(bad).is_none());
+    // High bit set.
+    let mut bad = RISTRETTO_G.encode();
+    bad[31] |= 0x80;
+    assert!(RistrettoPoint::decode(bad).is_none());
+}
+
+#[test]
+fn test_ristretto_hash() {
+    use hex::ToHex;
+    // RFC 9496, A.3: SHA-512 of the label, then from_uniform_bytes.
+    let p = RistrettoPoint::hash_from_bytes(
+        "Ristretto is traditionally a short shot of espresso coffee".as_bytes(),
+    );
+    assert_eq!(
+        p.encode().encode_hex::<String>(),
+        "3066f82a1a747d45120d1740f14358531a8f04bbffe6a819f86dfe50f44a0a46"
+    );
+    let q = RistrettoPoint::hash_from_bytes(
+        "Ristretto is traditionally a short shot of espresso coffee".as_bytes(),
+    );
+    assert_eq!(p, q);
+    assert_eq!(RistrettoPoint::decode(p.encode()).unwrap(), p);
+    let r = RistrettoPoint::hash_from_bytes("espresso".as_bytes());
+    assert_ne!(p, r);
+}
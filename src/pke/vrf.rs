@@ -0,0 +1,155 @@
+// vrf.rs - Verifiable random function, ECVRF-EDWARDS25519-SHA512-ELL2 (RFC 9381).
+//
+// Keys, points and scalars here use the standard edwards25519 encoding (RFC 8032),
+// not `encode_point`, so proofs interoperate with other RFC 9381 implementations.
+// The secret key is a 32-byte seed, expanded as in Ed25519.
+use super::arith_n::ModNItem;
+use super::ec25519::{ECCError, EdwardsPoint, G};
+use crate::sha512::SHA512;
+
+const SUITE: u8 = 0x04;
+const ENCODE_TO_CURVE_DST: &[u8] = b"ECVRF_edwards25519_XMD:SHA-512_ELL2_NU_\x04";
+/// Challenges are 16 bytes.
+const C_LEN: usize = 16;
+pub const PROOF_LEN: usize = 32 + C_LEN + 32;
+
+pub struct EcVrf;
+
+impl EcVrf {
+    /// (x, nonce prefix) from the seed, with x clamped as in Ed25519.
+    fn expand(sk: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+        let h = SHA512::do_hash(sk);
+        let mut x: [u8; 32] = h[..32].try_into().unwrap();
+        x[0] &= 0xf8;
+        x[31] &= 0x7f;
+        x[31] |= 0x40;
+        (x, h[32..].try_into().unwrap())
+    }
+
+    pub fn public_key(sk: &[u8; 32]) -> [u8; 32] {
+        (Self::expand(sk).0 * G).to_ed25519_bytes()
+    }
+
+    /// H = encode_to_curve(PK || alpha)
+    fn encode_to_curve(pk: &[u8; 32], alpha: &[u8]) -> EdwardsPoint {
+        let mut msg = pk.to_vec();
+        msg.extend_from_slice(alpha);
+        EdwardsPoint::encode_to_curve(&msg, ENCODE_TO_CURVE_DST)
+    }
+
+    fn challenge(points: [&EdwardsPoint; 5]) -> [u8; 32] {
+        let mut data = vec![SUITE, 0x02];
+        for p in points {
+            data.extend_from_slice(&p.to_ed25519_bytes());
+        }
+        data.push(0x00);
+        let h = SHA512::do_hash(&data);
+        let mut c = [0u8; 32];
+        c[..C_LEN].copy_from_slice(&h[..C_LEN]);
+        c
+    }
+
+    /// pi = Gamma || c || s
+    pub fn prove(sk: &[u8; 32], alpha: &[u8]) -> [u8; PROOF_LEN] {
+        let (x, prefix) = Self::expand(sk);
+        let y = x * G;
+        let pk = y.to_ed25519_bytes();
+        let h = Self::encode_to_curve(&pk, alpha);
+        let gamma = x * h;
+
+        let mut nonce = prefix.to_vec();
+        nonce.extend_from_slice(&h.to_ed25519_bytes());
+        let k = ModNItem::from_bytes_wide(&SHA512::do_hash(&nonce));
+        let u = k.to_bytes() * G;
+        let v = k.to_bytes() * h;
+        let c = Self::challenge([&y, &h, &gamma, &u, &v]);
+        let s = k + ModNItem::from(c) * ModNItem::from(x);
+
+        let mut pi = [0u8; PROOF_LEN];
+        pi[..32].copy_from_slice(&gamma.to_ed25519_bytes());
+        pi[32..32 + C_LEN].copy_from_slice(&c[..C_LEN]);
+        pi[32 + C_LEN..].copy_from_slice(&s.to_bytes());
+        pi
+    }
+
+    /// (Gamma, c, s), checking that s is canonical.
+    fn decode_proof(pi: &[u8; PROOF_LEN]) -> Result<(EdwardsPoint, [u8; 32], ModNItem), ECCError> {
+        let gamma = EdwardsPoint::from_ed25519_bytes(pi[..32].try_into().unwrap())
+            .ok_or(ECCError::InvalidPoint)?;
+        let mut c = [0u8; 32];
+        c[..C_LEN].copy_from_slice(&pi[32..32 + C_LEN]);
+        let s = ModNItem::from_canonical_bytes(pi[32 + C_LEN..].try_into().unwrap())
+            .ok_or(ECCError::InvalidProof)?;
+        Ok((gamma, c, s))
+    }
+
+    /// beta = H(suite || 0x03 || 8*Gamma || 0x00). Does not verify the proof.
+    pub fn proof_to_hash(pi: &[u8; PROOF_LEN]) -> Result<[u8; 64], ECCError> {
+        let (gamma, _, _) = Self::decode_proof(pi)?;
+        let mut data = vec![SUITE, 0x03];
+        data.extend_from_slice(&gamma.double().double().double().to_ed25519_bytes());
+        data.push(0x00);
+        Ok(SHA512::do_hash(&data))
+    }
+
+    /// Verify pi for alpha under pk, and return the VRF output beta.
+    pub fn verify(pk: &[u8; 32], alpha: &[u8], pi: &[u8; PROOF_LEN]) -> Result<[u8; 64], ECCError> {
+        let y = EdwardsPoint::from_ed25519_bytes(*pk).ok_or(ECCError::InvalidPoint)?;
+        if y.is_cofactor() {
+            return Err(ECCError::SmallOrderAttack);
+        }
+        let (gamma, c, s) = Self::decode_proof(pi)?;
+        let h = Self::encode_to_curve(pk, alpha);
+        let u = s.to_bytes() * G - c * y;
+        let v = s.to_bytes() * h - c * gamma;
+        if Self::challenge([&y, &h, &gamma, &u, &v]) != c {
+            return Err(ECCError::InvalidProof);
+        }
+        Self::proof_to_hash(pi)
+    }
+}
+
+#[test]
+fn test_ecvrf() {
+    use hex::{FromHex, ToHex};
+    // RFC 9381, B.3. Examples 16 to 18.
+    let cases = [
+        (
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+            "",
+            "7d9c633ffeee27349264cf5c667579fc583b4bda63ab71d001f89c10003ab46f14adf9a3cd8b8412d9038531e865c341cafa73589b023d14311c331a9ad15ff2fb37831e00f0acaa6d73bc9997b06501",
+            "9d574bf9b8302ec0fc1e21c3ec5368269527b87b462ce36dab2d14ccf80c53cccf6758f058c5b1c856b116388152bbe509ee3b9ecfe63d93c3b4346c1fbc6c54",
+        ),
+        (
+            "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+            "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+            "72",
+            "47b327393ff2dd81336f8a2ef10339112401253b3c714eeda879f12c509072ef055b48372bb82efbdce8e10c8cb9a2f9d60e93908f93df1623ad78a86a028d6bc064dbfc75a6a57379ef855dc6733801",
+            "38561d6b77b71d30eb97a062168ae12b667ce5c28caccdf76bc88e093e4635987cd96814ce55b4689b3dd2947f80e59aac7b7675f8083865b46c89b2ce9cc735",
+        ),
+        (
+            "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+            "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+            "af82",
+            "926e895d308f5e328e7aa159c06eddbe56d06846abf5d98c2512235eaa57fdce35b46edfc655bc828d44ad09d1150f31374e7ef73027e14760d42e77341fe05467bb286cc2c9d7fde29120a0b2320d04",
+            "121b7f9b9aaaa29099fc04a94ba52784d44eac976dd1a3cca458733be5cd090a7b5fbd148444f17f8daf1fb55cb04b1ae85a626e30a54b4b0f8abf4a43314a58",
+        ),
+    ];
+    for (sk, pk, alpha, pi, beta) in cases {
+        let sk = <[u8; 32]>::from_hex(sk).unwrap();
+        let alpha = Vec::from_hex(alpha).unwrap();
+        assert_eq!(EcVrf::public_key(&sk).encode_hex::<String>(), pk);
+        let proof = EcVrf::prove(&sk, &alpha);
+        assert_eq!(proof.encode_hex::<String>(), pi);
+        let pk = <[u8; 32]>::from_hex(pk).unwrap();
+        let out = EcVrf::verify(&pk, &alpha, &proof).unwrap();
+        assert_eq!(out.encode_hex::<String>(), beta);
+
+        // Another input, or a changed proof, does not verify.
+        assert!(EcVrf::verify(&pk, b"other", &proof).is_err());
+        let mut bad = proof;
+        bad[40] ^= 1;
+        assert!(EcVrf::verify(&pk, &alpha, &bad).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+// zkp.rs - Pedersen commitments and Schnorr zero-knowledge proofs.
+//
+// Proofs are non-interactive (Fiat-Shamir): the challenge is drawn from a `Transcript`
+// of the caller's context and every point of the statement and commitment, so a proof
+// made for one statement or context does not verify for another.
+// Points are in `encode_point` form, like the rest of the Sr25519 code.
+use super::arith_n::ModNItem;
+use super::ec25519::{ECCError, EdwardsPoint, G};
+use crate::transcript::Transcript;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+const PEDERSEN_DST: &[u8] = b"ec25519-Pedersen-generator";
+const DLOG_TAG: &[u8] = b"Schnorr-dlog-proof";
+const DLEQ_TAG: &[u8] = b"Chaum-Pedersen-dleq-proof";
+
+/// Context for proving possession of a certificate key.
+pub const CONTEXT_POSSESSION: &[u8] = b"proof of possession";
+
+/// Second generator H, nobody knows log_G(H).
+pub fn pedersen_h() -> EdwardsPoint {
+    static H: OnceLock<EdwardsPoint> = OnceLock::new();
+    *H.get_or_init(|| EdwardsPoint::hash_to_curve(b"H", PEDERSEN_DST))
+}
+
+/// C = v*G + r*H, hiding v and binding the committer to it.
+///
+/// Commitments add up: commit(v1, r1) + commit(v2, r2) = commit(v1 + v2, r1 + r2).
+pub fn commit(value: ModNItem, blinding: ModNItem) -> EdwardsPoint {
+    value.to_bytes() * G + blinding.to_bytes() * pedersen_h()
+}
+
+/// Commit to `value` with a fresh blinding factor, returned for the opening.
+pub fn commit_random(value: ModNItem) -> (EdwardsPoint, ModNItem) {
+    let blinding = ModNItem::random();
+    (commit(value, blinding), blinding)
+}
+
+pub fn open(commitment: &EdwardsPoint, value: ModNItem, blinding: ModNItem) -> bool {
+    *commitment == commit(value, blinding)
+}
+
+fn challenge(tag: &[u8], context: &[u8], points: &[&EdwardsPoint]) -> ModNItem {
+    let mut t = Transcript::new(tag);
+    t.append_message(b"context", context);
+    for p in points {
+        t.append_message(b"point", &p.encode_point());
+    }
+    t.challenge_scalar(b"challenge")
+}
+
+fn decode(point: [u8; 32]) -> Result<EdwardsPoint, ECCError> {
+    EdwardsPoint::recover_point(point).ok_or(ECCError::InvalidPoint)
+}
+
+/// Proof of knowledge of x with X = x*base: (R, s), s = k + c*x.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DlogProof {
+    pub r: [u8; 32],
+    pub s: [u8; 32],
+}
+
+impl DlogProof {
+    pub fn prove(key: [u8; 32], base: &EdwardsPoint, context: &[u8]) -> Self {
+        let x: ModNItem = key.into();
+        let public = key * *base;
+        let k = ModNItem::random();
+        let r = k.to_bytes() * *base;
+        let c = challenge(DLOG_TAG, context, &[base, &public, &r]);
+        Self {
+            r: r.encode_point(),
+            s: (k + c * x).to_bytes(),
+        }
+    }
+
+    /// Check s*base = R + c*X.
+    pub fn verify(
+        &self,
+        public: [u8; 32],
+        base: &EdwardsPoint,
+        context: &[u8],
+    ) -> Result<(), ECCError> {
+        let public = decode(public)?;
+        let r = decode(self.r)?;
+        let s = ModNItem::from_canonical_bytes(self.s).ok_or(ECCError::InvalidProof)?;
+        let c = challenge(DLOG_TAG, context, &[base, &public, &r]);
+        if s.to_bytes() * *base == r + c.to_bytes() * public {
+            Ok(())
+        } else {
+            Err(ECCError::InvalidProof)
+        }
+    }
+
+    /// Prove that the holder of `pubkey` = key*G knows key, e.g. for a certificate request.
+    pub fn prove_possession(key: [u8; 32]) -> Self {
+        Self::prove(key, &G, CONTEXT_POSSESSION)
+    }
+
+    pub fn verify_possession(&self, pubkey: [u8; 32]) -> Result<(), ECCError> {
+        self.verify(pubkey, &G, CONTEXT_POSSESSION)
+    }
+}
+
+/// Proof that log_G1(X1) = log_G2(X2), without revealing it: (c, s), s = k + c*x.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DleqProof {
+    pub c: [u8; 32],
+    pub s: [u8; 32],
+}
+
+impl DleqProof {
+    pub fn prove(key: [u8; 32], g1: &EdwardsPoint, g2: &EdwardsPoint, context: &[u8]) -> Self {
+        let x: ModNItem = key.into();
+        let (x1, x2) = (key * *g1, key * *g2);
+        let k = ModNItem::random();
+        let (a1, a2) = (k.to_bytes() * *g1, k.to_bytes() * *g2);
+        let c = challenge(DLEQ_TAG, context, &[g1, g2, &x1, &x2, &a1, &a2]);
+        Self {
+            c: c.to_bytes(),
+            s: (k + c * x).to_bytes(),
+        }
+    }
+
+    /// Recompute A_i = s*G_i - c*X_i and check the challenge.
+    pub fn verify(
+        &self,
+        g1: &EdwardsPoint,
+        x1: [u8; 32],
+        g2: &EdwardsPoint,
+        x2: [u8; 32],
+        context: &[u8],
+    ) -> Result<(), ECCError> {
+        let (x1, x2) = (decode(x1)?, decode(x2)?);
+        let c = ModNItem::from_canonical_bytes(self.c).ok_or(ECCError::InvalidProof)?;
+        let s = ModNItem::from_canonical_bytes(self.s).ok_or(ECCError::InvalidProof)?;
+        let a1 = s.to_bytes() * *g1 - c.to_bytes() * x1;
+        let a2 = s.to_bytes() * *g2 - c.to_bytes() * x2;
+        let expected = challenge(DLEQ_TAG, context, &[g1, g2, &x1, &x2, &a1, &a2]);
+        if expected.to_bytes() == self.c {
+            Ok(())
+        } else {
+            Err(ECCError::InvalidProof)
+        }
+    }
+}
+
+#[test]
+fn test_pedersen() {
+    let (v1, v2) = (ModNItem::from(20u64), ModNItem::from(22u64));
+    let (c1, r1) = commit_random(v1);
+    let (c2, r2) = commit_random(v2);
+    assert!(open(&c1, v1, r1));
+    assert!(!open(&c1, v2, r1));
+    assert!(!open(&c1, v1, r2));
+    assert!(open(&(c1 + c2), ModNItem::from(42u64), r1 + r2));
+    // Hiding: the same value gives another commitment each time.
+    assert!(commit_random(v1).0 != c1);
+}
+
+#[test]
+fn test_schnorr_proofs() {
+    let x = ModNItem::random().to_bytes();
+    let pubkey = EdwardsPoint::get_pubkey(x);
+
+    let proof = DlogProof::prove_possession(x);
+    assert!(proof.verify_possession(pubkey).is_ok());
+    let other = EdwardsPoint::get_pubkey(ModNItem::random().to_bytes());
+    assert!(proof.verify_possession(other).is_err());
+    assert!(proof.verify(pubkey, &G, b"another context").is_err());
+    let mut bad = proof;
+    bad.s[0] ^= 1;
+    assert!(bad.verify_possession(pubkey).is_err());
+
+    // Same key under G and H.
+    let h = pedersen_h();
+    let (x1, x2) = ((x * G).encode_point(), (x * h).encode_point());
+    let proof = DleqProof::prove(x, &G, &h, b"dleq");
+    assert!(proof.verify(&G, x1, &h, x2, b"dleq").is_ok());
+    assert!(proof.verify(&G, x1, &h, x1, b"dleq").is_err());
+    assert!(proof.verify(&G, x1, &h, x2, b"other").is_err());
+    let y = ModNItem::random().to_bytes();
+    let proof = DleqProof::prove(y, &G, &h, b"dleq");
+    assert!(proof.verify(&G, x1, &h, x2, b"dleq").is_err());
+}
@@ -144,6 +144,8 @@ impl SHA256 {
 }
 
 impl CryptoHash<32> for SHA256 {
+    const BLOCK_SIZE: usize = 64;
+
     fn hash(data: &[u8]) -> [u8; 32] {
         Self::do_hash(data)
     }
@@ -0,0 +1,221 @@
+// SHA-512 implementation.
+// From FIPS PUB 180-4.
+use crate::common::CryptoHash;
+use std::num::Wrapping;
+
+const H0: u64 = 0x6a09e667f3bcc908;
+const H1: u64 = 0xbb67ae8584caa73b;
+const H2: u64 = 0x3c6ef372fe94f82b;
+const H3: u64 = 0xa54ff53a5f1d36f1;
+const H4: u64 = 0x510e527fade682d1;
+const H5: u64 = 0x9b05688c2b3e6c1f;
+const H6: u64 = 0x1f83d9abfb41bd6b;
+const H7: u64 = 0x5be0cd19137e2179;
+const K: [u64; 80] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0x0fc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x06ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x06f067aa72176fba,
+    0x0a637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817,
+];
+
+pub struct SHA512;
+
+impl SHA512 {
+    pub fn do_hash(data: &[u8]) -> [u8; 64] {
+        let processed = Self::preprocess(data);
+        Self::process(&processed)
+    }
+
+    fn preprocess(data: &[u8]) -> Vec<u8> {
+        let mut data = data.to_owned();
+        let length = (data.len() as u128) * 8;
+        data.push(0x80);
+
+        // Padding: make bit(L+1+K+128) % 1024
+        //               byte(L+K+16) % 128
+        let mut k = (data.len() + 16) % 128;
+        if k != 0 {
+            k = 128 - k;
+        }
+
+        // Pad zeroes.
+        if k != 0 {
+            data.append(&mut [0_u8].repeat(k));
+        }
+
+        // Put length, in bits.
+        data.append(&mut length.to_be_bytes().to_vec());
+
+        data
+    }
+
+    fn process(data: &[u8]) -> [u8; 64] {
+        // For each 1024 bit(128 bytes).
+        assert!(data.len().is_multiple_of(128));
+        let mut h = [H0, H1, H2, H3, H4, H5, H6, H7];
+
+        for chunk in data.chunks(128) {
+            let mut w = [0u64; 80];
+            // Copy data into w[0:16];
+            for j in 0..16 {
+                w[j] = u64::from_be_bytes(chunk[8 * j..8 * j + 8].try_into().unwrap());
+            }
+            // Extend words
+            for j in 16..80 {
+                let s0 = w[j - 15].rotate_right(1) ^ w[j - 15].rotate_right(8) ^ (w[j - 15] >> 7);
+                let s1 = w[j - 2].rotate_right(19) ^ w[j - 2].rotate_right(61) ^ (w[j - 2] >> 6);
+                let Wrapping(w_j) =
+                    Wrapping(w[j - 16]) + Wrapping(s0) + Wrapping(w[j - 7]) + Wrapping(s1);
+                w[j] = w_j
+            }
+
+            let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+
+            for j in 0..80 {
+                let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+                let ch = (e & f) ^ ((!e) & g);
+                let Wrapping(temp1) =
+                    Wrapping(hh) + Wrapping(s1) + Wrapping(ch) + Wrapping(K[j]) + Wrapping(w[j]);
+                let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+                let maj = (a & b) ^ (a & c) ^ (b & c);
+                let Wrapping(temp2) = Wrapping(s0) + Wrapping(maj);
+
+                hh = g;
+                g = f;
+                f = e;
+                Wrapping(e) = Wrapping(d) + Wrapping(temp1);
+                d = c;
+                c = b;
+                b = a;
+                Wrapping(a) = Wrapping(temp1) + Wrapping(temp2);
+            }
+            for (h_i, v) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+                Wrapping(*h_i) = Wrapping(*h_i) + Wrapping(v);
+            }
+        }
+        let mut ret = [0u8; 64];
+        for i in 0..8 {
+            ret[8 * i..8 * i + 8].copy_from_slice(&h[i].to_be_bytes());
+        }
+
+        ret
+    }
+}
+
+impl CryptoHash<64> for SHA512 {
+    const BLOCK_SIZE: usize = 128;
+
+    fn hash(data: &[u8]) -> [u8; 64] {
+        Self::do_hash(data)
+    }
+}
+
+#[test]
+fn sha512_test() {
+    use hex::{FromHex, ToHex};
+    let data = "abc";
+    let result = SHA512::hash(data.as_ref());
+    println!("{}", result.encode_hex::<String>());
+    assert_eq!(
+        result,
+        <[u8; 64]>::from_hex("ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f")
+            .unwrap()
+    );
+
+    let result = SHA512::hash(vec![].as_ref());
+    assert_eq!(
+        result,
+        <[u8; 64]>::from_hex("cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e")
+            .unwrap()
+    );
+
+    let zeroes = [0u8].repeat(128);
+    assert_eq!(
+        SHA512::hash(zeroes.as_ref()),
+        <[u8; 64]>::from_hex("ab942f526272e456ed68a979f50202905ca903a141ed98443567b11ef0bf25a552d639051a01be58558122c58e3de07d749ee59ded36acf0c55cd91924d6ba11")
+            .unwrap()
+    );
+
+    let zeroes = [0u8].repeat(1024);
+    assert_eq!(
+        SHA512::hash(zeroes.as_ref()),
+        <[u8; 64]>::from_hex("8efb4f73c5655351c444eb109230c556d39e2c7624e9c11abc9e3fb4b9b9254218cc5085b454a9698d085cfa92198491f07a723be4574adc70617b73eb0b6461")
+            .unwrap()
+    );
+}
@@ -0,0 +1,228 @@
+// Shamir secret sharing over GF(2^8), for key backups.
+//
+// Every byte of the secret is shared on its own random polynomial of degree k-1,
+// share i holds the polynomials at x = i.
+// Printable form: `SSS1-` followed by the hex of
+// version || set id (2) || threshold || index || data (32) || checksum (4),
+// in groups of 8 digits. The checksum is the first 4 bytes of SHA256 over the rest,
+// so typos are caught before combining.
+use crate::common::{CryptError, CryptoHash};
+use crate::galois::Galois;
+use crate::sha256::SHA256;
+use hex::{FromHex, ToHex};
+use rand::prelude::*;
+use rand_chacha::ChaCha20Rng;
+use std::fmt;
+use std::str::FromStr;
+
+const VERSION: u8 = 1;
+const PREFIX: &str = "SSS1-";
+const ENCODED_LEN: usize = 1 + 2 + 1 + 1 + 32 + 4;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Share {
+    /// Random id of one split; shares of different splits do not mix.
+    pub set_id: [u8; 2],
+    pub threshold: u8,
+    /// x of this share, never 0.
+    pub index: u8,
+    pub data: [u8; 32],
+}
+
+impl Share {
+    fn body(&self) -> Vec<u8> {
+        let mut ret = vec![VERSION];
+        ret.extend_from_slice(&self.set_id);
+        ret.push(self.threshold);
+        ret.push(self.index);
+        ret.extend_from_slice(&self.data);
+        ret
+    }
+
+    fn checksum(body: &[u8]) -> [u8; 4] {
+        SHA256::hash(body)[..4].try_into().unwrap()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = self.body();
+        let checksum = Self::checksum(&ret);
+        ret.extend_from_slice(&checksum);
+        ret
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CryptError> {
+        if bytes.len() != ENCODED_LEN || bytes[0] != VERSION {
+            return Err(CryptError::InvalidParameter);
+        }
+        let (body, checksum) = bytes.split_at(ENCODED_LEN - 4);
+        if Self::checksum(body) != checksum {
+            return Err(CryptError::InvalidChecksum);
+        }
+        let share = Share {
+            set_id: [body[1], body[2]],
+            threshold: body[3],
+            index: body[4],
+            data: body[5..].try_into().unwrap(),
+        };
+        if share.index == 0 || share.threshold == 0 {
+            return Err(CryptError::InvalidParameter);
+        }
+        Ok(share)
+    }
+}
+
+impl fmt::Display for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.to_bytes().encode_hex_upper::<String>();
+        let groups: Vec<&str> = digits
+            .as_bytes()
+            .chunks(8)
+            .map(|c| std::str::from_utf8(c).unwrap())
+            .collect();
+        write!(f, "{}{}", PREFIX, groups.join("-"))
+    }
+}
+
+impl FromStr for Share {
+    type Err = CryptError;
+    /// Dashes and whitespace are ignored, case does not matter.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let body = s
+            .strip_prefix(PREFIX)
+            .or_else(|| s.strip_prefix(&PREFIX.to_lowercase()))
+            .ok_or(CryptError::InvalidParameter)?;
+        let digits: String = body
+            .chars()
+            .filter(|c| *c != '-' && !c.is_whitespace())
+            .collect();
+        let bytes = Vec::from_hex(digits).map_err(|_| CryptError::InvalidParameter)?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// Split `secret` into `n` shares, any `k` of which recover it.
+pub fn split(secret: &[u8; 32], k: u8, n: u8) -> Result<Vec<Share>, CryptError> {
+    if k == 0 || k > n {
+        return Err(CryptError::InvalidParameter);
+    }
+    let mut rng = ChaCha20Rng::from_entropy();
+    let mut set_id = [0u8; 2];
+    rng.fill_bytes(&mut set_id);
+    // coefficients[j][b] is the j-th coefficient for byte b, coefficients[0] is the secret.
+    let mut coefficients = vec![*secret];
+    for _ in 1..k {
+        let mut c = [0u8; 32];
+        rng.fill_bytes(&mut c);
+        coefficients.push(c);
+    }
+    Ok((1..=n)
+        .map(|x| {
+            let mut data = [0u8; 32];
+            for (b, y) in data.iter_mut().enumerate() {
+                // Horner's rule
+                *y = coefficients
+                    .iter()
+                    .rev()
+                    .fold(0, |acc, c| Galois::add(Galois::mul(acc, x), c[b]));
+            }
+            Share {
+                set_id,
+                threshold: k,
+                index: x,
+                data,
+            }
+        })
+        .collect())
+}
+
+/// Recover the secret from at least `threshold` shares of one split.
+pub fn combine(shares: &[Share]) -> Result<[u8; 32], CryptError> {
+    let first = shares.first().ok_or(CryptError::InvalidParameter)?;
+    if shares.len() < first.threshold as usize {
+        return Err(CryptError::InvalidParameter);
+    }
+    let shares = &shares[..first.threshold as usize];
+    for (i, s) in shares.iter().enumerate() {
+        if s.set_id != first.set_id || s.threshold != first.threshold {
+            return Err(CryptError::InvalidParameter);
+        }
+        if shares[..i].iter().any(|t| t.index == s.index) {
+            return Err(CryptError::InvalidParameter);
+        }
+    }
+    let mut secret = [0u8; 32];
+    for s in shares {
+        // Lagrange coefficient at 0: prod x_j / (x_j - x_i), and - is + here.
+        let mut num = 1u8;
+        let mut den = 1u8;
+        for t in shares.iter().filter(|t| t.index != s.index) {
+            num = Galois::mul(num, t.index);
+            den = Galois::mul(den, Galois::add(t.index, s.index));
+        }
+        let l = Galois::mul(num, Galois::inv(den));
+        for (b, y) in secret.iter_mut().enumerate() {
+            *y = Galois::add(*y, Galois::mul(l, s.data[b]));
+        }
+    }
+    Ok(secret)
+}
+
+#[test]
+fn test_shamir() {
+    let secret =
+        <[u8; 32]>::from_hex("cce23408fda42b852fdd4bae99ed990dbe398182c1d743b3d630958af47dfd96")
+            .unwrap();
+    let shares = split(&secret, 3, 5).unwrap();
+    assert_eq!(combine(&shares).unwrap(), secret);
+    assert_eq!(
+        combine(&[shares[4].clone(), shares[0].clone(), shares[2].clone()]).unwrap(),
+        secret
+    );
+    assert!(combine(&shares[..2]).is_err());
+    assert!(combine(&[shares[0].clone(), shares[0].clone(), shares[1].clone()]).is_err());
+    // Two shares say nothing, so they do not give the secret with a fake third one.
+    let mut fake = shares[2].clone();
+    fake.data = [0; 32];
+    assert_ne!(
+        combine(&[shares[0].clone(), shares[1].clone(), fake]).unwrap(),
+        secret
+    );
+
+    // Shares of another split do not mix.
+    let other = split(&secret, 3, 5).unwrap();
+    if other[0].set_id != shares[0].set_id {
+        assert!(combine(&[other[0].clone(), shares[1].clone(), shares[2].clone()]).is_err());
+    }
+
+    assert!(split(&secret, 0, 3).is_err());
+    assert!(split(&secret, 4, 3).is_err());
+    assert_eq!(combine(&split(&secret, 1, 1).unwrap()).unwrap(), secret);
+}
+
+#[test]
+fn test_shamir_encoding() {
+    let secret = [0x5au8; 32];
+    let shares = split(&secret, 2, 3).unwrap();
+    let text: Vec<String> = shares.iter().map(|s| s.to_string()).collect();
+    assert!(text[0].starts_with("SSS1-01"));
+    let parsed: Vec<Share> = text.iter().map(|t| t.parse().unwrap()).collect();
+    assert_eq!(parsed, shares);
+    // Lower case and spaces are fine.
+    let loose = text[1]
+        .to_lowercase()
+        .replace('-', " ")
+        .replacen("sss1 ", "sss1-", 1);
+    assert_eq!(loose.parse::<Share>().unwrap(), shares[1]);
+
+    // A typo is caught by the checksum.
+    let mut typo = text[2].clone().into_bytes();
+    let pos = typo.len() - 1;
+    typo[pos] = if typo[pos] == b'0' { b'1' } else { b'0' };
+    let typo = String::from_utf8(typo).unwrap();
+    assert!(matches!(
+        typo.parse::<Share>(),
+        Err(CryptError::InvalidChecksum)
+    ));
+    assert!("SSS1-0102".parse::<Share>().is_err());
+}
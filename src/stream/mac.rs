@@ -1,36 +1,26 @@
-use hex::{decode, FromHex};
+use hex::FromHex;
 
-use crate::{common::CryptoHash, sha256::SHA256};
+use crate::transcript::Transcript;
+use crate::util::ct_eq;
 
 // Message Authentication Code.
 // HMAC based.
 pub struct HMAC;
 
-/// Not a standard HMAC but it should work.
+/// Not a standard HMAC: a keyed hash over a transcript of key, nonce and payload.
 impl HMAC {
     pub fn compute(key: &[u8; 16], nonce: &[u8; 16], payload: &[u8]) -> [u8; 32] {
-        let mut sk = vec![];
-        sk.extend_from_slice(&key[..]);
-        sk.extend_from_slice(&nonce[..]);
-        let sk = SHA256::hash(&sk);
-        let mut data = vec![];
-        data.extend_from_slice(&sk);
-        data.extend_from_slice(payload);
-
-        SHA256::hash(&data)
+        let mut t = Transcript::new(b"stream-mac");
+        t.append_message(b"key", key);
+        t.append_message(b"nonce", nonce);
+        t.append_message(b"payload", payload);
+        let mut mac = [0u8; 32];
+        t.challenge_bytes(b"mac", &mut mac);
+        mac
     }
 
     pub fn verify(key: &[u8; 16], nonce: &[u8; 16], payload: &[u8], hmac: &[u8; 32]) -> bool {
-        let mut sk = vec![];
-        sk.extend_from_slice(&key[..]);
-        sk.extend_from_slice(&nonce[..]);
-        let sk = SHA256::hash(&sk);
-        let mut data = vec![];
-        data.extend_from_slice(&sk[..]);
-        data.extend_from_slice(&payload[..]);
-        let hmac_computed = SHA256::hash(&data);
-
-        hmac_computed == *hmac
+        ct_eq(&Self::compute(key, nonce, payload), hmac)
     }
 }
 
@@ -1,7 +1,11 @@
 // AES-CTR based.
+use std::time::{Duration, Instant};
+
 use crate::{
     aes::{self, Aes},
     common::CryptError,
+    transcript::Transcript,
+    util::zero,
 };
 
 use super::mac::HMAC;
@@ -13,8 +17,13 @@ use hex::FromHex;
 ///     4 : local counter
 /// ....... : encrypted data
 /// 16 bytes: HMAC
+///
+/// Each direction of a connection has its own keys, see `derive`.
+/// Keys are replaced by a one-way ratchet on rekey, see `start_rekey`.
 #[derive(Clone, Debug)]
 pub struct AesCtrHmac {
+    /// `CLIENT_TO_SERVER` or `SERVER_TO_CLIENT`.
+    direction: &'static [u8],
     aes_key: [u8; 16],
     mac_key: [u8; 16],
     /// One serial number can only be used once.
@@ -23,6 +32,33 @@ pub struct AesCtrHmac {
     recv_serial: u64,
     counter: u32,
     key_schedule: [[u8; 16]; 11],
+    /// Traffic sent under the current keys.
+    bytes_sent: u64,
+    packets_sent: u64,
+    epoch_start: Instant,
+}
+
+/// Direction labels, so what one side sends never passes for what it receives.
+pub const CLIENT_TO_SERVER: &[u8] = b"client->server";
+pub const SERVER_TO_CLIENT: &[u8] = b"server->client";
+
+/// When a sender must move to the next keys, whichever comes first.
+#[derive(Clone, Debug)]
+pub struct RekeyPolicy {
+    pub max_bytes: u64,
+    pub max_packets: u64,
+    pub max_age: Duration,
+}
+
+impl Default for RekeyPolicy {
+    /// Also keeps the 32 bit block counter far from wrapping.
+    fn default() -> Self {
+        Self {
+            max_bytes: 1 << 30,
+            max_packets: 1 << 20,
+            max_age: Duration::from_secs(3600),
+        }
+    }
 }
 
 pub trait StreamEnc<T: Clone> {
@@ -32,16 +68,133 @@ pub trait StreamEnc<T: Clone> {
 }
 
 impl AesCtrHmac {
-    pub fn new(aes_key: &[u8; 16], mac_key: &[u8; 16], serial: u64) -> Self {
+    pub fn new(direction: &'static [u8], aes_key: &[u8; 16], mac_key: &[u8; 16]) -> Self {
         Self {
+            direction,
             aes_key: *aes_key,
             mac_key: *mac_key,
             serial: 1,
             recv_serial: 0,
             counter: 0,
             key_schedule: Aes::aes128_key_schedule(*aes_key),
+            bytes_sent: 0,
+            packets_sent: 0,
+            epoch_start: Instant::now(),
         }
     }
+
+    /// Keys of one `direction`, derived from the handshake key.
+    pub fn derive(key: &[u8; 32], direction: &'static [u8]) -> Self {
+        let mut t = Transcript::new(b"stream-keys");
+        t.append_message(b"key", key);
+        t.append_message(b"direction", direction);
+        let mut keys = [0u8; 32];
+        t.challenge_bytes(b"keys", &mut keys);
+        let state = Self::new(
+            direction,
+            keys[..16].try_into().unwrap(),
+            keys[16..].try_into().unwrap(),
+        );
+        zero(&mut keys);
+        state
+    }
+
+    /// Has the current key been used enough under `policy`?
+    pub fn needs_rekey(&self, policy: &RekeyPolicy) -> bool {
+        self.bytes_sent >= policy.max_bytes
+            || self.packets_sent >= policy.max_packets
+            || self.epoch_start.elapsed() >= policy.max_age
+    }
+
+    /// Time left before the current key gets too old under `policy`.
+    pub fn rekey_due_in(&self, policy: &RekeyPolicy) -> Duration {
+        policy.max_age.saturating_sub(self.epoch_start.elapsed())
+    }
+
+    /// Sender side: authenticate a rekey under the current keys, then ratchet.
+    ///
+    /// Returns the serial and MAC to put in `Message::ReKey`. Everything encrypted before
+    /// uses the old keys, everything after the new ones; the receiver switches when it
+    /// reaches the `ReKey` in the stream, so packets in flight still decrypt.
+    pub fn start_rekey(&mut self) -> (u64, [u8; 32]) {
+        let sealed = self.seal_control(b"rekey");
+        self.ratchet();
+        sealed
+    }
+
+    /// Receiver side: check a `Message::ReKey` under the current keys, then ratchet.
+    ///
+    /// A rekey from an earlier epoch does not verify, as the MAC key has moved on.
+    pub fn accept_rekey(&mut self, serial: u64, mac: &[u8; 32]) -> Result<(), CryptError> {
+        self.open_control(b"rekey", serial, mac)?;
+        self.ratchet();
+        Ok(())
+    }
+
+    /// Serial and MAC of a fatal alert with `code`, for `Message::Fatal`.
+    pub fn seal_fatal(&mut self, code: u32) -> (u64, [u8; 32]) {
+        self.seal_control(&fatal_label(code))
+    }
+
+    pub fn open_fatal(&mut self, code: u32, serial: u64, mac: &[u8; 32]) -> Result<(), CryptError> {
+        self.open_control(&fatal_label(code), serial, mac)
+    }
+
+    /// Serial and MAC of a close notify, for `LinkMsg::Shutdown`.
+    pub fn seal_shutdown(&mut self) -> (u64, [u8; 32]) {
+        self.seal_control(b"shutdown")
+    }
+
+    /// Check a close notify. It must directly follow the last packet received,
+    /// so dropping the tail of the stream is detected.
+    pub fn open_shutdown(&mut self, serial: u64, mac: &[u8; 32]) -> Result<(), CryptError> {
+        if serial != self.recv_serial + 1 {
+            return Err(CryptError::InvalidState);
+        }
+        self.open_control(b"shutdown", serial, mac)
+    }
+
+    /// Take a serial for a control message, and MAC `label` with it.
+    fn seal_control(&mut self, label: &[u8]) -> (u64, [u8; 32]) {
+        self.serial += 1;
+        let mac = HMAC::compute(&self.mac_key, &control_nonce(self.serial), label);
+        (self.serial, mac)
+    }
+
+    fn open_control(
+        &mut self,
+        label: &[u8],
+        serial: u64,
+        mac: &[u8; 32],
+    ) -> Result<(), CryptError> {
+        if !HMAC::verify(&self.mac_key, &control_nonce(serial), label, mac) {
+            return Err(CryptError::HMACFailed);
+        }
+        if serial <= self.recv_serial {
+            return Err(CryptError::ReplayAttack);
+        }
+        self.recv_serial = serial;
+        Ok(())
+    }
+
+    /// Derive the next keys from the current ones, overwriting them.
+    /// Old keys cannot be recovered from new ones.
+    fn ratchet(&mut self) {
+        let mut t = Transcript::new(b"stream-rekey");
+        t.append_message(b"direction", self.direction);
+        t.append_message(b"aes", &self.aes_key);
+        t.append_message(b"mac", &self.mac_key);
+        let mut next = [0u8; 32];
+        t.challenge_bytes(b"next", &mut next);
+        self.aes_key.copy_from_slice(&next[..16]);
+        self.mac_key.copy_from_slice(&next[16..]);
+        zero(&mut next);
+        self.key_schedule = Aes::aes128_key_schedule(self.aes_key);
+        self.counter = 0;
+        self.bytes_sent = 0;
+        self.packets_sent = 0;
+        self.epoch_start = Instant::now();
+    }
     pub fn encrypt_stream(&mut self, data: &[u8]) -> Vec<u8> {
         // Make header.
         let mut data = data.to_owned();
@@ -49,6 +202,8 @@ impl AesCtrHmac {
         let data_len = data.len();
         self.serial += 1;
         self.counter += 100;
+        self.bytes_sent += data_len as u64;
+        self.packets_sent += 1;
         let header = gen_counter(self.serial, data_len as u32, self.counter);
 
         let cryptmsg = Aes::encrypt::<9, 11>(&header, &self.key_schedule);
@@ -83,6 +238,7 @@ impl AesCtrHmac {
 
     /// Raw decrypt. This does not check for replay attack.
     pub fn decrypt_raw(&self, msg: &[u8]) -> Result<Vec<u8>, CryptError> {
+        check_length(msg)?;
         // Decrypt header.
         let header_orig: [u8; 16] = msg[0..16].try_into().unwrap();
         let header = Aes::decrypt::<9, 11>(&header_orig, &self.key_schedule);
@@ -119,6 +275,7 @@ impl AesCtrHmac {
 
     /// Decrypt, and check for replay attack
     pub fn decrypt_stream(&mut self, msg: &[u8]) -> Result<Vec<u8>, CryptError> {
+        check_length(msg)?;
         // Decrypt header.
         let header_orig: [u8; 16] = msg[0..16].try_into().unwrap();
         let header = Aes::decrypt::<9, 11>(&header_orig, &self.key_schedule);
@@ -159,6 +316,35 @@ impl AesCtrHmac {
     }
 }
 
+impl Drop for AesCtrHmac {
+    fn drop(&mut self) {
+        zero(&mut self.aes_key);
+        zero(&mut self.mac_key);
+        for round_key in self.key_schedule.iter_mut() {
+            zero(round_key);
+        }
+    }
+}
+
+/// A packet is a header, whole blocks of data, and a MAC; anything else is refused
+/// before it is sliced.
+fn check_length(msg: &[u8]) -> Result<(), CryptError> {
+    if msg.len() < 48 || !(msg.len() - 48).is_multiple_of(16) {
+        return Err(CryptError::InvalidCipherText);
+    }
+    Ok(())
+}
+
+/// Nonce of a control message MAC, distinct from any packet header, whose size is never
+/// `u32::MAX` with a zero counter.
+fn control_nonce(serial: u64) -> [u8; 16] {
+    gen_counter(serial, u32::MAX, 0)
+}
+
+fn fatal_label(code: u32) -> Vec<u8> {
+    [b"fatal".as_slice(), &code.to_le_bytes()].concat()
+}
+
 fn gen_counter(serial: u64, size: u32, counter: u32) -> [u8; 16] {
     let mut ret = [0u8; 16];
     let serial: [u8; 8] = serial.to_le_bytes();
@@ -188,8 +374,7 @@ fn xor(a: &[u8], b: &[u8]) -> [u8; 16] {
 fn test_stream() {
     let aes_key = <[u8; 16]>::from_hex("277c6a6de132a226fefb1c469df53446").unwrap();
     let mac_key = <[u8; 16]>::from_hex("240dc26508f0c9fc65f83138782ad919").unwrap();
-    let serial = 1;
-    let mut state = AesCtrHmac::new(&aes_key, &mac_key, serial);
+    let mut state = AesCtrHmac::new(CLIENT_TO_SERVER, &aes_key, &mac_key);
     let data = "abcdefghijklmnopqrstuvwxyz01234567890!@#$%^&*()".as_bytes();
     let encrypted = state.encrypt_stream(data);
     let ans = state.decrypt_stream(&encrypted).unwrap();
@@ -198,3 +383,126 @@ fn test_stream() {
     assert_eq!(ans, String::from_utf8(data.to_owned()).unwrap());
     println!("{}", ans);
 }
+
+#[test]
+fn test_rekey() {
+    let aes_key = <[u8; 16]>::from_hex("277c6a6de132a226fefb1c469df53446").unwrap();
+    let mac_key = <[u8; 16]>::from_hex("240dc26508f0c9fc65f83138782ad919").unwrap();
+    let mut sender = AesCtrHmac::new(CLIENT_TO_SERVER, &aes_key, &mac_key);
+    let mut receiver = sender.clone();
+    let policy = RekeyPolicy {
+        max_bytes: 100,
+        max_packets: 3,
+        max_age: Duration::from_secs(3600),
+    };
+
+    // Sent before the rekey, received after it was issued.
+    let in_flight = sender.encrypt_stream(b"old key");
+    assert!(!sender.needs_rekey(&policy));
+    let old = sender.clone();
+    let (serial, mac) = sender.start_rekey();
+    let after = sender.encrypt_stream(b"new key");
+    assert_ne!(sender.aes_key, old.aes_key);
+    assert_ne!(sender.mac_key, old.mac_key);
+
+    assert_eq!(receiver.decrypt_stream(&in_flight).unwrap(), b"old key");
+    assert!(receiver.decrypt_stream(&after).is_err());
+    receiver.accept_rekey(serial, &mac).unwrap();
+    assert_eq!(receiver.decrypt_stream(&after).unwrap(), b"new key");
+    // Neither the old rekey nor old packets pass under the new keys.
+    assert!(receiver.accept_rekey(serial, &mac).is_err());
+    assert!(receiver
+        .decrypt_stream(&old.clone().encrypt_stream(b"x"))
+        .is_err());
+
+    // Triggers.
+    sender.encrypt_stream(b"a");
+    sender.encrypt_stream(b"b");
+    assert!(sender.needs_rekey(&policy));
+    sender.start_rekey();
+    assert!(!sender.needs_rekey(&policy));
+    sender.encrypt_stream(&[0u8; 100]);
+    assert!(sender.needs_rekey(&policy));
+    let policy = RekeyPolicy {
+        max_age: Duration::ZERO,
+        ..RekeyPolicy::default()
+    };
+    assert!(sender.needs_rekey(&policy));
+    assert_eq!(sender.rekey_due_in(&policy), Duration::ZERO);
+}
+
+#[test]
+fn test_alerts() {
+    let aes_key = <[u8; 16]>::from_hex("277c6a6de132a226fefb1c469df53446").unwrap();
+    let mac_key = <[u8; 16]>::from_hex("240dc26508f0c9fc65f83138782ad919").unwrap();
+    let mut sender = AesCtrHmac::new(CLIENT_TO_SERVER, &aes_key, &mac_key);
+    let mut receiver = sender.clone();
+
+    let (serial, mac) = sender.seal_fatal(3);
+    assert!(matches!(
+        receiver.clone().open_fatal(2, serial, &mac),
+        Err(CryptError::HMACFailed)
+    ));
+    receiver.open_fatal(3, serial, &mac).unwrap();
+    assert!(matches!(
+        receiver.open_fatal(3, serial, &mac),
+        Err(CryptError::ReplayAttack)
+    ));
+
+    // A close notify must follow the last packet, else the tail was dropped.
+    let packet = sender.encrypt_stream(b"last");
+    let (serial, mac) = sender.seal_shutdown();
+    assert!(matches!(
+        receiver.clone().open_shutdown(serial, &mac),
+        Err(CryptError::InvalidState)
+    ));
+    receiver.decrypt_stream(&packet).unwrap();
+    receiver.open_shutdown(serial, &mac).unwrap();
+}
+
+#[test]
+fn test_directions() {
+    let key = [5u8; 32];
+    let mut client_send = AesCtrHmac::derive(&key, CLIENT_TO_SERVER);
+    let client_recv = AesCtrHmac::derive(&key, SERVER_TO_CLIENT);
+    let mut server_recv = AesCtrHmac::derive(&key, CLIENT_TO_SERVER);
+    assert_ne!(client_send.aes_key, client_recv.aes_key);
+    assert_ne!(client_send.mac_key, client_recv.mac_key);
+
+    // A frame reflected back to its sender does not open.
+    let packet = client_send.encrypt_stream(b"to server");
+    assert!(client_recv.clone().decrypt_stream(&packet).is_err());
+    assert_eq!(server_recv.decrypt_stream(&packet).unwrap(), b"to server");
+    let (serial, mac) = client_send.seal_fatal(1);
+    assert!(client_recv.clone().open_fatal(1, serial, &mac).is_err());
+    server_recv.open_fatal(1, serial, &mac).unwrap();
+
+    // Both directions ratchet apart, even from equal keys.
+    let mut a = AesCtrHmac::new(CLIENT_TO_SERVER, &[1u8; 16], &[2u8; 16]);
+    let mut b = AesCtrHmac::new(SERVER_TO_CLIENT, &[1u8; 16], &[2u8; 16]);
+    a.start_rekey();
+    b.start_rekey();
+    assert_ne!(a.aes_key, b.aes_key);
+}
+
+#[test]
+fn test_short_payload() {
+    let mut state = AesCtrHmac::derive(&[5u8; 32], CLIENT_TO_SERVER);
+    let empty = state.encrypt_stream(b"");
+    assert_eq!(empty.len(), 48);
+    for len in [0, 16, 47] {
+        assert!(matches!(
+            state.decrypt_raw(&empty[..len]),
+            Err(CryptError::InvalidCipherText)
+        ));
+        assert!(matches!(
+            state.decrypt_stream(&empty[..len]),
+            Err(CryptError::InvalidCipherText)
+        ));
+    }
+    assert!(matches!(
+        state.decrypt_stream(&[empty.clone(), vec![0u8; 5]].concat()),
+        Err(CryptError::InvalidCipherText)
+    ));
+    assert_eq!(state.decrypt_stream(&empty).unwrap(), b"");
+}
@@ -21,10 +21,13 @@ use rand::SeedableRng;
 use rand_chacha::ChaCha20Rng;
 use tokio::task::JoinHandle;
 
+use crate::comm::authorized_clients::AuthorizedClients;
+use crate::comm::known_peers::KnownPeers;
 use crate::comm::ws::WsClient;
 use crate::comm::ws::WsServer;
 use crate::pke::ec25519::G;
-use crate::pke::eddsa::Sr25519;
+use crate::pke::eddsa::{Sr25519, CONTEXT_MESSAGE};
+use crate::wire::cert::TrustStore;
 use crate::wire::message;
 
 #[derive(Debug, Clone)]
@@ -148,6 +151,15 @@ impl Application for UI {
                     self.running = true;
                     let check_key = <[u8; 32]>::from_hex(&self.check_key).ok();
                     eprintln!("start pressed");
+                    self.last_error = None;
+                    let trust = match TrustStore::load(TrustStore::default_dir()) {
+                        Ok(trust) => trust,
+                        Err(e) => {
+                            self.running = false;
+                            self.last_error = Some(format!("cannot load trust store: {}", e));
+                            return Command::none();
+                        }
+                    };
                     // Client or server?
                     match self.page {
                         UIPage::Connect => {
@@ -158,7 +170,18 @@ impl Application for UI {
                                 self.key,
                                 sk,
                                 check_key,
+                                trust,
                             );
+                            if check_key.is_none() {
+                                match KnownPeers::load(KnownPeers::default_path()) {
+                                    Ok(peers) => client.set_known_peers(peers),
+                                    Err(e) => {
+                                        self.running = false;
+                                        self.last_error = Some(e.to_string());
+                                        return Command::none();
+                                    }
+                                }
+                            }
                             return Command::perform(
                                 async {
                                     tokio::spawn(async move {
@@ -177,7 +200,28 @@ impl Application for UI {
                                 None,
                                 self.key,
                                 check_key,
+                                trust,
                             );
+                            let authorized = AuthorizedClients::default_path();
+                            if authorized.is_file() {
+                                match AuthorizedClients::load(authorized) {
+                                    Ok(clients) => server.set_authorized_clients(clients),
+                                    Err(e) => {
+                                        self.running = false;
+                                        self.last_error = Some(e.to_string());
+                                        return Command::none();
+                                    }
+                                }
+                            } else if check_key.is_none() {
+                                match KnownPeers::load(KnownPeers::default_path()) {
+                                    Ok(peers) => server.set_known_peers(peers),
+                                    Err(e) => {
+                                        self.running = false;
+                                        self.last_error = Some(e.to_string());
+                                        return Command::none();
+                                    }
+                                }
+                            }
 
                             return Command::perform(
                                 async {
@@ -238,7 +282,7 @@ impl Application for UI {
                 let pubkey = pubkey.encode_point();
                 let pubkey = pubkey.encode_hex::<String>();
                 self.signature_state = SignCheckState::Wait;
-                let sig = Sr25519::sign(self.sign_key, self.signature_message.as_bytes());
+                let sig = Sr25519::sign_with_context(self.sign_key, CONTEXT_MESSAGE, self.signature_message.as_bytes());
                 self.signature_value = format!(
                     "{}{}",
                     sig.0.encode_hex::<String>(),
@@ -255,7 +299,7 @@ impl Application for UI {
                         a[i] = sig[i];
                         b[i] = sig[i+32];
                     }
-                    self.signature_state = if Sr25519::verify(pubkey, self.signature_message.as_bytes(), a, b).unwrap_or(false)
+                    self.signature_state = if Sr25519::verify_with_context(pubkey, CONTEXT_MESSAGE, self.signature_message.as_bytes(), a, b).unwrap_or(false)
                     {SignCheckState::Valid} else {SignCheckState::Invalid};
 
                 } else {
@@ -343,6 +387,9 @@ impl Application for UI {
                     UIMessage::KeyChanged(x)
                 }));
                 window = window.push(vertical_space(Length::Fill));
+                if let Some(e) = &self.last_error {
+                    window = window.push(text(e).style(Color::from_rgb(0.8, 0.0, 0.0)));
+                }
                 window = window.push(
                     button(
                         text(if self.running { "Stop" } else { "Connect" })
@@ -394,6 +441,9 @@ impl Application for UI {
                     UIMessage::KeyChanged(x)
                 }));
                 window = window.push(vertical_space(Length::Fill));
+                if let Some(e) = &self.last_error {
+                    window = window.push(text(e).style(Color::from_rgb(0.8, 0.0, 0.0)));
+                }
                 window = window.push(
                     button(
                         text(if self.running { "Stop" } else { "Serve" })
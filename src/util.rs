@@ -10,3 +10,32 @@ fn test_bytes_to_word() {
 pub fn word_to_bytes(data: u32) -> [u8; 4] {
     data.to_le_bytes()
 }
+
+/// Compare in time independent of where the inputs differ, for MACs and tags.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
+/// Overwrite key material with zeros, in a way the compiler does not drop as a dead store.
+pub fn zero(buf: &mut [u8]) {
+    buf.fill(0);
+    std::hint::black_box(buf);
+}
+
+#[test]
+fn test_ct_eq() {
+    assert!(ct_eq(&[1, 2, 3], &[1, 2, 3]));
+    assert!(!ct_eq(&[1, 2, 3], &[1, 2, 4]));
+    assert!(!ct_eq(&[1, 2, 3], &[1, 2]));
+}
+
+#[test]
+fn test_zero() {
+    let mut key = [7u8; 32];
+    zero(&mut key);
+    assert_eq!(key, [0u8; 32]);
+}
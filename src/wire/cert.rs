@@ -0,0 +1,564 @@
+// cert - Issuing and checking certificates.
+//
+// A certificate binds an exchange (identity) key and a sign key to a subject name;
+// the issuer signs its postcard bytes with `signature` set to `None`, under
+// `CONTEXT_CERTIFICATE`. Postcard has a single encoding for each value, so this is canonical.
+// A chain starts with the certificate of the handshake identity, and each next one
+// certifies the sign key of the previous issuer, up to an issuer among the trusted roots.
+use super::message::{
+    Certificate, RevocationList, CERTIFICATE_VERSION, KEY_USAGE_CERT_SIGN, KEY_USAGE_EXCHANGE,
+    KEY_USAGE_SIGN,
+};
+use crate::pke::ec25519::EdwardsPoint;
+use crate::pke::eddsa::{Sr25519, CONTEXT_CERTIFICATE};
+use anyhow::anyhow;
+use hex::FromHex;
+use postcard::to_allocvec;
+use rand::prelude::*;
+use rand_chacha::ChaCha20Rng;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Extensions this implementation understands; other critical ones are rejected.
+pub const KNOWN_EXTENSIONS: &[&str] = &[];
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CertError {
+    #[error("no certificate chain.")]
+    NoChain,
+    #[error("certificate does not match the identity.")]
+    IdentityMismatch,
+    #[error("certificate is not signed.")]
+    Unsigned,
+    #[error("invalid certificate signature.")]
+    InvalidSignature,
+    #[error("certificate chain is broken.")]
+    BrokenChain,
+    #[error("certificate chain does not end at a trusted root.")]
+    UntrustedRoot,
+    #[error("unsupported certificate version.")]
+    UnsupportedVersion,
+    #[error("certificate is not valid yet.")]
+    NotYetValid,
+    #[error("certificate has expired.")]
+    Expired,
+    #[error("certificate key usage does not allow this.")]
+    KeyUsage,
+    #[error("issuer is not a CA.")]
+    NotCA,
+    #[error("certificate chain is longer than a path length constraint.")]
+    PathLength,
+    #[error("unknown critical extension.")]
+    UnknownCriticalExtension,
+    #[error("certificate or identity key is revoked.")]
+    Revoked,
+    #[error("revocation list is out of date.")]
+    StaleRevocationList,
+}
+
+/// Seconds since the unix epoch, the clock used by the handshake.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl Certificate {
+    /// Unsigned end-entity certificate with a random serial, for identity and signing.
+    pub fn new(
+        exchange_pubkey: [u8; 32],
+        sign_pubkey: [u8; 32],
+        subject: &str,
+        not_before: u64,
+        not_after: u64,
+    ) -> Self {
+        Certificate {
+            version: CERTIFICATE_VERSION,
+            serial: ChaCha20Rng::from_entropy().next_u64(),
+            subject: subject.to_owned(),
+            issuer: String::new(),
+            not_before,
+            not_after,
+            key_usage: KEY_USAGE_EXCHANGE | KEY_USAGE_SIGN,
+            ca: false,
+            path_len: None,
+            extensions: vec![],
+            exchange_pubkey,
+            sign_pubkey,
+            signature: None,
+        }
+    }
+
+    /// Sign with `issuer_key`, the sign key of the certificate named `issuer`.
+    pub fn sign(&mut self, issuer_key: [u8; 32], issuer: &str) {
+        self.issuer = issuer.to_owned();
+        let (r, s) = Sr25519::sign_with_context(issuer_key, CONTEXT_CERTIFICATE, &self.tbs());
+        self.signature = Some((EdwardsPoint::get_pubkey(issuer_key), r, s));
+    }
+
+    /// Bytes covered by the signature.
+    fn tbs(&self) -> Vec<u8> {
+        let mut cert = self.clone();
+        cert.signature = None;
+        to_allocvec(&cert).unwrap()
+    }
+
+    /// Sign pubkey of the issuer, if signed.
+    pub fn issuer_key(&self) -> Option<[u8; 32]> {
+        self.signature.map(|(issuer, _, _)| issuer)
+    }
+
+    pub fn verify_signature(&self) -> Result<(), CertError> {
+        let (issuer, r, s) = self.signature.ok_or(CertError::Unsigned)?;
+        match Sr25519::verify_with_context(issuer, CONTEXT_CERTIFICATE, &self.tbs(), r, s) {
+            Ok(true) => Ok(()),
+            _ => Err(CertError::InvalidSignature),
+        }
+    }
+
+    /// Version, validity at `now` and extensions; constraints between certificates
+    /// are checked by `verify_chain`.
+    pub fn check(&self, now: u64) -> Result<(), CertError> {
+        if self.version != CERTIFICATE_VERSION {
+            return Err(CertError::UnsupportedVersion);
+        }
+        if now < self.not_before {
+            return Err(CertError::NotYetValid);
+        }
+        if now > self.not_after {
+            return Err(CertError::Expired);
+        }
+        if self
+            .extensions
+            .iter()
+            .any(|e| e.critical && !KNOWN_EXTENSIONS.contains(&e.id.as_str()))
+        {
+            return Err(CertError::UnknownCriticalExtension);
+        }
+        Ok(())
+    }
+}
+
+/// Check that `chain` certifies `identity` up to one of `roots` (trusted sign pubkeys) at time `now`.
+///
+/// The first certificate must allow the exchange key as an identity; every issuer in the
+/// chain must be a CA allowed to sign certificates, within its path length.
+pub fn verify_chain(
+    identity: &[u8; 32],
+    chain: &[Certificate],
+    roots: &[[u8; 32]],
+    now: u64,
+) -> Result<(), CertError> {
+    let leaf = chain.first().ok_or(CertError::NoChain)?;
+    if leaf.exchange_pubkey != *identity {
+        return Err(CertError::IdentityMismatch);
+    }
+    if leaf.key_usage & KEY_USAGE_EXCHANGE == 0 {
+        return Err(CertError::KeyUsage);
+    }
+    for (i, cert) in chain.iter().enumerate() {
+        cert.check(now)?;
+        cert.verify_signature()?;
+        if i > 0 {
+            if !cert.ca {
+                return Err(CertError::NotCA);
+            }
+            if cert.key_usage & KEY_USAGE_CERT_SIGN == 0 {
+                return Err(CertError::KeyUsage);
+            }
+            // CA certificates between this one and the leaf.
+            if matches!(cert.path_len, Some(n) if (n as usize) < i - 1) {
+                return Err(CertError::PathLength);
+            }
+        }
+        let issuer = cert.issuer_key().ok_or(CertError::Unsigned)?;
+        if roots.contains(&issuer) {
+            return Ok(());
+        }
+        match chain.get(i + 1) {
+            Some(next) if next.sign_pubkey == issuer && next.subject == cert.issuer => {}
+            Some(_) => return Err(CertError::BrokenChain),
+            None => return Err(CertError::UntrustedRoot),
+        }
+    }
+    Err(CertError::UntrustedRoot)
+}
+
+/// Trusted roots and the revocation lists to check chains against.
+#[derive(Clone, Debug, Default)]
+pub struct TrustStore {
+    /// Sign pubkeys of trusted roots.
+    pub roots: Vec<[u8; 32]>,
+    revocations: Vec<RevocationList>,
+}
+
+impl TrustStore {
+    pub fn new(roots: Vec<[u8; 32]>) -> Self {
+        Self {
+            roots,
+            revocations: vec![],
+        }
+    }
+
+    /// Add a list after checking its signature. It counts only if its issuer is a root
+    /// or an issuer in the chain being checked.
+    pub fn add_revocation_list(&mut self, crl: RevocationList) -> Result<(), CertError> {
+        crl.verify_signature()?;
+        self.revocations.push(crl);
+        Ok(())
+    }
+
+    /// ~/.config/sst/, next to the config file.
+    pub fn default_dir() -> PathBuf {
+        let home = std::env::var("HOME").unwrap_or("/".to_owned());
+        #[cfg(target_os = "windows")]
+        let dir = Path::new(&home).join("sst/");
+        #[cfg(not(target_os = "windows"))]
+        let dir = Path::new(&home).join(".config/sst/");
+        dir
+    }
+
+    /// Read the roots from `dir/roots`, one hex sign pubkey per line, `#` starts a comment,
+    /// and the revocation lists in `dir/crl/`.
+    ///
+    /// Without a roots file no certificates are required, and `None` is returned.
+    pub fn load<P: AsRef<Path>>(dir: P) -> anyhow::Result<Option<Self>> {
+        let dir = dir.as_ref();
+        let path = dir.join("roots");
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let mut roots = vec![];
+        for (i, line) in fs::read_to_string(&path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let root = <[u8; 32]>::from_hex(line)
+                .map_err(|_| anyhow!("{} line {} is malformed.", path.display(), i + 1))?;
+            roots.push(root);
+        }
+        let mut trust = Self::new(roots);
+        let crl = dir.join("crl");
+        if crl.is_dir() {
+            trust.load_revocation_lists(crl)?;
+        }
+        Ok(Some(trust))
+    }
+
+    /// Load every file in `dir` as a revocation list.
+    pub fn load_revocation_lists<P: AsRef<Path>>(&mut self, dir: P) -> anyhow::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() {
+                let crl = RevocationList::load(&path)?;
+                self.add_revocation_list(crl)
+                    .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `verify_chain` against the roots, then revocation of every certificate and of `identity`.
+    ///
+    /// A revocation list which applies must be current, otherwise nothing is accepted.
+    pub fn verify(
+        &self,
+        identity: &[u8; 32],
+        chain: &[Certificate],
+        now: u64,
+    ) -> Result<(), CertError> {
+        verify_chain(identity, chain, &self.roots, now)?;
+        let issuers: Vec<[u8; 32]> = chain.iter().filter_map(|c| c.issuer_key()).collect();
+        for crl in &self.revocations {
+            let signer = crl.issuer_key().ok_or(CertError::Unsigned)?;
+            if !issuers.contains(&signer) && !self.roots.contains(&signer) {
+                continue;
+            }
+            if !crl.is_current(now) {
+                return Err(CertError::StaleRevocationList);
+            }
+            if crl.identity_keys.contains(identity) {
+                return Err(CertError::Revoked);
+            }
+            let revoked = chain.iter().any(|c| {
+                c.issuer_key() == Some(signer)
+                    && c.issuer == crl.issuer
+                    && crl.serials.contains(&c.serial)
+            });
+            if revoked {
+                return Err(CertError::Revoked);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn random_key() -> [u8; 32] {
+    let mut k = [0u8; 32];
+    ChaCha20Rng::from_entropy().fill_bytes(&mut k);
+    k
+}
+
+#[cfg(test)]
+fn ca_certificate(
+    sign_key: [u8; 32],
+    name: &str,
+    issuer_key: [u8; 32],
+    issuer: &str,
+) -> Certificate {
+    let pk = EdwardsPoint::get_pubkey;
+    let mut cert = Certificate::new(pk(random_key()), pk(sign_key), name, 0, u64::MAX);
+    cert.ca = true;
+    cert.key_usage = KEY_USAGE_CERT_SIGN;
+    cert.sign(issuer_key, issuer);
+    cert
+}
+
+#[test]
+fn test_certificate_chain() {
+    let now = 1_700_000_000;
+    let (root, ca, leaf_sign, leaf_id) = (random_key(), random_key(), random_key(), random_key());
+    let pk = EdwardsPoint::get_pubkey;
+    let root_pk = pk(root);
+    let ca_cert = ca_certificate(ca, "ca", root, "root");
+    let mut leaf = Certificate::new(pk(leaf_id), pk(leaf_sign), "example.com", 0, u64::MAX);
+    leaf.sign(ca, "ca");
+    let chain = vec![leaf.clone(), ca_cert.clone()];
+
+    assert!(verify_chain(&pk(leaf_id), &chain, &[root_pk], now).is_ok());
+    // A trusted intermediate is enough.
+    assert!(verify_chain(&pk(leaf_id), &chain[..1], &[pk(ca)], now).is_ok());
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &chain[..1], &[root_pk], now),
+        Err(CertError::UntrustedRoot)
+    );
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &chain, &[pk(random_key())], now),
+        Err(CertError::UntrustedRoot)
+    );
+    assert_eq!(
+        verify_chain(&pk(random_key()), &chain, &[root_pk], now),
+        Err(CertError::IdentityMismatch)
+    );
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &[], &[root_pk], now),
+        Err(CertError::NoChain)
+    );
+
+    // Out of order, or with a certificate of another key in between.
+    let other = ca_certificate(random_key(), "ca", root, "root");
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &[leaf.clone(), other], &[root_pk], now),
+        Err(CertError::BrokenChain)
+    );
+    // Right key, wrong name.
+    let renamed = ca_certificate(ca, "other ca", root, "root");
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &[leaf.clone(), renamed], &[root_pk], now),
+        Err(CertError::BrokenChain)
+    );
+
+    // Tampered or unsigned certificates.
+    let mut forged = leaf.clone();
+    forged.sign_pubkey = pk(random_key());
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &[forged, ca_cert.clone()], &[root_pk], now),
+        Err(CertError::InvalidSignature)
+    );
+    let mut forged = leaf.clone();
+    forged.not_after -= 1;
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &[forged, ca_cert.clone()], &[root_pk], now),
+        Err(CertError::InvalidSignature)
+    );
+    let mut unsigned = leaf;
+    unsigned.signature = None;
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &[unsigned, ca_cert], &[root_pk], now),
+        Err(CertError::Unsigned)
+    );
+}
+
+#[test]
+fn test_certificate_constraints() {
+    let now = 1_700_000_000;
+    let pk = EdwardsPoint::get_pubkey;
+    let (root, ca, leaf_id) = (random_key(), random_key(), random_key());
+    let roots = [pk(root)];
+    let ca_cert = ca_certificate(ca, "ca", root, "root");
+    let leaf = |f: &dyn Fn(&mut Certificate)| {
+        let mut cert = Certificate::new(pk(leaf_id), pk(random_key()), "leaf", now - 10, now + 10);
+        f(&mut cert);
+        cert.sign(ca, "ca");
+        vec![cert, ca_cert.clone()]
+    };
+
+    // Validity, against the supplied clock.
+    let chain = leaf(&|_| {});
+    assert!(verify_chain(&pk(leaf_id), &chain, &roots, now + 10).is_ok());
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &chain, &roots, now - 11),
+        Err(CertError::NotYetValid)
+    );
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &chain, &roots, now + 11),
+        Err(CertError::Expired)
+    );
+
+    // Key usage and version.
+    let chain = leaf(&|c| c.key_usage = KEY_USAGE_SIGN);
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &chain, &roots, now),
+        Err(CertError::KeyUsage)
+    );
+    let chain = leaf(&|c| c.version = 2);
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &chain, &roots, now),
+        Err(CertError::UnsupportedVersion)
+    );
+
+    // Extensions: unknown ones are ignored unless critical.
+    let ext = |critical| super::message::Extension {
+        id: "x-test".to_owned(),
+        critical,
+        value: vec![1, 2, 3],
+    };
+    let chain = leaf(&|c| c.extensions.push(ext(false)));
+    assert!(verify_chain(&pk(leaf_id), &chain, &roots, now).is_ok());
+    let chain = leaf(&|c| c.extensions.push(ext(true)));
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &chain, &roots, now),
+        Err(CertError::UnknownCriticalExtension)
+    );
+
+    // Issuers must be CAs.
+    let mut not_ca = ca_certificate(ca, "ca", root, "root");
+    not_ca.ca = false;
+    not_ca.sign(root, "root");
+    let mut chain = leaf(&|_| {});
+    chain[1] = not_ca;
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &chain, &roots, now),
+        Err(CertError::NotCA)
+    );
+
+    // Path length: root -> ca1 (path_len 0) -> ca2 -> leaf has one CA below ca1.
+    let ca2 = random_key();
+    let mut ca1_cert = ca_certificate(ca, "ca", root, "root");
+    ca1_cert.path_len = Some(0);
+    ca1_cert.sign(root, "root");
+    let ca2_cert = ca_certificate(ca2, "ca2", ca, "ca");
+    let mut leaf_cert = Certificate::new(pk(leaf_id), pk(random_key()), "leaf", 0, u64::MAX);
+    leaf_cert.sign(ca2, "ca2");
+    let chain = vec![leaf_cert, ca2_cert, ca1_cert.clone()];
+    assert_eq!(
+        verify_chain(&pk(leaf_id), &chain, &roots, now),
+        Err(CertError::PathLength)
+    );
+    ca1_cert.path_len = Some(1);
+    ca1_cert.sign(root, "root");
+    let chain = vec![chain[0].clone(), chain[1].clone(), ca1_cert];
+    assert!(verify_chain(&pk(leaf_id), &chain, &roots, now).is_ok());
+}
+
+#[test]
+fn test_revocation() {
+    let now = 1_700_000_000;
+    let pk = EdwardsPoint::get_pubkey;
+    let (root, ca, leaf_id) = (random_key(), random_key(), random_key());
+    let ca_cert = ca_certificate(ca, "ca", root, "root");
+    let mut leaf = Certificate::new(pk(leaf_id), pk(random_key()), "leaf", 0, u64::MAX);
+    leaf.sign(ca, "ca");
+    let chain = vec![leaf.clone(), ca_cert.clone()];
+    let crl = |issuer: &str, key, serials: Vec<u64>, ids: Vec<[u8; 32]>| {
+        let mut crl = RevocationList::new(issuer, now - 100, now + 100);
+        crl.serials = serials;
+        crl.identity_keys = ids;
+        crl.sign(key);
+        crl
+    };
+    let store = |crls: Vec<RevocationList>| {
+        let mut store = TrustStore::new(vec![pk(root)]);
+        for c in crls {
+            store.add_revocation_list(c).unwrap();
+        }
+        store
+    };
+
+    let other_serial = leaf.serial.wrapping_add(1);
+    assert!(store(vec![crl("ca", ca, vec![other_serial], vec![])])
+        .verify(&pk(leaf_id), &chain, now)
+        .is_ok());
+    // By serial, from the issuer of the leaf or of the CA certificate.
+    assert_eq!(
+        store(vec![crl("ca", ca, vec![leaf.serial], vec![])]).verify(&pk(leaf_id), &chain, now),
+        Err(CertError::Revoked)
+    );
+    assert_eq!(
+        store(vec![crl("root", root, vec![ca_cert.serial], vec![])]).verify(
+            &pk(leaf_id),
+            &chain,
+            now
+        ),
+        Err(CertError::Revoked)
+    );
+    // The same serial from another issuer does not count.
+    assert!(store(vec![crl("root", root, vec![leaf.serial], vec![])])
+        .verify(&pk(leaf_id), &chain, now)
+        .is_ok());
+    // By identity key.
+    assert_eq!(
+        store(vec![crl("root", root, vec![], vec![pk(leaf_id)])]).verify(&pk(leaf_id), &chain, now),
+        Err(CertError::Revoked)
+    );
+    // Lists from unrelated keys are ignored.
+    assert!(
+        store(vec![crl("x", random_key(), vec![], vec![pk(leaf_id)])])
+            .verify(&pk(leaf_id), &chain, now)
+            .is_ok()
+    );
+    // A stale list fails closed.
+    assert_eq!(
+        store(vec![crl("ca", ca, vec![], vec![])]).verify(&pk(leaf_id), &chain, now + 101),
+        Err(CertError::StaleRevocationList)
+    );
+    // Forged lists are refused when added.
+    let mut forged = crl("ca", ca, vec![leaf.serial], vec![]);
+    forged.serials.clear();
+    assert_eq!(
+        TrustStore::new(vec![]).add_revocation_list(forged),
+        Err(CertError::InvalidSignature)
+    );
+}
+
+#[test]
+fn test_trust_store_load() {
+    use hex::ToHex;
+    let dir = std::env::temp_dir().join(format!("trust-test-{}", std::process::id()));
+    fs::create_dir_all(dir.join("crl")).unwrap();
+    assert!(TrustStore::load(&dir).unwrap().is_none());
+
+    let root = random_key();
+    let root_pk = EdwardsPoint::get_pubkey(root);
+    fs::write(
+        dir.join("roots"),
+        format!("# roots\n\n{}\n", root_pk.encode_hex::<String>()),
+    )
+    .unwrap();
+    let mut crl = RevocationList::new("root", 100, 200);
+    crl.identity_keys = vec![[9u8; 32]];
+    crl.sign(root);
+    crl.save(dir.join("crl").join("root.crl")).unwrap();
+    let trust = TrustStore::load(&dir).unwrap().unwrap();
+    assert_eq!(trust.roots, vec![root_pk]);
+    assert_eq!(trust.revocations, vec![crl]);
+
+    fs::write(dir.join("roots"), "not a key\n").unwrap();
+    assert!(TrustStore::load(&dir).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,97 @@
+// crl - Certificate revocation lists.
+//
+// A list is signed by the sign key of an issuer under `CONTEXT_REVOCATION`, over its
+// postcard bytes with `signature` set to `None`. It revokes serials of certificates
+// signed by that key, and identity keys outright.
+// Lists are stored on disk in postcard form.
+use super::cert::CertError;
+use super::message::{RevocationList, CERTIFICATE_VERSION};
+use crate::pke::ec25519::EdwardsPoint;
+use crate::pke::eddsa::{Sr25519, CONTEXT_REVOCATION};
+use anyhow::Result;
+use postcard::{from_bytes, to_allocvec};
+use std::{fs, path::Path};
+
+impl RevocationList {
+    pub fn new(issuer: &str, this_update: u64, next_update: u64) -> Self {
+        RevocationList {
+            version: CERTIFICATE_VERSION,
+            issuer: issuer.to_owned(),
+            this_update,
+            next_update,
+            serials: vec![],
+            identity_keys: vec![],
+            signature: None,
+        }
+    }
+
+    /// Sign with `issuer_key`, the sign key of `self.issuer`.
+    pub fn sign(&mut self, issuer_key: [u8; 32]) {
+        let (r, s) = Sr25519::sign_with_context(issuer_key, CONTEXT_REVOCATION, &self.tbs());
+        self.signature = Some((EdwardsPoint::get_pubkey(issuer_key), r, s));
+    }
+
+    fn tbs(&self) -> Vec<u8> {
+        let mut crl = self.clone();
+        crl.signature = None;
+        to_allocvec(&crl).unwrap()
+    }
+
+    /// Sign pubkey of the issuer, if signed.
+    pub fn issuer_key(&self) -> Option<[u8; 32]> {
+        self.signature.map(|(issuer, _, _)| issuer)
+    }
+
+    pub fn verify_signature(&self) -> Result<(), CertError> {
+        let (issuer, r, s) = self.signature.ok_or(CertError::Unsigned)?;
+        if self.version != CERTIFICATE_VERSION {
+            return Err(CertError::UnsupportedVersion);
+        }
+        match Sr25519::verify_with_context(issuer, CONTEXT_REVOCATION, &self.tbs(), r, s) {
+            Ok(true) => Ok(()),
+            _ => Err(CertError::InvalidSignature),
+        }
+    }
+
+    pub fn is_current(&self, now: u64) -> bool {
+        self.this_update <= now && now <= self.next_update
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(from_bytes(&fs::read(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, to_allocvec(self)?)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_revocation_list() {
+    use rand::prelude::*;
+    use rand_chacha::ChaCha20Rng;
+    let mut key = [0u8; 32];
+    ChaCha20Rng::from_entropy().fill_bytes(&mut key);
+    let mut crl = RevocationList::new("ca", 100, 200);
+    crl.serials = vec![3, 7];
+    crl.identity_keys = vec![[9u8; 32]];
+    crl.sign(key);
+    assert_eq!(crl.issuer_key(), Some(EdwardsPoint::get_pubkey(key)));
+    assert!(crl.verify_signature().is_ok());
+    assert!(crl.is_current(100) && crl.is_current(200));
+    assert!(!crl.is_current(99) && !crl.is_current(201));
+
+    let path = std::env::temp_dir().join(format!("crl-test-{}.bin", std::process::id()));
+    crl.save(&path).unwrap();
+    let loaded = RevocationList::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, crl);
+
+    // Taking a serial off the list breaks the signature.
+    let mut forged = loaded;
+    forged.serials.pop();
+    assert_eq!(forged.verify_signature(), Err(CertError::InvalidSignature));
+    forged.signature = None;
+    assert_eq!(forged.verify_signature(), Err(CertError::Unsigned));
+}
@@ -0,0 +1,163 @@
+// hello - Version and algorithm negotiation of the handshake.
+//
+// The client hello carries an offer, the server hello the selection. Nothing here is
+// authenticated by itself: the hellos are covered by the key-confirmation MACs, so a
+// peer which had its offer rewritten in flight fails the handshake.
+use super::message::{CipherSuite, Hello, KexGroup, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use thiserror::Error;
+
+/// Cipher suites this implementation can run, in order of preference.
+pub const SUPPORTED_CIPHER_SUITES: &[CipherSuite] = &[CipherSuite::AesCtrHmac];
+pub const SUPPORTED_KEX_GROUPS: &[KexGroup] = &[KexGroup::Fhmqv25519];
+/// Hello extensions this implementation understands; other critical ones are rejected.
+pub const KNOWN_HELLO_EXTENSIONS: &[&str] = &[];
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum NegotiationError {
+    #[error("unsupported protocol version {0}.")]
+    UnsupportedVersion(u16),
+    #[error("no common cipher suite.")]
+    NoCommonCipherSuite,
+    #[error("no common key exchange group.")]
+    NoCommonKexGroup,
+    #[error("unknown critical hello extension {0}.")]
+    UnknownCriticalExtension(String),
+    #[error("server selected something that was not offered.")]
+    InvalidSelection,
+}
+
+impl Hello {
+    /// Everything this implementation supports.
+    pub fn local() -> Self {
+        Hello {
+            version: PROTOCOL_VERSION,
+            cipher_suites: SUPPORTED_CIPHER_SUITES.to_vec(),
+            kex_groups: SUPPORTED_KEX_GROUPS.to_vec(),
+            extensions: vec![],
+        }
+    }
+
+    /// Server side: pick from the client's `offer` what `self` supports.
+    ///
+    /// The highest common version wins; suites and groups go by the client's preference.
+    pub fn select(&self, offer: &Hello) -> Result<Hello, NegotiationError> {
+        check_extensions(offer)?;
+        let version = self.version.min(offer.version);
+        if version < MIN_PROTOCOL_VERSION {
+            return Err(NegotiationError::UnsupportedVersion(offer.version));
+        }
+        let suite = offer
+            .cipher_suites
+            .iter()
+            .find(|s| self.cipher_suites.contains(s))
+            .ok_or(NegotiationError::NoCommonCipherSuite)?;
+        let group = offer
+            .kex_groups
+            .iter()
+            .find(|g| self.kex_groups.contains(g))
+            .ok_or(NegotiationError::NoCommonKexGroup)?;
+        Ok(Hello {
+            version,
+            cipher_suites: vec![*suite],
+            kex_groups: vec![*group],
+            extensions: vec![],
+        })
+    }
+
+    /// Client side: check the server's `selection` against what `self` offered,
+    /// and return the chosen suite.
+    pub fn accept(&self, selection: &Hello) -> Result<CipherSuite, NegotiationError> {
+        check_extensions(selection)?;
+        if selection.version < MIN_PROTOCOL_VERSION || selection.version > self.version {
+            return Err(NegotiationError::UnsupportedVersion(selection.version));
+        }
+        match (&selection.cipher_suites[..], &selection.kex_groups[..]) {
+            ([suite], [group])
+                if self.cipher_suites.contains(suite) && self.kex_groups.contains(group) =>
+            {
+                Ok(*suite)
+            }
+            _ => Err(NegotiationError::InvalidSelection),
+        }
+    }
+}
+
+fn check_extensions(hello: &Hello) -> Result<(), NegotiationError> {
+    match hello
+        .extensions
+        .iter()
+        .find(|e| e.critical && !KNOWN_HELLO_EXTENSIONS.contains(&e.id.as_str()))
+    {
+        Some(e) => Err(NegotiationError::UnknownCriticalExtension(e.id.clone())),
+        None => Ok(()),
+    }
+}
+
+#[test]
+fn test_negotiation() {
+    use super::message::Extension;
+    let server = Hello::local();
+    let mut offer = Hello {
+        version: PROTOCOL_VERSION + 1,
+        cipher_suites: vec![CipherSuite::ChaCha20Poly1305, CipherSuite::AesCtrHmac],
+        kex_groups: vec![KexGroup::Fhmqv25519],
+        extensions: vec![Extension {
+            id: "future".to_owned(),
+            critical: false,
+            value: vec![1],
+        }],
+    };
+    // A newer client falls back to our version and our only suite.
+    let selection = server.select(&offer).unwrap();
+    assert_eq!(selection.version, PROTOCOL_VERSION);
+    assert_eq!(selection.cipher_suites, vec![CipherSuite::AesCtrHmac]);
+    assert_eq!(offer.accept(&selection), Ok(CipherSuite::AesCtrHmac));
+
+    // The client's preference wins among common suites.
+    let mut wide = server.clone();
+    wide.cipher_suites.push(CipherSuite::ChaCha20Poly1305);
+    let selection = wide.select(&offer).unwrap();
+    assert_eq!(selection.cipher_suites, vec![CipherSuite::ChaCha20Poly1305]);
+
+    // Nothing in common.
+    offer.cipher_suites = vec![CipherSuite::AesGcm];
+    assert_eq!(
+        server.select(&offer),
+        Err(NegotiationError::NoCommonCipherSuite)
+    );
+    offer.cipher_suites = vec![CipherSuite::AesCtrHmac];
+    offer.version = 0;
+    assert_eq!(
+        server.select(&offer),
+        Err(NegotiationError::UnsupportedVersion(0))
+    );
+    offer.version = PROTOCOL_VERSION;
+    offer.extensions[0].critical = true;
+    assert_eq!(
+        server.select(&offer),
+        Err(NegotiationError::UnknownCriticalExtension(
+            "future".to_owned()
+        ))
+    );
+
+    // The server may not pick what was not offered, nor a newer version.
+    let offer = Hello::local();
+    let mut selection = server.select(&offer).unwrap();
+    selection.cipher_suites = vec![CipherSuite::AesGcm];
+    assert_eq!(
+        offer.accept(&selection),
+        Err(NegotiationError::InvalidSelection)
+    );
+    selection.cipher_suites = offer.cipher_suites.clone();
+    selection.kex_groups = vec![];
+    assert_eq!(
+        offer.accept(&selection),
+        Err(NegotiationError::InvalidSelection)
+    );
+    selection.kex_groups = offer.kex_groups.clone();
+    selection.version = PROTOCOL_VERSION + 1;
+    assert_eq!(
+        offer.accept(&selection),
+        Err(NegotiationError::UnsupportedVersion(PROTOCOL_VERSION + 1))
+    );
+}
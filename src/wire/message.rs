@@ -9,27 +9,146 @@ pub struct Signature {
     pub signed_by: [u8; 32],
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Current certificate format.
+pub const CERTIFICATE_VERSION: u8 = 1;
+
+/// Key usage: the exchange key may be used as a handshake identity.
+pub const KEY_USAGE_EXCHANGE: u8 = 1;
+/// Key usage: the sign key may sign messages.
+pub const KEY_USAGE_SIGN: u8 = 2;
+/// Key usage: the sign key may sign certificates.
+pub const KEY_USAGE_CERT_SIGN: u8 = 4;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Extension {
+    pub id: String,
+    /// A verifier which does not know `id` must reject the certificate.
+    pub critical: bool,
+    pub value: Vec<u8>,
+}
+
+/// Signed with `signature` set to `None`, over its postcard bytes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Certificate {
+    pub version: u8,
+    pub serial: u64,
+    /// Subject name, e.g. a hostname.
+    pub subject: String,
+    /// Subject name of the issuer.
+    pub issuer: String,
+    /// Validity, unix seconds, both inclusive.
+    pub not_before: u64,
+    pub not_after: u64,
+    /// `KEY_USAGE_*` bits.
+    pub key_usage: u8,
+    /// May issue certificates.
+    pub ca: bool,
+    /// Most CA certificates allowed below this one in a chain.
+    pub path_len: Option<u8>,
+    pub extensions: Vec<Extension>,
     pub exchange_pubkey: [u8; 32], // for exchange
     pub sign_pubkey: [u8; 32],     // for signature
     /// pubkey, (schnorr sig)
     pub signature: Option<([u8; 32], [u8; 32], [u8; 32])>,
 }
 
+/// Certificates revoked by one issuer, signed like a certificate with `signature` set to `None`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevocationList {
+    pub version: u8,
+    /// Subject name of the issuer.
+    pub issuer: String,
+    /// Validity, unix seconds; a list past `next_update` is stale.
+    pub this_update: u64,
+    pub next_update: u64,
+    /// Serials of certificates by this issuer.
+    pub serials: Vec<u64>,
+    /// Identity (exchange) keys, revoked whatever certificate they come with.
+    pub identity_keys: Vec<[u8; 32]>,
+    /// pubkey, (schnorr sig)
+    pub signature: Option<([u8; 32], [u8; 32], [u8; 32])>,
+}
+
+/// Current wire protocol; a peer may speak any version from `MIN_PROTOCOL_VERSION` up.
+pub const PROTOCOL_VERSION: u16 = 1;
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Record protection of the data stream. Only `AesCtrHmac` is implemented so far;
+/// the others are reserved so peers can offer them once they are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CipherSuite {
+    AesCtrHmac,
+    AesGcm,
+    ChaCha20Poly1305,
+}
+
+/// Key exchange of the handshake.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KexGroup {
+    /// FHMQV over ec25519.
+    Fhmqv25519,
+}
+
+/// Negotiation part of a handshake message.
+///
+/// The client offers its highest version and everything it supports, in order of preference;
+/// the server answers with the version and the single suite and group it picked.
+/// Both hellos are covered by the key-confirmation MACs, so a tampered offer fails the handshake.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    pub version: u16,
+    pub cipher_suites: Vec<CipherSuite>,
+    pub kex_groups: Vec<KexGroup>,
+    pub extensions: Vec<Extension>,
+}
+
+/// Codes of fatal alerts, `WireMessage::Fatal` and `Message::Fatal`.
+pub const FATAL_INTERNAL: u32 = 0;
+/// Too many packets failed integrity or replay checks, or could not be decoded.
+pub const FATAL_INTEGRITY: u32 = 1;
+/// The TCP connection on the sender's side was reset.
+pub const FATAL_TCP_RESET: u32 = 2;
+/// The server could not connect to its TCP endpoint.
+pub const FATAL_TCP_UNREACHABLE: u32 = 3;
+/// The peer was refused: not pinned, authorized or certified, or key confirmation failed.
+pub const FATAL_HANDSHAKE: u32 = 4;
+/// No common version, cipher suite or key exchange group.
+pub const FATAL_NEGOTIATION: u32 = 5;
+
+pub fn fatal_reason(code: u32) -> &'static str {
+    match code {
+        FATAL_INTEGRITY => "integrity failure",
+        FATAL_TCP_RESET => "connection reset",
+        FATAL_TCP_UNREACHABLE => "endpoint unreachable",
+        FATAL_HANDSHAKE => "handshake refused",
+        FATAL_NEGOTIATION => "negotiation failed",
+        _ => "internal error",
+    }
+}
+
+/// Every websocket frame, handshake or not.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum WireMessage {
-    Link { msg: LinkMsg },
+    Link {
+        msg: LinkMsg,
+    },
     // Decrypt data to `struct Message`.
-    Encrypted { msg: Vec<u8> },
-    // To signal fatal state after handshake, a valid MAC is required.
-    Fatal { code: u32, mac: [u8; 32] },
+    Encrypted {
+        msg: Vec<u8>,
+    },
+    /// Handshake refusal. It is sent before a key is confirmed, so `mac` is zero and the
+    /// alert is advisory only; after the handshake, `Message::Fatal` is used.
+    Fatal {
+        code: u32,
+        mac: [u8; 32],
+    },
 }
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum LinkMsg {
     FHMQVHandshake {
+        hello: Hello,
         identity: [u8; 32],      // MQV public key
         ephemeral_key: [u8; 32], // session key.
         /// Put cert chain from your identity to root approved cert.
@@ -37,14 +156,27 @@ pub enum LinkMsg {
         certification: Option<Vec<Certificate>>,
         mac: [u8; 32], // This MAC is used to check validity.
     },
-    Shutdown,
+    /// Client's key-confirmation MAC, last message of the handshake.
+    KeyConfirm { mac: [u8; 32] },
+    /// Close notify: the sender will send no more data. MAC under the sender's keys,
+    /// see `AesCtrHmac::seal_shutdown`; a stream that ends without one was truncated.
+    Shutdown { serial: u64, mac: [u8; 32] },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Message {
-    ReKey,
+    /// Sender moved to its next keys; MAC under the previous ones, see `AesCtrHmac::start_rekey`.
+    ReKey {
+        serial: u64,
+        mac: [u8; 32],
+    },
     Data(Packet),
-    Fatal { code: u32, mac: [u8; 32] },
+    /// Fatal alert; MAC under the sender's keys, see `AesCtrHmac::seal_fatal`.
+    Fatal {
+        code: u32,
+        serial: u64,
+        mac: [u8; 32],
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
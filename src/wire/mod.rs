@@ -1,2 +1,5 @@
 // wire - module for wire protocol definition and transport.
+pub mod cert;
+pub mod crl;
+pub mod hello;
 pub mod message;